@@ -0,0 +1,257 @@
+use crate::Config;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackendConfig {
+    /// age identity files or unencrypted SSH private keys,
+    /// e.g. the hosts SSH host keys.
+    identity_files: Vec<PathBuf>,
+    /// A directory of `<name>.age` files.
+    secret_dir: Option<PathBuf>,
+    /// Paths to encrypted files keyed by secret name, checked
+    /// before `secret_dir`.
+    secrets: HashMap<String, PathBuf>,
+}
+
+/// This backend decrypts [age](https://age-encryption.org)
+/// encrypted files in-process.
+///
+/// Secrets are laid out like [agenix](https://github.com/ryantm/agenix)
+/// does, one file per secret, either as `<name>.age` files in
+/// `secret_dir` or mapped explicitly in `secrets`. Both armored and
+/// binary files are accepted.
+pub struct Age {
+    config: BackendConfig,
+}
+
+/// Load every identity in `path`, an age identity file or an
+/// SSH private key.
+fn load_identities(path: &Path) -> Result<Vec<Box<dyn age::Identity>>, BackendError> {
+    let contents = std::fs::read(path)
+        .map_err(|err| BackendError::BadKey(format!("can't read \"{}\": {err}", path.display())))?;
+
+    if contents.starts_with(b"-----BEGIN") {
+        let identity = age::ssh::Identity::from_buffer(
+            contents.as_slice(),
+            Some(path.to_string_lossy().into_owned()),
+        )
+        .map_err(|err| BackendError::BadKey(format!("\"{}\": {err}", path.display())))?;
+
+        if !matches!(identity, age::ssh::Identity::Unencrypted(_)) {
+            return Err(BackendError::BadKey(format!(
+                "\"{}\" is passphrase protected or unsupported",
+                path.display()
+            )));
+        }
+
+        return Ok(vec![Box::new(identity)]);
+    }
+
+    age::IdentityFile::from_buffer(contents.as_slice())
+        .map_err(|err| BackendError::BadKey(format!("\"{}\": {err}", path.display())))?
+        .into_identities()
+        .map_err(|err| BackendError::BadKey(format!("\"{}\": {err}", path.display())))
+}
+
+impl Age {
+    /// Find the encrypted file for `secret`.
+    fn secret_file(&self, secret: &Secret) -> Option<PathBuf> {
+        if let Some(path) = self.config.secrets.get(&secret.name) {
+            return Some(path.clone());
+        }
+
+        let secret_dir = self.config.secret_dir.as_ref()?;
+
+        // Don't let a secret name escape the secret directory
+        if secret.name.contains('/') || secret.name.starts_with('.') {
+            return None;
+        }
+
+        Some(secret_dir.join(format!("{}.age", secret.name)))
+    }
+
+    fn identities(&self) -> Result<Vec<Box<dyn age::Identity>>, BackendError> {
+        let mut identities = Vec::new();
+
+        for path in &self.config.identity_files {
+            match load_identities(path) {
+                Ok(loaded) => identities.extend(loaded),
+                // One unusable key shouldn't stop the others being tried
+                Err(err) => warn!("skipping age identity: {err}"),
+            }
+        }
+
+        if identities.is_empty() {
+            return Err(BackendError::BadKey("no usable age identities".to_string()));
+        }
+
+        Ok(identities)
+    }
+}
+
+impl Backend<'_> for Age {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let Some(path) = self.secret_file(secret) else {
+            return Err(BackendError::NotFound);
+        };
+
+        let file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!("no age file at \"{}\"", path.display());
+                return Err(BackendError::NotFound);
+            }
+            Err(err) => {
+                return Err(BackendError::Decrypt(format!(
+                    "can't open \"{}\": {err}",
+                    path.display()
+                )));
+            }
+        };
+
+        let identities = self.identities()?;
+
+        let decryptor = age::Decryptor::new(age::armor::ArmoredReader::new(file))
+            .map_err(|err| BackendError::Decrypt(format!("\"{}\": {err}", path.display())))?;
+
+        let mut reader = decryptor
+            .decrypt(identities.iter().map(AsRef::as_ref))
+            .map_err(|err| match err {
+                age::DecryptError::NoMatchingKeys => {
+                    BackendError::BadKey(format!("no identity can decrypt \"{}\"", path.display()))
+                }
+                err => BackendError::Decrypt(format!("\"{}\": {err}", path.display())),
+            })?;
+
+        let mut content = Vec::new();
+        reader
+            .read_to_end(&mut content)
+            .map_err(|err| BackendError::Decrypt(format!("\"{}\": {err}", path.display())))?;
+
+        debug!("successfully decrypted secret {}", secret.name);
+
+        Ok(SecretContent(content))
+    }
+}
+
+impl Age {
+    /// Creates a new Age backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Age { config })
+    }
+
+    /// Validate an age backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        !config.identity_files.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Age, BackendConfig};
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::{scratch_dir, secret};
+    use age::secrecy::ExposeSecret;
+    use std::io::Write;
+
+    fn encrypt(recipient: &age::x25519::Recipient, plaintext: &[u8]) -> Vec<u8> {
+        let encryptor =
+            age::Encryptor::with_recipients(std::iter::once(recipient as &dyn age::Recipient))
+                .expect("encryptor");
+        let mut encrypted = Vec::new();
+        let mut writer = encryptor.wrap_output(&mut encrypted).expect("wrap output");
+        writer.write_all(plaintext).expect("write plaintext");
+        writer.finish().expect("finish");
+        encrypted
+    }
+
+    #[test]
+    fn decrypts_agenix_layout() {
+        let dir = scratch_dir("age-layout");
+        let identity = age::x25519::Identity::generate();
+
+        let key_file = dir.join("key.txt");
+        std::fs::write(&key_file, identity.to_string().expose_secret()).expect("write key");
+        std::fs::write(
+            dir.join("s3-credentials.age"),
+            encrypt(&identity.to_public(), b"meow"),
+        )
+        .expect("write secret");
+
+        let backend = Age {
+            config: BackendConfig {
+                identity_files: vec![key_file],
+                secret_dir: Some(dir.clone()),
+                ..BackendConfig::default()
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        let content = backend
+            .provision(&secret("s3-credentials"), &derivation)
+            .expect("provision");
+        assert_eq!(content.0, b"meow");
+
+        assert!(matches!(
+            backend.provision(&secret("missing"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn wrong_identity_is_a_bad_key() {
+        let dir = scratch_dir("age-wrong-key");
+        let identity = age::x25519::Identity::generate();
+        let other = age::x25519::Identity::generate();
+
+        let key_file = dir.join("key.txt");
+        std::fs::write(&key_file, other.to_string().expose_secret()).expect("write key");
+        std::fs::write(
+            dir.join("token.age"),
+            encrypt(&identity.to_public(), b"meow"),
+        )
+        .expect("write secret");
+
+        let backend = Age {
+            config: BackendConfig {
+                identity_files: vec![key_file],
+                secret_dir: Some(dir.clone()),
+                ..BackendConfig::default()
+            },
+        };
+
+        assert!(matches!(
+            backend.provision(&secret("token"), &DerivationInfo::default()),
+            Err(BackendError::BadKey(_))
+        ));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,696 @@
+use crate::Config;
+use crate::backend::client::{self, ClientConfig};
+use crate::backend::util::{hex, split_field};
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Where secrets are read from.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Service {
+    #[default]
+    SecretsManager,
+    /// `SecureString` parameters of the SSM Parameter Store.
+    Ssm,
+}
+
+impl Service {
+    /// The `SigV4` signing name and endpoint prefix.
+    fn signing_name(self) -> &'static str {
+        match self {
+            Service::SecretsManager => "secretsmanager",
+            Service::Ssm => "ssm",
+        }
+    }
+
+    fn target(self) -> &'static str {
+        match self {
+            Service::SecretsManager => "secretsmanager.GetSecretValue",
+            Service::Ssm => "AmazonSSM.GetParameter",
+        }
+    }
+}
+
+fn default_profile() -> String {
+    "default".to_string()
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    #[serde(default)]
+    service: Service,
+    /// Read from the profile in `config_file` if unset.
+    region: Option<String>,
+    /// Overrides the AWS endpoint, e.g. `http://localhost:4566`
+    /// for `LocalStack`.
+    endpoint: Option<String>,
+    #[serde(default = "default_profile")]
+    profile: String,
+    /// A shared credentials file, `~/.aws/credentials` style.
+    credentials_file: Option<PathBuf>,
+    /// A shared config file, `~/.aws/config` style.
+    config_file: Option<PathBuf>,
+    /// Prepended to every secret name.
+    #[serde(default)]
+    prefix: String,
+    /// The version stage read from Secrets Manager, `AWSCURRENT`
+    /// when unset.
+    version_stage: Option<String>,
+    /// Version stages, or SSM parameter labels, keyed by secret
+    /// name.
+    #[serde(default)]
+    version_stages: HashMap<String, String>,
+    #[serde(flatten)]
+    client: ClientConfig,
+}
+
+/// Credentials used to sign requests.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// This backend reads secrets from AWS Secrets Manager or, with
+/// `"service": "ssm"`, `SecureString` parameters from the SSM
+/// Parameter Store.
+///
+/// Credentials are read from the `profile` of the configured
+/// credentials and config files. A `:<key>` suffix on the secret
+/// name selects a key of a JSON secret, so `app/db:password`
+/// provisions the `password` key of the `app/db` secret. ARNs are
+/// made of `:` separated parts, so names starting with `arn:` are
+/// read whole and without the `prefix`.
+pub struct Aws {
+    name: String,
+    config: BackendConfig,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Format a unix timestamp as the `YYYYMMDDTHHMMSSZ` basic ISO
+/// 8601 form `SigV4` uses.
+fn amz_date(unix_secs: u64) -> String {
+    let days = unix_secs / 86400;
+    let secs = unix_secs % 86400;
+
+    // Civil from days, https://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// A request to be signed with AWS Signature Version 4.
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    /// Header names must be lowercase.
+    pub headers: Vec<(&'a str, String)>,
+    pub payload: &'a [u8],
+}
+
+impl SignedRequest<'_> {
+    /// The `Authorization` header for this request, `amz_date` must
+    /// match its `x-amz-date` header.
+    #[must_use]
+    pub fn authorization(
+        &self,
+        credentials: &Credentials,
+        region: &str,
+        service: &str,
+        amz_date: &str,
+    ) -> String {
+        let mut headers = self.headers.clone();
+        headers.sort();
+
+        let signed_headers = headers
+            .iter()
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers =
+            headers
+                .iter()
+                .fold(String::new(), |mut canonical, (key, value)| {
+                    let _ = writeln!(canonical, "{key}:{}", value.trim());
+                    canonical
+                });
+
+        let canonical_request = format!(
+            "{}\n{}\n\n{canonical_headers}\n{signed_headers}\n{}",
+            self.method,
+            self.path,
+            hex(&Sha256::digest(self.payload))
+        );
+
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{region}/{service}/aws4_request");
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = [date, region, service, "aws4_request"].iter().fold(
+            format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+            |key, part| hmac(&key, part),
+        );
+        let signature = hex(&hmac(&key, &string_to_sign));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key_id
+        )
+    }
+}
+
+/// Parse the `section` of an ini style AWS file.
+fn parse_section(contents: &str, section: &str) -> HashMap<String, String> {
+    let mut in_section = false;
+    let mut values = HashMap::new();
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = header.trim() == section;
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=')
+            && in_section
+        {
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    values
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(rename = "__type", default)]
+    kind: String,
+    #[serde(alias = "Message", default)]
+    message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SecretValue {
+    secret_string: Option<String>,
+    secret_binary: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ParameterResponse {
+    parameter: Parameter,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Parameter {
+    #[serde(rename = "Type")]
+    kind: String,
+    value: String,
+}
+
+/// Turn an AWS error response into a [`BackendError`].
+fn service_error(status: u16, body: &[u8]) -> BackendError {
+    let Ok(error) = serde_json::from_slice::<ErrorResponse>(body) else {
+        return BackendError::Http(format!(
+            "aws answered {status}: {}",
+            String::from_utf8_lossy(body).trim()
+        ));
+    };
+
+    // `__type` may be namespaced, e.g. `com.amazon...#ResourceNotFoundException`
+    let kind = error.kind.rsplit('#').next().unwrap_or_default();
+
+    match kind {
+        "ResourceNotFoundException" | "ParameterNotFound" | "ParameterVersionNotFound" => {
+            BackendError::NotFound
+        }
+        "AccessDeniedException"
+        | "UnrecognizedClientException"
+        | "InvalidSignatureException"
+        | "ExpiredTokenException" => BackendError::Rejected(format!("{kind}: {}", error.message)),
+        "DecryptionFailure" => BackendError::Decrypt(error.message),
+        _ => BackendError::Http(format!("aws answered {status}: {kind}: {}", error.message)),
+    }
+}
+
+impl Aws {
+    /// Read the credentials and region of the configured profile.
+    fn load_profile(&self) -> Result<(Credentials, String), BackendError> {
+        let profile = &self.config.profile;
+
+        let mut values = HashMap::new();
+        if let Some(config_file) = &self.config.config_file {
+            let section = if profile == "default" {
+                profile.clone()
+            } else {
+                format!("profile {profile}")
+            };
+            let contents = std::fs::read_to_string(config_file).map_err(|err| {
+                BackendError::BadKey(format!("\"{}\": {err}", config_file.display()))
+            })?;
+            values.extend(parse_section(&contents, &section));
+        }
+        // The credentials file holds the secret access key
+        if let Some(credentials_file) = &self.config.credentials_file {
+            let contents = client::read_credential(credentials_file)?;
+            values.extend(parse_section(&contents, profile));
+        }
+
+        let (Some(access_key_id), Some(secret_access_key)) = (
+            values.remove("aws_access_key_id"),
+            values.remove("aws_secret_access_key"),
+        ) else {
+            return Err(BackendError::BadKey(format!(
+                "no credentials for profile \"{profile}\""
+            )));
+        };
+
+        let Some(region) = self.config.region.clone().or(values.remove("region")) else {
+            return Err(BackendError::BadKey(format!(
+                "no region for profile \"{profile}\""
+            )));
+        };
+
+        Ok((
+            Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: values.remove("aws_session_token"),
+            },
+            region,
+        ))
+    }
+
+    fn request_body(&self, id: &str) -> serde_json::Value {
+        let stage = self
+            .config
+            .version_stages
+            .get(id)
+            .or(self.config.version_stage.as_ref());
+        let id = if id.starts_with("arn:") {
+            id.to_string()
+        } else {
+            format!("{}{id}", self.config.prefix)
+        };
+
+        match (self.config.service, stage) {
+            (Service::SecretsManager, Some(stage)) => {
+                serde_json::json!({ "SecretId": id, "VersionStage": stage })
+            }
+            (Service::SecretsManager, None) => serde_json::json!({ "SecretId": id }),
+            (Service::Ssm, Some(label)) => {
+                serde_json::json!({ "Name": format!("{id}:{label}"), "WithDecryption": true })
+            }
+            (Service::Ssm, None) => serde_json::json!({ "Name": id, "WithDecryption": true }),
+        }
+    }
+
+    /// Fetch the raw secret named `id`.
+    fn fetch(&self, id: &str) -> Result<Vec<u8>, BackendError> {
+        let (credentials, region) = self.load_profile()?;
+        let service = self.config.service;
+
+        let endpoint = self.config.endpoint.clone().unwrap_or_else(|| {
+            format!("https://{}.{region}.amazonaws.com", service.signing_name())
+        });
+        let endpoint = endpoint.trim_end_matches('/');
+        let host = endpoint
+            .split_once("://")
+            .map_or(endpoint, |(_, host)| host)
+            .to_string();
+
+        let payload = self.request_body(id).to_string();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+        let date = amz_date(now);
+
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", host),
+            ("x-amz-date", date.clone()),
+            ("x-amz-target", service.target().to_string()),
+        ];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let signed = SignedRequest {
+            method: "POST",
+            path: "/",
+            headers,
+            payload: payload.as_bytes(),
+        };
+        let authorization =
+            signed.authorization(&credentials, &region, service.signing_name(), &date);
+
+        let agent = client::agent(&self.config.client)?;
+        let mut request = agent.post(format!("{endpoint}/"));
+        for (key, value) in &signed.headers {
+            request = request.header(*key, value);
+        }
+
+        debug!("{}: reading {id} from {endpoint}", self.name);
+        let mut response = request
+            .header("authorization", authorization)
+            .send(payload)
+            .map_err(client::error)?;
+        let body = client::read_body(&mut response, &self.config.client)?;
+
+        if !response.status().is_success() {
+            return Err(service_error(response.status().as_u16(), &body));
+        }
+
+        let invalid = |err: serde_json::Error| BackendError::InvalidResponse(err.to_string());
+        match service {
+            Service::SecretsManager => {
+                let value = serde_json::from_slice::<SecretValue>(&body).map_err(invalid)?;
+                match (value.secret_string, value.secret_binary) {
+                    (Some(string), _) => Ok(string.into_bytes()),
+                    (None, Some(binary)) => base64::engine::general_purpose::STANDARD
+                        .decode(binary)
+                        .map_err(|err| BackendError::InvalidResponse(err.to_string())),
+                    (None, None) => Err(BackendError::NotFound),
+                }
+            }
+            Service::Ssm => {
+                let parameter = serde_json::from_slice::<ParameterResponse>(&body)
+                    .map_err(invalid)?
+                    .parameter;
+                if parameter.kind != "SecureString" {
+                    return Err(BackendError::Rejected(format!(
+                        "{id} is a {}, not a SecureString",
+                        parameter.kind
+                    )));
+                }
+                Ok(parameter.value.into_bytes())
+            }
+        }
+    }
+}
+
+impl Backend<'_> for Aws {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let (id, key) = if secret.name.starts_with("arn:") {
+            (secret.name.as_str(), None)
+        } else {
+            split_field(&secret.name)
+        };
+        let value = self.fetch(id)?;
+
+        let Some(key) = key else {
+            return Ok(SecretContent(value));
+        };
+
+        let json = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&value)
+            .map_err(|err| BackendError::InvalidResponse(format!("{id} isn't JSON: {err}")))?;
+
+        match json.get(key) {
+            Some(serde_json::Value::String(value)) => Ok(SecretContent(value.as_bytes().to_vec())),
+            Some(value) => Ok(SecretContent(value.to_string().into_bytes())),
+            None => Err(BackendError::NotFound),
+        }
+    }
+}
+
+impl Aws {
+    /// Creates a new Aws backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Aws {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate an aws backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aws, BackendConfig, Credentials, Service, SignedRequest, amz_date, service_error};
+    use crate::backend::client::ClientConfig;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::credential;
+    use crate::test_support::secret;
+    use crate::test_support::serve;
+    use std::collections::HashMap;
+
+    #[test]
+    fn signs_like_the_sigv4_test_suite() {
+        // The "get-vanilla" case of the AWS SigV4 test suite
+        let request = SignedRequest {
+            method: "GET",
+            path: "/",
+            headers: vec![
+                ("host", "example.amazonaws.com".to_string()),
+                ("x-amz-date", "20150830T123600Z".to_string()),
+            ],
+            payload: b"",
+        };
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+
+        assert_eq!(amz_date(1_440_938_160), "20150830T123600Z");
+        assert_eq!(
+            request.authorization(&credentials, "us-east-1", "service", "20150830T123600Z"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn service_errors_are_classified() {
+        let error = |kind: &str| {
+            service_error(
+                400,
+                format!(r#"{{"__type": "{kind}", "message": "m"}}"#).as_bytes(),
+            )
+        };
+
+        assert!(matches!(
+            error("ParameterVersionNotFound"),
+            BackendError::NotFound
+        ));
+        assert!(matches!(
+            error("com.amazonaws.secretsmanager#ResourceNotFoundException"),
+            BackendError::NotFound
+        ));
+        assert!(matches!(
+            error("ParameterVersionLabelLimitExceeded"),
+            BackendError::Http(_)
+        ));
+        assert!(matches!(
+            error("ExpiredTokenException"),
+            BackendError::Rejected(_)
+        ));
+    }
+
+    #[test]
+    fn credentials_must_be_private() {
+        let dir = std::env::temp_dir().join(format!("aws-private-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        std::fs::write(
+            dir.join("credentials"),
+            "[default]\naws_access_key_id = AKID\naws_secret_access_key = secret\n",
+        )
+        .expect("write credentials");
+
+        let backend = Aws {
+            name: "aws".to_string(),
+            config: BackendConfig {
+                service: Service::SecretsManager,
+                region: Some("eu-west-1".to_string()),
+                endpoint: None,
+                profile: "default".to_string(),
+                credentials_file: Some(dir.join("credentials")),
+                config_file: None,
+                prefix: String::new(),
+                version_stage: None,
+                version_stages: HashMap::new(),
+                client: ClientConfig::default(),
+            },
+        };
+
+        assert!(matches!(
+            backend.load_profile(),
+            Err(BackendError::BadKey(msg)) if msg.contains("not accessible by others")
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn reads_secrets_and_parameters() {
+        let dir = std::env::temp_dir().join(format!("aws-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        credential(
+            &dir.join("credentials"),
+            "[default]\naws_access_key_id = other\n\n[ci]\naws_access_key_id = AKID\n\
+             aws_secret_access_key = secret\naws_session_token = session\n",
+        );
+        std::fs::write(dir.join("config"), "[profile ci]\nregion = eu-west-1\n")
+            .expect("write config");
+
+        let (address, server) = serve(vec![
+            (
+                200,
+                r#"{"SecretString": "{\"password\": \"hunter2\"}"}"#.to_string(),
+            ),
+            (
+                400,
+                r#"{"__type": "ResourceNotFoundException", "message": "no"}"#.to_string(),
+            ),
+            (200, r#"{"SecretString": "by-arn"}"#.to_string()),
+            (
+                200,
+                r#"{"Parameter": {"Type": "SecureString", "Value": "s3cr3t"}}"#.to_string(),
+            ),
+            (
+                200,
+                r#"{"Parameter": {"Type": "String", "Value": "public"}}"#.to_string(),
+            ),
+        ]);
+        let mut backend = Aws {
+            name: "aws".to_string(),
+            config: BackendConfig {
+                service: Service::SecretsManager,
+                region: None,
+                endpoint: Some(address),
+                profile: "ci".to_string(),
+                credentials_file: Some(dir.join("credentials")),
+                config_file: Some(dir.join("config")),
+                prefix: "ci/".to_string(),
+                version_stage: None,
+                version_stages: HashMap::from([("app/db".to_string(), "AWSPREVIOUS".to_string())]),
+                client: ClientConfig::default(),
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        let password = backend
+            .provision(&secret("app/db:password"), &derivation)
+            .expect("password");
+        assert_eq!(password.0, b"hunter2");
+        assert!(matches!(
+            backend.provision(&secret("app/gone"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+        let arn = "arn:aws:secretsmanager:eu-west-1:123456789012:secret:app/db-AbCdEf";
+        let by_arn = backend.provision(&secret(arn), &derivation).expect("arn");
+        assert_eq!(by_arn.0, b"by-arn");
+
+        backend.config.service = Service::Ssm;
+        let parameter = backend
+            .provision(&secret("app/token"), &derivation)
+            .expect("parameter");
+        assert_eq!(parameter.0, b"s3cr3t");
+        assert!(matches!(
+            backend.provision(&secret("app/public"), &derivation),
+            Err(BackendError::Rejected(_))
+        ));
+
+        let requests = server.join().expect("server");
+        std::fs::remove_dir_all(dir).expect("remove dir");
+
+        let body = serde_json::from_slice::<serde_json::Value>(&requests[0].body).expect("body");
+        assert_eq!(
+            body,
+            serde_json::json!({"SecretId": "ci/app/db", "VersionStage": "AWSPREVIOUS"})
+        );
+        assert_eq!(
+            requests[0].header("x-amz-target"),
+            Some("secretsmanager.GetSecretValue")
+        );
+        assert_eq!(requests[0].header("x-amz-security-token"), Some("session"));
+        let authorization = requests[0].header("authorization").expect("authorization");
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKID/"));
+        assert!(authorization.contains("/eu-west-1/secretsmanager/aws4_request"));
+        assert_eq!(
+            requests[0]
+                .headers
+                .iter()
+                .filter(|(key, _)| key.eq_ignore_ascii_case("host"))
+                .count(),
+            1
+        );
+
+        let body = serde_json::from_slice::<serde_json::Value>(&requests[2].body).expect("body");
+        assert_eq!(body, serde_json::json!({"SecretId": arn}));
+
+        let body = serde_json::from_slice::<serde_json::Value>(&requests[3].body).expect("body");
+        assert_eq!(
+            body,
+            serde_json::json!({"Name": "ci/app/token", "WithDecryption": true})
+        );
+        assert_eq!(
+            requests[3].header("x-amz-target"),
+            Some("AmazonSSM.GetParameter")
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::Config;
+use crate::backend::client;
+use crate::backend::process::ProcessConfig;
+use crate::backend::util::split_field;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing::debug;
+
+fn default_program() -> PathBuf {
+    PathBuf::from("bw")
+}
+
+fn default_field() -> String {
+    "password".to_string()
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The `bw` executable.
+    #[serde(default = "default_program")]
+    program: PathBuf,
+    /// A file holding the session key from `bw unlock --raw`.
+    session_file: PathBuf,
+    /// The CLI's data directory, `BITWARDENCLI_APPDATA_DIR`.
+    appdata_dir: Option<PathBuf>,
+    /// The field provisioned when the secret name doesn't
+    /// select one.
+    #[serde(default = "default_field")]
+    field: String,
+    #[serde(flatten)]
+    process: ProcessConfig,
+}
+
+/// This backend reads secrets with the Bitwarden CLI, using the
+/// session key of an unlocked vault.
+///
+/// The secret `github` is the item named or with the ID `github`,
+/// as found by `bw get item github`. Its password is provisioned
+/// unless a `:<field>` suffix selects another field, one of
+/// `username`, `password`, `totp`, `notes` or the name of a
+/// custom field, e.g. `github:token`.
+pub struct Bitwarden {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Turn a failed `bw` into a clearer error.
+fn classify_failure(err: BackendError) -> BackendError {
+    let BackendError::CommandFailed { stderr, .. } = &err else {
+        return err;
+    };
+
+    if stderr.contains("Not found") {
+        BackendError::NotFound
+    } else if stderr.contains("Vault is locked") || stderr.contains("You are not logged in") {
+        BackendError::BadKey(format!("Bitwarden is locked: {}", stderr.trim()))
+    } else if stderr.contains("More than one result") {
+        BackendError::Rejected(stderr.trim().to_string())
+    } else {
+        err
+    }
+}
+
+/// Pick a field out of the JSON of an item.
+fn extract_field(item: &[u8], field: &str) -> Result<SecretContent, BackendError> {
+    let item = serde_json::from_slice::<serde_json::Value>(item)
+        .map_err(|err| BackendError::InvalidResponse(err.to_string()))?;
+
+    let value = match field {
+        "username" | "password" | "totp" => item.pointer(&format!("/login/{field}")),
+        "notes" => item.get("notes"),
+        field => item
+            .get("fields")
+            .and_then(serde_json::Value::as_array)
+            .and_then(|fields| {
+                fields.iter().find(|custom| {
+                    custom.get("name").and_then(serde_json::Value::as_str) == Some(field)
+                })
+            })
+            .and_then(|custom| custom.get("value")),
+    };
+
+    match value {
+        Some(serde_json::Value::String(value)) => Ok(SecretContent(value.as_bytes().to_vec())),
+        _ => Err(BackendError::NotFound),
+    }
+}
+
+impl Backend<'_> for Bitwarden {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let (item, field) = split_field(&secret.name);
+        let field = field.unwrap_or(&self.config.field);
+
+        // Don't let a name pass as an option
+        if item.starts_with('-') {
+            return Err(BackendError::NotFound);
+        }
+
+        let mut cmd = std::process::Command::new(&self.config.program);
+        cmd.args(["get", "item", item, "--nointeraction"]);
+        cmd.env_clear();
+
+        // Retain parent process' PATH and HOME, `bw` keeps its
+        // data under HOME
+        cmd.envs(std::env::vars().filter(|(key, _)| key == "PATH" || key == "HOME"));
+
+        cmd.env(
+            "BW_SESSION",
+            client::read_credential(&self.config.session_file)?,
+        );
+        if let Some(appdata_dir) = &self.config.appdata_dir {
+            cmd.env("BITWARDENCLI_APPDATA_DIR", appdata_dir);
+        }
+
+        debug!("{}: getting {item}", self.name);
+        let item = crate::backend::provision_with_cmd(
+            &self.name,
+            secret,
+            &mut cmd,
+            &self.config.process,
+            None,
+        )
+        .map_err(classify_failure)?;
+
+        extract_field(&item.0, field)
+    }
+}
+
+impl Bitwarden {
+    /// Creates a new Bitwarden backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Bitwarden {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a bitwarden backend config, the vault must have
+    /// been unlocked into its `session_file`.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        config.session_file.exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, Bitwarden};
+    use crate::Config;
+    use crate::backend::process::ProcessConfig;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::secret;
+    use crate::test_support::{credential, fake_cli};
+    use std::collections::HashMap;
+
+    #[test]
+    fn reads_item_fields() {
+        let dir = std::env::temp_dir().join(format!("bw-{}", std::process::id()));
+        let program = fake_cli(
+            &dir,
+            "bw",
+            r#"[ "$BW_SESSION" = s3ssion ] || { echo 'Vault is locked.' >&2; exit 1; }
+case "$3" in
+    github) cat <<'EOF'
+{"name": "github", "login": {"username": "octocat", "password": "hunter2"},
+ "notes": null, "fields": [{"name": "token", "value": "ghp_t0ken", "type": 1}]}
+EOF
+    ;;
+    *) echo 'Not found.' >&2; exit 1 ;;
+esac"#,
+        );
+        credential(&dir.join("session"), "s3ssion\n");
+        credential(&dir.join("expired"), "expired\n");
+
+        let mut backend = Bitwarden {
+            name: "bitwarden".to_string(),
+            config: BackendConfig {
+                program,
+                session_file: dir.join("session"),
+                appdata_dir: None,
+                field: "password".to_string(),
+                process: ProcessConfig {
+                    retries: 0,
+                    ..ProcessConfig::default()
+                },
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        let read = |backend: &Bitwarden, name: &str| backend.provision(&secret(name), &derivation);
+        assert_eq!(read(&backend, "github").expect("password").0, b"hunter2");
+        assert_eq!(
+            read(&backend, "github:username").expect("username").0,
+            b"octocat"
+        );
+        assert_eq!(
+            read(&backend, "github:token").expect("custom field").0,
+            b"ghp_t0ken"
+        );
+        assert!(matches!(
+            read(&backend, "github:notes"),
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            read(&backend, "gitlab"),
+            Err(BackendError::NotFound)
+        ));
+
+        backend.config.session_file = dir.join("expired");
+        assert!(matches!(
+            read(&backend, "github"),
+            Err(BackendError::BadKey(_))
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn needs_a_session_file() {
+        let dir = std::env::temp_dir().join(format!("bw-validate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        let config = Config {
+            backend_config: Some(HashMap::from([(
+                "bitwarden".to_string(),
+                serde_json::json!({"session_file": dir.join("session")}),
+            )])),
+            ..Config::default()
+        };
+
+        assert!(!Bitwarden::validate_config(&config, "bitwarden"));
+        credential(&dir.join("session"), "s3ssion\n");
+        assert!(Bitwarden::validate_config(&config, "bitwarden"));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
@@ -0,0 +1,162 @@
+use crate::error::BackendError;
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use ureq::tls::{Certificate, ClientCert, PemItem, PrivateKey, RootCerts, TlsConfig};
+
+/// Connection options shared by the backends that talk HTTP.
+///
+/// Flattened into their configuration, e.g.
+/// `backend_config.vault.ca_file`.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// A PEM bundle of CA certificates, trusted instead of
+    /// the built in roots.
+    pub ca_file: Option<PathBuf>,
+    /// A PEM client certificate chain, presented with
+    /// `client_key` for mutual TLS.
+    pub client_cert: Option<PathBuf>,
+    /// The PEM private key of `client_cert`.
+    pub client_key: Option<PathBuf>,
+    /// Seconds before a request is abandoned, `null` disables
+    /// the timeout.
+    pub timeout_secs: Option<u64>,
+    /// The largest response body, in bytes, that is read.
+    pub max_response_bytes: u64,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            ca_file: None,
+            client_cert: None,
+            client_key: None,
+            timeout_secs: Some(30),
+            max_response_bytes: 1024 * 1024,
+        }
+    }
+}
+
+pub type Response = ureq::http::Response<ureq::Body>;
+
+fn read_pem(path: &PathBuf) -> Result<Vec<u8>, BackendError> {
+    std::fs::read(path)
+        .map_err(|err| BackendError::Http(format!("can't read \"{}\": {err}", path.display())))
+}
+
+/// Read every certificate in the PEM file at `path`.
+fn read_certificates(path: &PathBuf) -> Result<Vec<Certificate<'static>>, BackendError> {
+    let pem = read_pem(path)?;
+
+    ureq::tls::parse_pem(&pem)
+        .filter_map(|item| match item {
+            Ok(PemItem::Certificate(cert)) => Some(Ok(cert)),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| BackendError::Http(format!("invalid PEM in \"{}\": {err}", path.display())))
+}
+
+/// Build an agent for `config`.
+///
+/// Error statuses are returned as responses, not errors, so
+/// each backend can decide what they mean.
+///
+/// # Errors
+///
+/// If the CA bundle or client certificate can't be read, or only
+/// one of `client_cert` and `client_key` is set.
+pub fn agent(config: &ClientConfig) -> Result<ureq::Agent, BackendError> {
+    let mut tls = TlsConfig::builder();
+
+    if let Some(ca_file) = &config.ca_file {
+        let certs = read_certificates(ca_file)?;
+        tls = tls.root_certs(RootCerts::new_with_certs(&certs));
+    }
+
+    match (&config.client_cert, &config.client_key) {
+        (None, None) => {}
+        (Some(cert_file), Some(key_file)) => {
+            let chain = read_certificates(cert_file)?;
+            let key = PrivateKey::from_pem(&read_pem(key_file)?).map_err(|err| {
+                BackendError::BadKey(format!("\"{}\": {err}", key_file.display()))
+            })?;
+            tls = tls.client_cert(Some(ClientCert::new_with_certs(&chain, key)));
+        }
+        _ => {
+            return Err(BackendError::Http(
+                "\"client_cert\" and \"client_key\" must be set together".to_string(),
+            ));
+        }
+    }
+
+    Ok(ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(config.timeout_secs.map(Duration::from_secs))
+        .tls_config(tls.build())
+        .build()
+        .into())
+}
+
+/// Read the body of `response`, refusing bodies larger
+/// than `max_response_bytes`.
+///
+/// # Errors
+///
+/// If the body is too large or the connection fails.
+pub fn read_body(response: &mut Response, config: &ClientConfig) -> Result<Vec<u8>, BackendError> {
+    response
+        .body_mut()
+        .with_config()
+        .limit(config.max_response_bytes)
+        .read_to_vec()
+        .map_err(error)
+}
+
+/// Read a credential from `path`, trimming surrounding whitespace.
+/// The file must belong to the user running the hook, root in
+/// practice, and not be accessible by anyone else.
+///
+/// # Errors
+///
+/// If the file can't be read, is too permissive or isn't UTF-8.
+pub fn read_credential(path: &Path) -> Result<String, BackendError> {
+    String::from_utf8(read_credential_bytes(path)?)
+        .map(|contents| contents.trim().to_string())
+        .map_err(|err| BackendError::BadKey(format!("\"{}\": {err}", path.display())))
+}
+
+/// Read a binary credential from `path` as is, with the same
+/// ownership and permission checks as [`read_credential`].
+///
+/// # Errors
+///
+/// If the file can't be read or is too permissive.
+pub fn read_credential_bytes(path: &Path) -> Result<Vec<u8>, BackendError> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|err| BackendError::BadKey(format!("\"{}\": {err}", path.display())))?;
+
+    // SAFETY: geteuid has no preconditions and can't fail
+    let euid = unsafe { libc::geteuid() };
+    if metadata.uid() != euid || metadata.mode() & 0o077 != 0 {
+        return Err(BackendError::BadKey(format!(
+            "\"{}\" must be owned by uid {euid} and not accessible by others",
+            path.display()
+        )));
+    }
+
+    std::fs::read(path)
+        .map_err(|err| BackendError::BadKey(format!("\"{}\": {err}", path.display())))
+}
+
+/// Convert a transport error.
+#[must_use]
+pub fn error(err: ureq::Error) -> BackendError {
+    match err {
+        ureq::Error::BodyExceedsLimit(limit) => BackendError::OutputTooLarge(limit),
+        err => BackendError::Http(err.to_string()),
+    }
+}
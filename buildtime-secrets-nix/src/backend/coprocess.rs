@@ -0,0 +1,366 @@
+use crate::backend::process::{self, ProcessConfig};
+use crate::backend::sandbox;
+use crate::error::BackendError;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long a helper has to exit after its stdin is closed.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+type Line = Result<Vec<u8>, BackendError>;
+
+/// A long running helper process exchanging one line
+/// per request and response over stdin and stdout.
+struct CoProcess {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<Line>,
+}
+
+type Slot = Arc<Mutex<Option<CoProcess>>>;
+
+/// The co-processes started for a single hook run, keyed by
+/// backend instance name. They're shut down when it's dropped.
+#[derive(Default)]
+pub struct Coprocesses {
+    slots: Mutex<HashMap<String, Slot>>,
+}
+
+/// Read newline terminated lines, each at most `limit` bytes,
+/// until EOF or an oversized line.
+fn read_lines<R: Read>(reader: R, limit: u64, lines: &mpsc::Sender<Line>) {
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let mut line = Vec::new();
+
+        match (&mut reader).take(limit + 1).read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) if line.last() != Some(&b'\n') && line.len() as u64 > limit => {
+                let _ = lines.send(Err(BackendError::OutputTooLarge(limit)));
+                break;
+            }
+            Ok(_) => {
+                if line.last() == Some(&b'\n') {
+                    line.pop();
+                }
+
+                if lines.send(Ok(line)).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl CoProcess {
+    /// Start a helper from the program, arguments and environment
+    /// of `template`.
+    fn spawn(
+        backend_name: &str,
+        template: &Command,
+        config: &ProcessConfig,
+    ) -> Result<Self, BackendError> {
+        let mut cmd = Command::new(template.get_program());
+        cmd.args(template.get_args());
+
+        for (key, value) in template.get_envs() {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+
+        cmd.process_group(0)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        if let Some(sandbox_config) = &config.sandbox {
+            sandbox::apply(&mut cmd, sandbox_config)?;
+        }
+
+        debug!("starting co-process for backend {backend_name}");
+
+        let mut child = cmd.spawn().map_err(BackendError::Spawn)?;
+
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            unreachable!("stdio of the co-process is piped");
+        };
+
+        let (sender, lines) = mpsc::channel();
+        let limit = config.max_output_bytes;
+        std::thread::spawn(move || read_lines(stdout, limit, &sender));
+
+        let name = backend_name.to_string();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr)
+                .lines()
+                .map_while(std::result::Result::ok)
+            {
+                debug!("co-process {name}: {line}");
+            }
+        });
+
+        Ok(Self {
+            child,
+            stdin,
+            lines,
+        })
+    }
+
+    /// Send a request and wait for its response. `Ok(None)` means
+    /// the helper went away before answering.
+    fn exchange(
+        &mut self,
+        request: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<Option<Vec<u8>>, BackendError> {
+        let mut framed = request.to_vec();
+        framed.push(b'\n');
+
+        if self
+            .stdin
+            .write_all(&framed)
+            .and_then(|()| self.stdin.flush())
+            .is_err()
+        {
+            return Ok(None);
+        }
+
+        let line = match timeout {
+            Some(timeout) => match self.lines.recv_timeout(timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Err(BackendError::Timeout(timeout)),
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            },
+            None => match self.lines.recv() {
+                Ok(line) => line,
+                Err(_) => return Ok(None),
+            },
+        };
+
+        line.map(Some)
+    }
+
+    fn kill(mut self) {
+        process::kill_process_group(self.child.id());
+        let _ = self.child.wait();
+    }
+
+    /// Close stdin, asking the helper to exit, and kill it if it
+    /// hasn't within the grace period.
+    fn shutdown(self) {
+        let CoProcess {
+            mut child, stdin, ..
+        } = self;
+        drop(stdin);
+
+        let started = Instant::now();
+        while started.elapsed() < SHUTDOWN_GRACE {
+            // Once reaped its group id can be reused, so anything it
+            // left behind isn't killed
+            if let Ok(Some(status)) = child.try_wait() {
+                debug!("co-process exited with {status}");
+                return;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        warn!("co-process didn't exit after its stdin closed, killing it");
+        process::kill_process_group(child.id());
+        let _ = child.wait();
+    }
+}
+
+fn shutdown_slot(backend_name: &str, slot: &Slot) {
+    let coprocess = slot
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .take();
+
+    if let Some(coprocess) = coprocess {
+        debug!("shutting down co-process for backend {backend_name}");
+        coprocess.shutdown();
+    }
+}
+
+impl Coprocesses {
+    fn slot(&self, backend_name: &str) -> Slot {
+        let mut slots = self
+            .slots
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        Arc::clone(slots.entry(backend_name.to_string()).or_default())
+    }
+
+    /// Send `request` to the co-process for `backend_name`, starting
+    /// it from `cmd` if needed, and return the response line.
+    ///
+    /// A helper that crashes is restarted and sent the request again,
+    /// up to `max_restarts` times. A helper that times out is killed
+    /// and restarted on the next request.
+    ///
+    /// # Errors
+    ///
+    /// If the helper can't be started, keeps crashing, times out or
+    /// writes an oversized response.
+    pub fn request(
+        &self,
+        backend_name: &str,
+        cmd: &Command,
+        config: &ProcessConfig,
+        max_restarts: u32,
+        request: &[u8],
+    ) -> Result<Vec<u8>, BackendError> {
+        if process::breaker_is_open(backend_name, config) {
+            debug!("skipping backend {backend_name}, its breaker is open");
+            return Err(BackendError::CircuitOpen);
+        }
+
+        let slot = self.slot(backend_name);
+        let mut coprocess = slot
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut restarts = 0;
+
+        loop {
+            let running = match coprocess.as_mut() {
+                Some(running) => running,
+                None => coprocess.insert(CoProcess::spawn(backend_name, cmd, config)?),
+            };
+
+            let result = running.exchange(request, config.timeout());
+            process::breaker_record(
+                backend_name,
+                config,
+                matches!(result, Err(BackendError::Timeout(_))),
+            );
+
+            match result {
+                Ok(Some(response)) => return Ok(response),
+                Ok(None) => {
+                    if let Some(crashed) = coprocess.take() {
+                        crashed.kill();
+                    }
+
+                    if restarts >= max_restarts {
+                        return Err(BackendError::CoprocessExited(backend_name.to_string()));
+                    }
+
+                    restarts += 1;
+                    warn!("co-process for backend {backend_name} exited, restarting it");
+                }
+                Err(err) => {
+                    // The helper is out of step with us, start afresh next time
+                    if let Some(stuck) = coprocess.take() {
+                        stuck.kill();
+                    }
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Shut down the co-process for `backend_name`, if it's running.
+    pub fn shutdown(&self, backend_name: &str) {
+        let slot = self
+            .slots
+            .lock()
+            .ok()
+            .and_then(|mut slots| slots.remove(backend_name));
+
+        if let Some(slot) = slot {
+            shutdown_slot(backend_name, &slot);
+        }
+    }
+
+    /// Shut down every running co-process.
+    pub fn shutdown_all(&self) {
+        let slots = self
+            .slots
+            .lock()
+            .map(|mut slots| std::mem::take(&mut *slots))
+            .unwrap_or_default();
+
+        for (backend_name, slot) in slots {
+            shutdown_slot(&backend_name, &slot);
+        }
+    }
+}
+
+impl Drop for Coprocesses {
+    fn drop(&mut self) {
+        self.shutdown_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coprocesses;
+    use crate::backend::process::ProcessConfig;
+    use crate::test_support::fake_cli;
+    use std::process::Command;
+
+    #[test]
+    fn helper_is_reused_between_requests() {
+        // Answers every request with its own pid
+        let dir = std::env::temp_dir().join(format!("coprocess-reuse-{}", std::process::id()));
+        let file = fake_cli(&dir, "reuse", "while read -r _; do echo $$; done");
+        let config = ProcessConfig::default();
+        let coprocesses = Coprocesses::default();
+
+        let cmd = Command::new(&file);
+        let request = |coprocesses: &Coprocesses| {
+            coprocesses
+                .request("reuse", &cmd, &config, 0, b"{}")
+                .expect("response")
+        };
+
+        let first = request(&coprocesses);
+        assert_eq!(first, request(&coprocesses));
+
+        // Each hook run has its own helpers, shutting down one
+        // run's leaves the other's running
+        let other = Coprocesses::default();
+        let other_first = request(&other);
+        assert_ne!(first, other_first);
+        drop(coprocesses);
+        assert_eq!(other_first, request(&other));
+
+        other.shutdown("reuse");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn crashed_helper_is_restarted() {
+        // Exits after answering a single request
+        let dir = std::env::temp_dir().join(format!("coprocess-crash-{}", std::process::id()));
+        let file = fake_cli(&dir, "crash", "read -r _; echo $$");
+        let config = ProcessConfig::default();
+        let coprocesses = Coprocesses::default();
+
+        let cmd = Command::new(&file);
+
+        let first = coprocesses
+            .request("crash", &cmd, &config, 1, b"{}")
+            .expect("first");
+        let second = coprocesses
+            .request("crash", &cmd, &config, 1, b"{}")
+            .expect("second");
+
+        assert_ne!(first, second);
+        coprocesses.shutdown("crash");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
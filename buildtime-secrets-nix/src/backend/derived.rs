@@ -0,0 +1,322 @@
+use crate::backend::util::hex;
+use crate::backend::{Backend, BackendKind, DerivationInfo, instance_kind};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use crate::secret::SecretKind;
+use crate::{Config, Session};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+/// How derived bytes are encoded.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Hex,
+    Base64,
+    Bytes,
+}
+
+fn default_length() -> usize {
+    32
+}
+
+/// The shape of a derived secret.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Output {
+    /// Bytes derived, before encoding.
+    #[serde(default = "default_length")]
+    length: usize,
+    #[serde(default)]
+    encoding: Encoding,
+    /// Mix the derivation name into the derived value, so each
+    /// derivation gets its own.
+    #[serde(default)]
+    per_derivation: bool,
+}
+
+/// The secret the master key is read from.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Master {
+    /// The backend instance holding the master key.
+    backend: String,
+    /// The name of the master key secret.
+    secret: String,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    master: Master,
+    /// The HKDF salt.
+    salt: Option<String>,
+    /// The secrets this backend derives.
+    secrets: HashMap<String, Output>,
+}
+
+/// This backend derives secrets from a single master key with
+/// HKDF-SHA256, so many credentials can be backed by one stored
+/// key and still have stable hashes.
+///
+/// The secret `api-key` is HKDF(master key, info = `api-key`),
+/// or `api-key`, a NUL byte and the derivation name when derived
+/// per derivation. The master key is provisioned by another
+/// backend instance, which can't itself be a derived backend.
+pub struct Derived<'a> {
+    name: String,
+    root_config: &'a Config,
+    config: BackendConfig,
+    session: &'a Session,
+}
+
+impl Derived<'_> {
+    /// The instance the derived instance `name` reads its master
+    /// key from.
+    pub(crate) fn upstream(root_config: &Config, name: &str) -> Vec<String> {
+        crate::backend::get_backend_config::<BackendConfig>(root_config, name)
+            .map(|config| vec![config.master.backend])
+            .unwrap_or_default()
+    }
+
+    fn master_key(&self) -> Result<SecretContent, BackendError> {
+        let master = &self.config.master;
+        let kind = instance_kind(self.root_config, &master.backend).ok_or_else(|| {
+            BackendError::Rejected(format!("no backend instance \"{}\"", master.backend))
+        })?;
+
+        if kind == BackendKind::Derived {
+            return Err(BackendError::Rejected(
+                "the master key can't be derived".to_string(),
+            ));
+        }
+
+        let secret = Secret {
+            name: master.secret.clone(),
+            hash: String::new(),
+            backend_hint: None,
+            kind: SecretKind::Pinned,
+        };
+
+        match crate::backend::fetch(
+            self.root_config,
+            self.session,
+            &self.name,
+            &master.backend,
+            &secret,
+            &DerivationInfo::default(),
+        ) {
+            Err(BackendError::NotFound) => Err(BackendError::BadKey(format!(
+                "{} doesn't have the master key \"{}\"",
+                master.backend, master.secret
+            ))),
+            result => result,
+        }
+    }
+
+    /// The hash derivations declare the derived secret `name` by.
+    /// Secrets derived per derivation need the name of the
+    /// derivation, e.g. `hello-2.12`.
+    ///
+    /// # Errors
+    ///
+    /// If this backend doesn't derive the secret, a derivation name
+    /// is needed but missing, or the master key can't be read.
+    pub fn hash(&self, name: &str, derivation: Option<&str>) -> Result<String, BackendError> {
+        let output = self
+            .config
+            .secrets
+            .get(name)
+            .ok_or(BackendError::NotFound)?;
+        let derivation = match derivation {
+            Some(derivation) => DerivationInfo {
+                name: derivation.to_string(),
+                ..DerivationInfo::default()
+            },
+            None if output.per_derivation => {
+                return Err(BackendError::Rejected(format!(
+                    "{name} is derived per derivation, its name is needed"
+                )));
+            }
+            None => DerivationInfo::default(),
+        };
+
+        let secret = Secret {
+            name: name.to_string(),
+            hash: String::new(),
+            backend_hint: None,
+            kind: SecretKind::Pinned,
+        };
+        self.provision(&secret, &derivation)
+            .map(|content| content.sri_hash())
+    }
+}
+
+impl Backend<'_> for Derived<'_> {
+    fn provision(
+        &self,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let Some(output) = self.config.secrets.get(&secret.name) else {
+            return Err(BackendError::NotFound);
+        };
+
+        let master_key = self.master_key()?;
+        let hkdf = Hkdf::<Sha256>::new(
+            self.config.salt.as_deref().map(str::as_bytes),
+            &master_key.0,
+        );
+
+        let mut info = secret.name.as_bytes().to_vec();
+        if output.per_derivation {
+            info.push(0);
+            info.extend_from_slice(derivation.name.as_bytes());
+        }
+
+        let mut derived = vec![0; output.length];
+        hkdf.expand(&info, &mut derived)
+            .map_err(|_| BackendError::Rejected(format!("can't derive {} bytes", output.length)))?;
+
+        Ok(SecretContent(match output.encoding {
+            Encoding::Hex => hex(&derived).into_bytes(),
+            Encoding::Base64 => STANDARD.encode(derived).into_bytes(),
+            Encoding::Bytes => derived,
+        }))
+    }
+}
+
+impl<'a> Derived<'a> {
+    /// Creates a new Derived backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &'a Config, name: &str, session: &'a Session) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Derived {
+            name: name.to_string(),
+            root_config,
+            config,
+            session,
+        })
+    }
+
+    /// Validate a derived backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        // HKDF-SHA256 can't expand past 255 blocks
+        instance_kind(root_config, &config.master.backend)
+            .is_some_and(|kind| kind != BackendKind::Derived)
+            && config
+                .secrets
+                .values()
+                .all(|output| (1..=255 * 32).contains(&output.length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Derived;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::secret;
+    use crate::test_support::vault;
+    use crate::{Config, Session};
+    use hkdf::Hkdf;
+    use sha2::Sha256;
+    use std::collections::HashMap;
+
+    #[test]
+    fn derives_from_another_backend() {
+        let dir = std::env::temp_dir().join(format!("derived-{}", std::process::id()));
+        vault(&dir)
+            .insert("master", b"correct horse battery staple")
+            .expect("insert master key");
+
+        let config = Config {
+            backend_config: Some(HashMap::from([
+                (
+                    "keys".to_string(),
+                    serde_json::json!({
+                        "kind": "local",
+                        "database": dir.join("local.age"),
+                        "key": {"file": dir.join("local.key")},
+                    }),
+                ),
+                (
+                    "derived".to_string(),
+                    serde_json::json!({
+                        "master": {"backend": "keys", "secret": "master"},
+                        "salt": "buildtime",
+                        "secrets": {
+                            "api-key": {"length": 16},
+                            "cache-key": {"encoding": "base64", "per_derivation": true},
+                        },
+                    }),
+                ),
+            ])),
+            ..Config::default()
+        };
+        assert!(Derived::validate_config(&config, "derived"));
+
+        let session = Session::new(&config).expect("session");
+        let backend = Derived::new(&config, "derived", &session).expect("backend");
+        let derivation = |name: &str| DerivationInfo {
+            path: String::new(),
+            name: name.to_string(),
+            fixed_output: false,
+        };
+        let read = |name: &str, drv: &str| {
+            backend
+                .provision(&secret(name), &derivation(drv))
+                .map(|content| content.0)
+        };
+
+        let mut expected = [0; 16];
+        Hkdf::<Sha256>::new(Some(b"buildtime"), b"correct horse battery staple")
+            .expand(b"api-key", &mut expected)
+            .expect("expand");
+        assert_eq!(
+            read("api-key", "hello").expect("api key"),
+            super::hex(&expected).into_bytes()
+        );
+        assert_eq!(
+            read("api-key", "hello").expect("api key"),
+            read("api-key", "world").expect("api key")
+        );
+        assert_ne!(
+            read("cache-key", "hello").expect("cache key"),
+            read("cache-key", "world").expect("cache key")
+        );
+        assert!(matches!(
+            read("master", "hello"),
+            Err(BackendError::NotFound)
+        ));
+
+        // A master key guarded by a quorum can't be read around it
+        let mut guarded = config.clone();
+        guarded.backend_config.as_mut().expect("backends").insert(
+            "quorum".to_string(),
+            serde_json::json!({"instances": ["keys"], "secrets": ["master"]}),
+        );
+        let session = Session::new(&guarded).expect("session");
+        let backend = Derived::new(&guarded, "derived", &session).expect("backend");
+        assert!(matches!(
+            backend.provision(&secret("api-key"), &derivation("hello")),
+            Err(BackendError::Rejected(_))
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
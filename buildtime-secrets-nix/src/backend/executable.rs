@@ -1,52 +1,281 @@
-use crate::backend::Backend;
+use crate::backend::process::ProcessConfig;
+use crate::backend::template::Placeholders;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
 use crate::secret::Secret;
 use crate::secret::SecretContent;
-use crate::{Config, Result};
+use crate::{Config, Session};
+use base64::Engine;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
 
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Serialize, Deserialize)]
+/// How the executable is asked for a secret.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// The secret name is passed as argv[1], stdout is the secret.
+    #[default]
+    Raw,
+    /// A [`Request`] is written to stdin, a [`Response`] is read
+    /// from stdout.
+    Json,
+    /// The executable is started once and kept running, each
+    /// [`Request`] and [`Response`] is a single line on its
+    /// stdin and stdout.
+    Coprocess,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct BackendConfig {
     file: PathBuf,
+    #[serde(default)]
+    protocol: Protocol,
+    /// Argument templates, see [`Placeholders`]. Defaults to
+    /// `["{name}"]` with the raw protocol.
+    args: Option<Vec<String>>,
+    /// Environment variable templates.
+    #[serde(default)]
+    env: HashMap<String, String>,
+    /// Argument templates used instead of `args` for specific
+    /// secrets, keyed by secret name.
+    #[serde(default)]
+    secret_args: HashMap<String, Vec<String>>,
+    /// How many times a co-process that exits is restarted for a
+    /// single secret, 2 if unset.
+    max_restarts: Option<u32>,
+    #[serde(flatten)]
+    process: ProcessConfig,
+}
+
+/// The request written to the executables stdin in
+/// [`Protocol::Json`] mode.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Request<'a> {
+    pub name: &'a str,
+    pub hash: &'a str,
+    pub derivation_path: &'a str,
+    pub derivation_name: &'a str,
+    pub backend: &'a str,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseStatus {
+    Ok,
+    NotFound,
+    Error,
+}
+
+/// The response read from the executables stdout in
+/// [`Protocol::Json`] mode.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub status: ResponseStatus,
+    /// The base64 encoded secret, required when `status` is "ok".
+    pub content: Option<String>,
+    /// Unix time after which the secret must not be used.
+    pub expires_at: Option<u64>,
+    pub message: Option<String>,
+}
+
+impl Response {
+    /// Turn a response into the secret it carries.
+    ///
+    /// # Errors
+    ///
+    /// If the executable reported a failure, the secret has expired or
+    /// the content isn't valid base64.
+    pub fn into_content(self) -> Result<SecretContent, BackendError> {
+        let message = self.message.unwrap_or_default();
+
+        match self.status {
+            ResponseStatus::Ok => {}
+            ResponseStatus::NotFound => return Err(BackendError::NotFound),
+            ResponseStatus::Error => return Err(BackendError::Rejected(message)),
+        }
+
+        if !message.is_empty() {
+            debug!("executable says: {message}");
+        }
+
+        if let Some(expires_at) = self.expires_at {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs())
+                .unwrap_or_default();
+
+            if expires_at <= now {
+                return Err(BackendError::Expired(format!(
+                    "secret expired at {expires_at}"
+                )));
+            }
+        }
+
+        let Some(content) = self.content else {
+            return Err(BackendError::InvalidResponse(
+                "\"content\" is required when \"status\" is \"ok\"".to_string(),
+            ));
+        };
+
+        base64::engine::general_purpose::STANDARD
+            .decode(content)
+            .map(SecretContent)
+            .map_err(|err| BackendError::InvalidResponse(format!("invalid content: {err}")))
+    }
 }
 
 /// A simple backend that accepts an arbitrary executable that,
 /// when invoked, provisions a secret.
 ///
-/// The name of the secret will be passed as the executables
-/// first command line argument (argv[1]).
+/// By default the name of the secret will be passed as the
+/// executables first command line argument (argv[1]).
 /// The executable will then write the full contents of the
 /// secret to stdout.
-pub struct Executable {
+///
+/// With `"protocol": "json"` the executable instead receives
+/// a JSON [`Request`] on stdin, carrying the derivation being
+/// built, and answers with a JSON [`Response`] on stdout.
+///
+/// With `"protocol": "coprocess"` the executable is started once
+/// per hook invocation and answers every request, one JSON line
+/// each way, which suits helpers that are slow to start.
+///
+/// The arguments and environment of the executable are rendered
+/// from the `args`, `secret_args` and `env` templates, so a tool
+/// like `pass` can be used directly with `"args": ["show", "{name}"]`.
+/// A co-process outlives any one secret, so its templates can only
+/// use the derivation and backend placeholders.
+pub struct Executable<'a> {
+    name: String,
     config: BackendConfig,
+    session: &'a Session,
 }
 
-impl Backend<'_> for Executable {
-    fn provision(&self, secret: &Secret) -> Option<SecretContent> {
-        let mut cmd = std::process::Command::new(&self.config.file);
-        cmd.arg(secret.name.clone());
-        crate::backend::provision_with_cmd(secret, &mut cmd)
+impl Backend<'_> for Executable<'_> {
+    fn provision(
+        &self,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let secret_placeholders = match self.config.protocol {
+            Protocol::Raw | Protocol::Json => Some(secret),
+            Protocol::Coprocess => None,
+        };
+        let mut cmd = self.command(&Placeholders {
+            secret: secret_placeholders,
+            derivation,
+            backend: &self.name,
+        })?;
+
+        match self.config.protocol {
+            Protocol::Raw => crate::backend::provision_with_cmd(
+                &self.name,
+                secret,
+                &mut cmd,
+                &self.config.process,
+                None,
+            ),
+            Protocol::Json => {
+                let request = self.request(secret, derivation)?;
+
+                let stdout = crate::backend::provision_with_cmd(
+                    &self.name,
+                    secret,
+                    &mut cmd,
+                    &self.config.process,
+                    Some(&request),
+                )?;
+
+                serde_json::from_slice::<Response>(stdout.as_ref())
+                    .map_err(|err| BackendError::InvalidResponse(err.to_string()))?
+                    .into_content()
+            }
+            Protocol::Coprocess => {
+                let request = self.request(secret, derivation)?;
+
+                let line = self.session.coprocesses().request(
+                    &self.name,
+                    &cmd,
+                    &self.config.process,
+                    self.config.max_restarts.unwrap_or(2),
+                    &request,
+                )?;
+
+                serde_json::from_slice::<Response>(&line)
+                    .map_err(|err| BackendError::InvalidResponse(err.to_string()))?
+                    .into_content()
+            }
+        }
     }
 }
 
-impl Executable {
+impl<'a> Executable<'a> {
+    /// Build the command for the executable, rendering its
+    /// argument and environment templates.
+    fn command(
+        &self,
+        placeholders: &Placeholders<'_>,
+    ) -> Result<std::process::Command, BackendError> {
+        let default_args = match self.config.protocol {
+            Protocol::Raw => vec!["{name}".to_string()],
+            Protocol::Json | Protocol::Coprocess => Vec::new(),
+        };
+
+        let args = placeholders
+            .secret
+            .and_then(|secret| self.config.secret_args.get(&secret.name))
+            .or(self.config.args.as_ref())
+            .unwrap_or(&default_args);
+
+        let mut cmd = std::process::Command::new(&self.config.file);
+        cmd.args(placeholders.render_all(args)?);
+
+        for (key, value) in &self.config.env {
+            cmd.env(key, placeholders.render(value)?);
+        }
+
+        Ok(cmd)
+    }
+
+    fn request(
+        &self,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<Vec<u8>, BackendError> {
+        serde_json::to_vec(&Request {
+            name: &secret.name,
+            hash: &secret.hash,
+            derivation_path: &derivation.path,
+            derivation_name: &derivation.name,
+            backend: &self.name,
+        })
+        .map_err(|err| BackendError::InvalidResponse(err.to_string()))
+    }
+
     /// Creates a new Executable backend.
     ///
     /// # Errors
     ///
     /// If the associated config can't be parsed.
-    pub fn new(root_config: &Config) -> Result<Self> {
-        let config =
-            crate::backend::get_backend_config::<BackendConfig>(root_config, "executable")?;
+    pub fn new(root_config: &Config, name: &str, session: &'a Session) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config::<BackendConfig>(root_config, name)?;
 
-        Ok(Executable { config })
+        Ok(Executable {
+            name: name.to_string(),
+            config,
+            session,
+        })
     }
 
     /// Validate an exacutable backend config.
     #[must_use]
-    pub fn validate_config(root_config: &Config) -> bool {
-        let parse_result =
-            crate::backend::get_backend_config::<BackendConfig>(root_config, "executable");
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
 
         let Ok(_config) = parse_result else {
             return false;
@@ -55,3 +284,99 @@ impl Executable {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, Executable, Protocol, Response, ResponseStatus};
+    use crate::Config;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::session::Session;
+    use crate::test_support::{fake_cli, secret};
+
+    fn response(json: &str) -> Response {
+        serde_json::from_str(json).expect("valid response")
+    }
+
+    #[test]
+    fn ok_response_decodes_content() {
+        let content = response(r#"{"status": "ok", "content": "bWVvdw=="}"#)
+            .into_content()
+            .expect("content");
+
+        assert_eq!(content.0, b"meow");
+    }
+
+    #[test]
+    fn not_found_response() {
+        let response = response(r#"{"status": "not_found", "message": "no such secret"}"#);
+
+        assert_eq!(response.status, ResponseStatus::NotFound);
+        assert!(matches!(
+            response.into_content(),
+            Err(BackendError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn expired_response_is_rejected() {
+        let result =
+            response(r#"{"status": "ok", "content": "bWVvdw==", "expires_at": 1}"#).into_content();
+
+        assert!(matches!(result, Err(BackendError::Expired(_))));
+    }
+
+    #[test]
+    fn json_protocol_round_trip() {
+        let dir = std::env::temp_dir().join(format!("executable-{}", std::process::id()));
+
+        // Answers with the request it was sent as the secret
+        let file = fake_cli(
+            &dir,
+            "helper",
+            r#"request=$(cat)
+case "$request" in
+    *'"name":"db"'*)
+        printf '{"status": "ok", "content": "%s"}' "$(printf %s "$request" | base64 -w0)" ;;
+    *) printf '{"status": "not_found", "message": "no such secret"}' ;;
+esac"#,
+        );
+        let session = Session::new(&Config::default()).expect("session");
+        let backend = Executable {
+            name: "helper".to_string(),
+            config: BackendConfig {
+                file,
+                protocol: Protocol::Json,
+                ..BackendConfig::default()
+            },
+            session: &session,
+        };
+        let derivation = DerivationInfo {
+            path: "/nix/store/aaaa-hello.drv".to_string(),
+            name: "hello".to_string(),
+            fixed_output: false,
+        };
+
+        let content = backend
+            .provision(&secret("db"), &derivation)
+            .expect("secret");
+        let request = serde_json::from_slice::<serde_json::Value>(&content.0).expect("request");
+        assert_eq!(
+            request,
+            serde_json::json!({
+                "name": "db",
+                "hash": "",
+                "derivation_path": "/nix/store/aaaa-hello.drv",
+                "derivation_name": "hello",
+                "backend": "helper",
+            })
+        );
+
+        assert!(matches!(
+            backend.provision(&secret("other"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
@@ -0,0 +1,270 @@
+use crate::Config;
+use crate::backend::local::{self, KeySource, LocalVault};
+use crate::backend::util::hex;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::debug;
+
+const ALPHANUMERIC: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// How a generated value is encoded.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// `length` letters and digits.
+    #[default]
+    Alphanumeric,
+    /// `length` characters picked from this alphabet.
+    Alphabet(String),
+    /// `length` random bytes, hex encoded.
+    Hex,
+    /// `length` random bytes, base64 encoded.
+    Base64,
+    /// `length` raw random bytes.
+    Bytes,
+}
+
+fn default_length() -> usize {
+    32
+}
+
+/// The shape of a generated secret.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Generator {
+    #[serde(default = "default_length")]
+    length: usize,
+    #[serde(default)]
+    format: Format,
+}
+
+fn default_database() -> PathBuf {
+    PathBuf::from("/var/lib/buildtime-secrets/generated.age")
+}
+
+fn default_key() -> KeySource {
+    KeySource::File(PathBuf::from("/var/lib/buildtime-secrets/generated.key"))
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The encrypted database generated values are kept in.
+    #[serde(default = "default_database")]
+    database: PathBuf,
+    #[serde(default = "default_key")]
+    key: KeySource,
+    /// The secrets this backend generates.
+    secrets: HashMap<String, Generator>,
+}
+
+/// This backend generates random secrets the first time they're
+/// requested and returns the same value afterwards, for secrets
+/// no one needs to know, e.g. a signing salt.
+///
+/// Only the secrets listed in `secrets` are generated. Values are
+/// kept encrypted like the local vault, and only change when
+/// rotated with `buildtime-secrets-nix regenerate`, which prints
+/// the new value's hash for derivations to declare.
+pub struct Generated {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Fill `buffer` from the operating system's random number generator.
+pub(crate) fn fill_random(buffer: &mut [u8]) -> Result<(), BackendError> {
+    SystemRandom::new()
+        .fill(buffer)
+        .map_err(|_| BackendError::Rejected("can't generate random bytes".to_string()))
+}
+
+/// Pick `length` characters uniformly from `alphabet`.
+fn random_string(alphabet: &str, length: usize) -> Result<Vec<u8>, BackendError> {
+    let alphabet = alphabet.chars().collect::<Vec<_>>();
+    if alphabet.is_empty() || alphabet.len() > 256 {
+        return Err(BackendError::Rejected(
+            "an alphabet needs between 1 and 256 characters".to_string(),
+        ));
+    }
+
+    // Bytes past the last whole multiple of the alphabet are
+    // rejected, so no character is more likely than another
+    let limit = 256 - 256 % alphabet.len();
+    let mut value = Vec::with_capacity(length);
+    let mut byte = [0];
+
+    while value.len() < length {
+        fill_random(&mut byte)?;
+        let index = usize::from(byte[0]);
+        if index < limit {
+            value.push(alphabet[index % alphabet.len()]);
+        }
+    }
+
+    Ok(value.into_iter().collect::<String>().into_bytes())
+}
+
+impl Generator {
+    fn generate(&self) -> Result<Vec<u8>, BackendError> {
+        let random_bytes = || {
+            let mut bytes = vec![0; self.length];
+            fill_random(&mut bytes).map(|()| bytes)
+        };
+
+        match &self.format {
+            Format::Alphanumeric => random_string(ALPHANUMERIC, self.length),
+            Format::Alphabet(alphabet) => random_string(alphabet, self.length),
+            Format::Hex => Ok(hex(&random_bytes()?).into_bytes()),
+            Format::Base64 => Ok(STANDARD.encode(random_bytes()?).into_bytes()),
+            Format::Bytes => random_bytes(),
+        }
+    }
+}
+
+impl Generated {
+    /// The vault generated values are kept in.
+    #[must_use]
+    pub fn vault(&self) -> LocalVault {
+        LocalVault::with_config(local::BackendConfig {
+            database: self.config.database.clone(),
+            key: self.config.key.clone(),
+        })
+    }
+
+    fn generator(&self, name: &str) -> Result<&Generator, BackendError> {
+        self.config.secrets.get(name).ok_or(BackendError::NotFound)
+    }
+
+    /// Replace the value of a generated secret, returning the hash
+    /// derivations declare it by.
+    ///
+    /// # Errors
+    ///
+    /// If this backend doesn't generate the secret, or the vault
+    /// can't be decrypted or written.
+    pub fn regenerate(&self, name: &str) -> Result<String, BackendError> {
+        let value = SecretContent(self.generator(name)?.generate()?);
+        self.vault().insert(name, &value.0)?;
+        Ok(value.sri_hash())
+    }
+}
+
+impl Backend<'_> for Generated {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let generator = self.generator(&secret.name)?;
+        let vault = self.vault();
+
+        if let Some(value) = vault.get(&secret.name)? {
+            return Ok(SecretContent(value));
+        }
+
+        // Another build may generate the same secret first, so
+        // whichever value was stored wins
+        debug!("{}: generating {}", self.name, secret.name);
+        let value = generator.generate()?;
+        vault.get_or_insert(&secret.name, &value).map(SecretContent)
+    }
+}
+
+impl Generated {
+    /// Creates a new Generated backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Generated {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a generated backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        config
+            .secrets
+            .values()
+            .all(|generator| match &generator.format {
+                Format::Alphabet(alphabet) => (1..=256).contains(&alphabet.chars().count()),
+                _ => true,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, Format, Generated, Generator, random_string};
+    use crate::backend::local::KeySource;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::secret::SecretContent;
+    use crate::test_support::secret;
+    use std::collections::HashMap;
+
+    #[test]
+    fn strings_use_the_alphabet() {
+        let value = random_string("ab", 64).expect("generate");
+
+        assert_eq!(value.len(), 64);
+        assert!(value.iter().all(|byte| b"ab".contains(byte)));
+    }
+
+    #[test]
+    fn values_are_stable_until_regenerated() {
+        let dir = std::env::temp_dir().join(format!("generated-{}", std::process::id()));
+        let generator = |length, format| Generator { length, format };
+
+        let backend = Generated {
+            name: "generated".to_string(),
+            config: BackendConfig {
+                database: dir.join("generated.age"),
+                key: KeySource::File(dir.join("generated.key")),
+                secrets: HashMap::from([
+                    ("salt".to_string(), generator(16, Format::Hex)),
+                    ("api-key".to_string(), generator(24, Format::Alphanumeric)),
+                ]),
+            },
+        };
+        let derivation = DerivationInfo::default();
+        let read = |name: &str| {
+            backend
+                .provision(&secret(name), &derivation)
+                .map(|content| content.0)
+        };
+
+        let salt = read("salt").expect("salt");
+        assert_eq!(salt.len(), 32);
+        assert!(salt.iter().all(u8::is_ascii_hexdigit));
+        assert_eq!(read("api-key").expect("api key").len(), 24);
+        assert_eq!(read("salt").expect("salt"), salt);
+        assert!(matches!(read("unlisted"), Err(BackendError::NotFound)));
+
+        let hash = backend.regenerate("salt").expect("regenerate");
+        let regenerated = read("salt").expect("salt");
+        assert_ne!(regenerated, salt);
+        assert_eq!(SecretContent(regenerated).sri_hash(), hash);
+        assert!(matches!(
+            backend.regenerate("unlisted"),
+            Err(BackendError::NotFound)
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
@@ -0,0 +1,264 @@
+use crate::Config;
+use crate::backend::process::ProcessConfig;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// How gpg is run, shared by every backend that decrypts
+/// with it.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct GpgOptions {
+    /// The `GNUPGHOME` holding the decryption keys.
+    pub gnupg_home: PathBuf,
+    /// A file holding the passphrase of the decryption key,
+    /// passed to gpg with loopback pinentry.
+    pub passphrase_file: Option<PathBuf>,
+    #[serde(flatten)]
+    pub process: ProcessConfig,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct BackendConfig {
+    #[serde(flatten)]
+    gpg: GpgOptions,
+    /// A directory of `<name>.gpg` files.
+    secret_dir: Option<PathBuf>,
+    /// Paths to encrypted files keyed by secret name, checked
+    /// before `secret_dir`.
+    #[serde(default)]
+    secrets: HashMap<String, PathBuf>,
+}
+
+/// Map the status lines gpg wrote (`--status-fd 2`) to the
+/// reason decryption failed. Older versions of gpg don't report
+/// `NO_SECKEY`, the environment is cleared so the accompanying
+/// message is untranslated.
+fn classify_failure(path: &Path, stderr: &str) -> Option<BackendError> {
+    let statuses = stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] "))
+        .filter_map(|status| status.split_whitespace().next())
+        .collect::<Vec<_>>();
+
+    let has = |keyword: &str| statuses.contains(&keyword);
+
+    if has("KEYEXPIRED") || has("EXPKEYSIG") {
+        Some(BackendError::Expired(format!(
+            "the key for \"{}\" has expired",
+            path.display()
+        )))
+    } else if has("BAD_PASSPHRASE") || has("MISSING_PASSPHRASE") {
+        Some(BackendError::BadKey(format!(
+            "wrong or missing passphrase for \"{}\"",
+            path.display()
+        )))
+    } else if has("NO_SECKEY") || (has("DECRYPTION_FAILED") && stderr.contains("No secret key")) {
+        Some(BackendError::BadKey(format!(
+            "no secret key can decrypt \"{}\"",
+            path.display()
+        )))
+    } else if has("NODATA") {
+        Some(BackendError::Decrypt(format!(
+            "\"{}\" isn't OpenPGP data",
+            path.display()
+        )))
+    } else if has("DECRYPTION_FAILED") {
+        Some(BackendError::Decrypt(format!(
+            "\"{}\" failed to decrypt",
+            path.display()
+        )))
+    } else {
+        None
+    }
+}
+
+/// Decrypt a `.gpg` file with gpg.
+///
+/// Pinentry is never shown, a passphrase is only ever read
+/// from `options.passphrase_file`.
+///
+/// # Errors
+///
+/// `NotFound` if `path` doesn't exist, `BadKey` or `Expired` if the
+/// keyring can't decrypt it, or the usual process failures.
+pub fn decrypt_file(
+    backend_name: &str,
+    options: &GpgOptions,
+    secret: &Secret,
+    path: &Path,
+) -> Result<SecretContent, BackendError> {
+    if !path.exists() {
+        debug!("no gpg file at \"{}\"", path.display());
+        return Err(BackendError::NotFound);
+    }
+
+    let mut cmd = std::process::Command::new("gpg");
+    cmd.args([
+        "--batch",
+        "--no-tty",
+        "--quiet",
+        "--status-fd",
+        "2",
+        "--pinentry-mode",
+        "loopback",
+    ]);
+
+    if let Some(passphrase_file) = &options.passphrase_file {
+        cmd.arg("--passphrase-file").arg(passphrase_file);
+    }
+
+    cmd.arg("--decrypt").arg(path);
+    cmd.env_clear();
+
+    // Retain parent process' PATH
+    if let Some(path_var) = std::env::var_os("PATH") {
+        cmd.env("PATH", path_var);
+    }
+
+    cmd.env("GNUPGHOME", &options.gnupg_home);
+
+    match crate::backend::provision_with_cmd(backend_name, secret, &mut cmd, &options.process, None)
+    {
+        Err(BackendError::CommandFailed { status, stderr }) => Err(classify_failure(path, &stderr)
+            .unwrap_or(BackendError::CommandFailed { status, stderr })),
+        result => result,
+    }
+}
+
+/// This backend asks [GnuPG](https://gnupg.org) to decrypt
+/// per-secret `.gpg` files using the keys in a dedicated
+/// `GNUPGHOME`.
+///
+/// Files are either `<name>.gpg` in `secret_dir` or mapped
+/// explicitly in `secrets`.
+pub struct Gpg {
+    name: String,
+    config: BackendConfig,
+}
+
+impl Gpg {
+    /// Find the encrypted file for `secret`.
+    fn secret_file(&self, secret: &Secret) -> Option<PathBuf> {
+        if let Some(path) = self.config.secrets.get(&secret.name) {
+            return Some(path.clone());
+        }
+
+        let secret_dir = self.config.secret_dir.as_ref()?;
+
+        // Don't let a secret name escape the secret directory
+        if secret.name.contains('/') || secret.name.starts_with('.') {
+            return None;
+        }
+
+        Some(secret_dir.join(format!("{}.gpg", secret.name)))
+    }
+}
+
+impl Backend<'_> for Gpg {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let Some(path) = self.secret_file(secret) else {
+            return Err(BackendError::NotFound);
+        };
+
+        decrypt_file(&self.name, &self.config.gpg, secret, &path)
+    }
+}
+
+impl Gpg {
+    /// Creates a new Gpg backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Gpg {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a gpg backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, Gpg, GpgOptions, classify_failure};
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::{GpgKeyring, secret};
+    use std::path::Path;
+
+    fn backend(keyring: &GpgKeyring) -> Gpg {
+        Gpg {
+            name: "gpg".to_string(),
+            config: BackendConfig {
+                gpg: GpgOptions {
+                    gnupg_home: keyring.0.clone(),
+                    ..GpgOptions::default()
+                },
+                secret_dir: Some(keyring.0.clone()),
+                ..BackendConfig::default()
+            },
+        }
+    }
+
+    #[test]
+    fn decrypts_with_local_keyring() {
+        let keyring = GpgKeyring::new("ok");
+        keyring.encrypt(b"meow", &keyring.0.join("token.gpg"));
+        let backend = backend(&keyring);
+
+        let content = backend
+            .provision(&secret("token"), &DerivationInfo::default())
+            .expect("provision");
+        assert_eq!(content.0, b"meow");
+
+        assert!(matches!(
+            backend.provision(&secret("missing"), &DerivationInfo::default()),
+            Err(BackendError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn missing_secret_key_is_a_bad_key() {
+        let keyring = GpgKeyring::new("nokey");
+        keyring.encrypt(b"meow", &keyring.0.join("token.gpg"));
+        keyring.delete_secret_keys();
+
+        let result = backend(&keyring).provision(&secret("token"), &DerivationInfo::default());
+
+        assert!(matches!(result, Err(BackendError::BadKey(_))), "{result:?}");
+    }
+
+    #[test]
+    fn expired_key_is_reported_as_expired() {
+        let stderr = "[GNUPG:] ENC_TO 0123456789ABCDEF 18 0
+[GNUPG:] KEYEXPIRED 1700000000
+[GNUPG:] DECRYPTION_FAILED
+gpg: decryption failed: No secret key";
+
+        assert!(matches!(
+            classify_failure(Path::new("token.gpg"), stderr),
+            Some(BackendError::Expired(_))
+        ));
+    }
+}
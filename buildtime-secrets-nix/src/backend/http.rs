@@ -0,0 +1,365 @@
+use crate::Config;
+use crate::backend::client::{self, ClientConfig};
+use crate::backend::template::Placeholders;
+use crate::backend::util::encode_name;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
+
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+    #[default]
+    Get,
+    Post,
+    Put,
+}
+
+impl Method {
+    fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+        }
+    }
+}
+
+fn default_not_found_statuses() -> Vec<u16> {
+    vec![404]
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The URL template, see [`Placeholders`]. `{name}` is
+    /// percent encoded, keeping its slashes.
+    url: String,
+    #[serde(default)]
+    method: Method,
+    /// Header templates.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// The request body template. Values are JSON string escaped
+    /// when the `Content-Type` header is a JSON type, so
+    /// `"{name}"` stays a single string whatever the name is.
+    body: Option<String>,
+    /// A file holding a token sent as `Authorization: Bearer <token>`.
+    bearer_token_file: Option<PathBuf>,
+    /// A JSON pointer, e.g. `/data/value`, selecting the secret
+    /// from a JSON response. The whole body is provisioned
+    /// without one.
+    pointer: Option<String>,
+    /// Statuses meaning the service doesn't have the secret.
+    #[serde(default = "default_not_found_statuses")]
+    not_found_statuses: Vec<u16>,
+    #[serde(flatten)]
+    client: ClientConfig,
+}
+
+/// A backend for in-house secret services that hand out
+/// secrets over HTTP(S).
+///
+/// The request is rendered from the `url`, `headers` and `body`
+/// templates, so `"url": "https://secrets.internal/v1/{name}"`
+/// fetches `db/password` from `https://secrets.internal/v1/db/password`.
+/// A `2xx` response provisions the body, or the value at `pointer`
+/// in it. The `not_found_statuses`, 404 by default, let the next
+/// backend try, `401` and `403` are reported as rejections.
+pub struct Http {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Pick the secret out of a response body.
+fn extract(body: Vec<u8>, pointer: Option<&str>) -> Result<SecretContent, BackendError> {
+    let Some(pointer) = pointer else {
+        return Ok(SecretContent(body));
+    };
+
+    let value = serde_json::from_slice::<serde_json::Value>(&body)
+        .map_err(|err| BackendError::InvalidResponse(err.to_string()))?;
+
+    match value.pointer(pointer) {
+        Some(serde_json::Value::String(value)) => Ok(SecretContent(value.as_bytes().to_vec())),
+        Some(serde_json::Value::Null) | None => Err(BackendError::NotFound),
+        Some(value) => Ok(SecretContent(value.to_string().into_bytes())),
+    }
+}
+
+/// Escape `value` for use inside a JSON string.
+fn escape_json(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+impl Http {
+    fn is_json(&self) -> bool {
+        self.config
+            .headers
+            .iter()
+            .any(|(key, value)| key.eq_ignore_ascii_case("content-type") && value.contains("json"))
+    }
+
+    fn render_body(
+        &self,
+        body: &str,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<String, BackendError> {
+        if !self.is_json() {
+            return Placeholders {
+                secret: Some(secret),
+                derivation,
+                backend: &self.name,
+            }
+            .render(body);
+        }
+
+        let escaped = Secret {
+            name: escape_json(&secret.name),
+            hash: escape_json(&secret.hash),
+            ..secret.clone()
+        };
+        let derivation = DerivationInfo {
+            path: escape_json(&derivation.path),
+            name: escape_json(&derivation.name),
+            ..derivation.clone()
+        };
+        Placeholders {
+            secret: Some(&escaped),
+            derivation: &derivation,
+            backend: &escape_json(&self.name),
+        }
+        .render(body)
+    }
+
+    fn request(
+        &self,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<ureq::http::request::Builder, BackendError> {
+        let encoded = Secret {
+            name: encode_name(&secret.name),
+            ..secret.clone()
+        };
+        let url_placeholders = Placeholders {
+            secret: Some(&encoded),
+            derivation,
+            backend: &self.name,
+        };
+        let placeholders = Placeholders {
+            secret: Some(secret),
+            ..url_placeholders
+        };
+
+        let mut request = ureq::http::Request::builder()
+            .method(self.config.method.as_str())
+            .uri(url_placeholders.render(&self.config.url)?);
+
+        for (key, value) in &self.config.headers {
+            request = request.header(key, placeholders.render(value)?);
+        }
+
+        if let Some(token_file) = &self.config.bearer_token_file {
+            let token = client::read_credential(token_file)?;
+            request = request.header("Authorization", format!("Bearer {token}"));
+        }
+
+        Ok(request)
+    }
+}
+
+impl Backend<'_> for Http {
+    fn provision(
+        &self,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        if !Path::new(&secret.name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(BackendError::NotFound);
+        }
+
+        let agent = client::agent(&self.config.client)?;
+        let request = self.request(secret, derivation)?;
+        let to_error = |err: ureq::http::Error| BackendError::Http(err.to_string());
+
+        debug!("{}: requesting {}", self.name, secret.name);
+        let mut response = match &self.config.body {
+            Some(body) => {
+                let body = self.render_body(body, secret, derivation)?;
+                agent.run(request.body(body).map_err(to_error)?)
+            }
+            None => agent.run(request.body(()).map_err(to_error)?),
+        }
+        .map_err(client::error)?;
+
+        let status = response.status().as_u16();
+        let body = client::read_body(&mut response, &self.config.client)?;
+
+        if self.config.not_found_statuses.contains(&status) {
+            return Err(BackendError::NotFound);
+        }
+
+        if !response.status().is_success() {
+            let message = String::from_utf8_lossy(&body).trim().to_string();
+            return Err(match status {
+                401 | 403 => BackendError::Rejected(message),
+                status => BackendError::Http(format!("server answered {status}: {message}")),
+            });
+        }
+
+        extract(body, self.config.pointer.as_deref())
+    }
+}
+
+impl Http {
+    /// Creates a new Http backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Http {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a http backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, Http, Method};
+    use crate::backend::client::ClientConfig;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::secret;
+    use crate::test_support::serve;
+    use std::collections::HashMap;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn fetches_and_maps_statuses() {
+        let token_file = std::env::temp_dir().join(format!("http-token-{}", std::process::id()));
+        std::fs::write(&token_file, "t0ken\n").expect("write token");
+
+        let (address, server) = serve(vec![
+            (200, r#"{"data": {"value": "hunter2"}}"#.to_string()),
+            (404, String::new()),
+            (403, "go away".to_string()),
+            (200, r#"{"data": {"value": "hunter2"}}"#.to_string()),
+        ]);
+        let mut backend = Http {
+            name: "http".to_string(),
+            config: BackendConfig {
+                url: format!("{address}/v1/{{name}}?drv={{drv_name}}"),
+                method: Method::Post,
+                headers: HashMap::from([("X-Secret".to_string(), "{name}".to_string())]),
+                body: Some(r#"{{"hash": "{hash}"}}"#.to_string()),
+                bearer_token_file: Some(token_file.clone()),
+                pointer: Some("/data/value".to_string()),
+                not_found_statuses: vec![404],
+                client: ClientConfig::default(),
+            },
+        };
+        let derivation = DerivationInfo {
+            path: "/nix/store/x.drv".to_string(),
+            name: "hello".to_string(),
+            fixed_output: false,
+        };
+
+        std::fs::set_permissions(&token_file, std::fs::Permissions::from_mode(0o644))
+            .expect("chmod");
+        assert!(matches!(
+            backend.provision(&secret("db/password"), &derivation),
+            Err(BackendError::BadKey(_))
+        ));
+        std::fs::set_permissions(&token_file, std::fs::Permissions::from_mode(0o600))
+            .expect("chmod");
+
+        let value = backend
+            .provision(&secret("db/pass word"), &derivation)
+            .expect("value");
+        assert_eq!(value.0, b"hunter2");
+        assert!(matches!(
+            backend.provision(&secret("db/gone"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            backend.provision(&secret("db/denied"), &derivation),
+            Err(BackendError::Rejected(message)) if message == "go away"
+        ));
+
+        backend.config.pointer = Some("/data/missing".to_string());
+        assert!(matches!(
+            backend.provision(&secret("db/password"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+
+        let requests = server.join().expect("server");
+        std::fs::remove_file(token_file).expect("remove token");
+
+        assert_eq!(
+            requests[0].line,
+            "POST /v1/db/pass%20word?drv=hello HTTP/1.1"
+        );
+        assert_eq!(requests[0].header("authorization"), Some("Bearer t0ken"));
+        assert_eq!(requests[0].header("x-secret"), Some("db/pass word"));
+        assert_eq!(requests[0].body, br#"{"hash": ""}"#);
+    }
+
+    #[test]
+    fn json_bodies_are_escaped() {
+        let mut backend = Http {
+            name: "http".to_string(),
+            config: BackendConfig {
+                url: "http://localhost/".to_string(),
+                method: Method::Post,
+                headers: HashMap::from([(
+                    "Content-Type".to_string(),
+                    "application/json".to_string(),
+                )]),
+                body: None,
+                bearer_token_file: None,
+                pointer: None,
+                not_found_statuses: vec![404],
+                client: ClientConfig::default(),
+            },
+        };
+        let derivation = DerivationInfo::default();
+        let secret = secret(r#"x","role":"admin"#);
+
+        let body = backend
+            .render_body(r#"{{"name": "{name}"}}"#, &secret, &derivation)
+            .expect("body");
+        let body: serde_json::Value = serde_json::from_str(&body).expect("json");
+        assert_eq!(body, serde_json::json!({ "name": secret.name }));
+
+        backend.config.headers.clear();
+        let body = backend
+            .render_body("name={name}", &secret, &derivation)
+            .expect("body");
+        assert_eq!(body, format!("name={}", secret.name));
+    }
+}
@@ -0,0 +1,297 @@
+use crate::backend::client::{read_credential, read_credential_bytes};
+use crate::backend::util::split_field;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use crate::{Config, Session};
+use keepass::db::{DatabaseOpenError, EntryRef};
+use keepass::{Database, DatabaseKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// The databases unlocked during a hook run, keyed by backend
+/// name, so the key derivation runs once rather than per secret.
+pub(crate) type Databases = Mutex<HashMap<String, Arc<Database>>>;
+
+fn default_field() -> String {
+    "Password".to_string()
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The `.kdbx` file.
+    database: PathBuf,
+    /// A file holding the database password.
+    password_file: Option<PathBuf>,
+    /// A `KeePass` key file.
+    key_file: Option<PathBuf>,
+    /// The field provisioned when the secret name doesn't
+    /// select one.
+    #[serde(default = "default_field")]
+    field: String,
+}
+
+/// This backend reads secrets from a `KeePass` database,
+/// unlocked with a password file, a key file or both.
+///
+/// The secret `Build/Deploy/github` is the entry titled
+/// `github` in the `Build/Deploy` group. Its `Password` is
+/// provisioned unless a `:<field>` suffix selects another
+/// field, e.g. `Build/Deploy/github:UserName`. Custom
+/// attributes and attachments are selected by name the same way.
+pub struct KeePass<'a> {
+    name: String,
+    config: BackendConfig,
+    session: &'a Session,
+}
+
+/// Read a field, falling back to an attachment of the same name.
+fn read_field(entry: &EntryRef<'_>, field: &str) -> Option<Vec<u8>> {
+    if let Some(value) = entry.get(field) {
+        return Some(value.as_bytes().to_vec());
+    }
+
+    entry
+        .attachment_by_name(field)
+        .map(|attachment| attachment.get().clone())
+}
+
+impl KeePass<'_> {
+    fn key(&self) -> Result<DatabaseKey, BackendError> {
+        let mut key = DatabaseKey::new();
+
+        if let Some(path) = &self.config.password_file {
+            key = key.with_password(&read_credential(path)?);
+        }
+
+        if let Some(path) = &self.config.key_file {
+            let key_file = read_credential_bytes(path)?;
+            key = key
+                .with_keyfile(&mut key_file.as_slice())
+                .map_err(|err| BackendError::BadKey(format!("\"{}\": {err}", path.display())))?;
+        }
+
+        if key.is_empty() {
+            return Err(BackendError::BadKey(
+                "one of \"password_file\" and \"key_file\" is required".to_string(),
+            ));
+        }
+
+        Ok(key)
+    }
+
+    /// Unlock the database, or reuse it if this backend already has.
+    fn database(&self) -> Result<Arc<Database>, BackendError> {
+        let mut databases = self
+            .session
+            .databases()
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if let Some(database) = databases.get(&self.name) {
+            return Ok(Arc::clone(database));
+        }
+
+        let path = &self.config.database;
+        let mut file = std::fs::File::open(path)
+            .map_err(|err| BackendError::Decrypt(format!("\"{}\": {err}", path.display())))?;
+
+        debug!("{}: unlocking \"{}\"", self.name, path.display());
+        let database = Database::open(&mut file, self.key()?).map_err(|err| match err {
+            DatabaseOpenError::Key(err) => {
+                BackendError::BadKey(format!("\"{}\": {err}", path.display()))
+            }
+            err => BackendError::Decrypt(format!("\"{}\": {err}", path.display())),
+        })?;
+
+        let database = Arc::new(database);
+        databases.insert(self.name.clone(), Arc::clone(&database));
+        Ok(database)
+    }
+}
+
+impl Backend<'_> for KeePass<'_> {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let (path, field) = split_field(&secret.name);
+        let field = field.unwrap_or(&self.config.field);
+
+        let (groups, title) = match path.rsplit_once('/') {
+            Some((groups, title)) => (groups.split('/').collect::<Vec<_>>(), title),
+            None => (Vec::new(), path),
+        };
+
+        let database = self.database()?;
+
+        database
+            .root()
+            .group_by_path(&groups)
+            .and_then(|group| {
+                group
+                    .entry_by_name(title)
+                    .and_then(|entry| read_field(&entry, field))
+            })
+            .map(SecretContent)
+            .ok_or(BackendError::NotFound)
+    }
+}
+
+impl<'a> KeePass<'a> {
+    /// Creates a new `KeePass` backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str, session: &'a Session) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(KeePass {
+            name: name.to_string(),
+            config,
+            session,
+        })
+    }
+
+    /// Validate a keepass backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        config.password_file.is_some() || config.key_file.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, KeePass};
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::{credential, secret};
+    use crate::{Config, Session};
+    use keepass::config::KdfConfig;
+    use keepass::db::{Value, fields};
+    use keepass::{Database, DatabaseKey};
+    use std::path::Path;
+
+    /// Write a fixture database with a cheap key derivation.
+    fn write_database(path: &Path, password: &str) {
+        let mut database = Database::new();
+        database.config.kdf_config = KdfConfig::Aes { rounds: 100 };
+
+        let mut root = database.root_mut();
+        let mut build = root.add_group();
+        build.name = "Build".to_string();
+        let mut deploy = build.add_group();
+        deploy.name = "Deploy".to_string();
+
+        let mut entry = deploy.add_entry();
+        entry.set_unprotected(fields::TITLE, "github");
+        entry.set_unprotected(fields::USERNAME, "octocat");
+        entry.set_protected(fields::PASSWORD, "hunter2");
+        entry.set_protected("Token", "ghp_t0ken");
+        entry.add_attachment("id_ed25519", Value::protected(b"PRIVATE KEY".to_vec()));
+
+        let mut file = std::fs::File::create(path).expect("create database");
+        database
+            .save(&mut file, DatabaseKey::new().with_password(password))
+            .expect("save database");
+    }
+
+    #[test]
+    fn reads_entries_fields_and_attachments() {
+        let dir = std::env::temp_dir().join(format!("keepass-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        write_database(&dir.join("build.kdbx"), "correct horse");
+        credential(&dir.join("password"), "correct horse\n");
+        credential(&dir.join("wrong"), "battery staple\n");
+
+        let session = Session::new(&Config::default()).expect("session");
+        let backend = KeePass {
+            name: "keepass-test".to_string(),
+            config: BackendConfig {
+                database: dir.join("build.kdbx"),
+                password_file: Some(dir.join("password")),
+                key_file: None,
+                field: "Password".to_string(),
+            },
+            session: &session,
+        };
+        let derivation = DerivationInfo::default();
+
+        let read = |name: &str| backend.provision(&secret(name), &derivation);
+        assert_eq!(read("Build/Deploy/github").expect("password").0, b"hunter2");
+        assert_eq!(
+            read("build/deploy/github:UserName").expect("username").0,
+            b"octocat"
+        );
+        assert_eq!(
+            read("Build/Deploy/github:Token").expect("token").0,
+            b"ghp_t0ken"
+        );
+        assert_eq!(
+            read("Build/Deploy/github:id_ed25519")
+                .expect("attachment")
+                .0,
+            b"PRIVATE KEY"
+        );
+        assert!(matches!(
+            read("Build/Deploy/gitlab"),
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            read("Build/Deploy/github:Missing"),
+            Err(BackendError::NotFound)
+        ));
+
+        let wrong_password = KeePass {
+            name: "keepass-test-wrong".to_string(),
+            config: BackendConfig {
+                password_file: Some(dir.join("wrong")),
+                ..backend.config.clone()
+            },
+            session: &session,
+        };
+        assert!(matches!(
+            wrong_password.provision(&secret("Build/Deploy/github"), &derivation),
+            Err(BackendError::BadKey(_))
+        ));
+
+        // Another hook run doesn't reuse the unlocked database
+        let other_session = Session::new(&Config::default()).expect("session");
+        let other_run = KeePass {
+            name: "keepass-test".to_string(),
+            session: &other_session,
+            ..wrong_password
+        };
+        assert!(matches!(
+            other_run.provision(&secret("Build/Deploy/github"), &derivation),
+            Err(BackendError::BadKey(_))
+        ));
+
+        // A password file others can read is refused
+        std::fs::set_permissions(
+            dir.join("wrong"),
+            std::os::unix::fs::PermissionsExt::from_mode(0o644),
+        )
+        .expect("chmod");
+        let readable = KeePass {
+            name: "keepass-test-readable".to_string(),
+            ..other_run
+        };
+        assert!(matches!(
+            readable.provision(&secret("Build/Deploy/github"), &derivation),
+            Err(BackendError::BadKey(msg)) if msg.contains("not accessible by others")
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
@@ -0,0 +1,308 @@
+use crate::Config;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use tracing::debug;
+
+// From linux/keyctl.h
+const KEY_SPEC_PROCESS_KEYRING: libc::c_long = -2;
+const KEY_SPEC_SESSION_KEYRING: libc::c_long = -3;
+pub(crate) const KEY_SPEC_USER_KEYRING: libc::c_long = -4;
+const KEY_SPEC_USER_SESSION_KEYRING: libc::c_long = -5;
+const KEYCTL_SEARCH: libc::c_long = 10;
+const KEYCTL_READ: libc::c_long = 11;
+const KEYCTL_GET_PERSISTENT: libc::c_long = 22;
+
+/// The keyring searched for secrets.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    /// `@u`, shared by every process of the hooks user.
+    #[default]
+    User,
+    /// `@s`
+    Session,
+    /// `@us`
+    UserSession,
+    /// The users persistent keyring, which outlives their sessions.
+    Persistent,
+    /// A keyring with this description, linked into the session
+    /// or user keyring.
+    Named(String),
+}
+
+fn default_prefix() -> String {
+    "buildsecret:".to_string()
+}
+
+fn default_key_type() -> String {
+    "user".to_string()
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    #[serde(default)]
+    keyring: Target,
+    /// Prepended to the secret name to form the key description.
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    #[serde(default = "default_key_type")]
+    key_type: String,
+}
+
+/// This backend reads secrets from the Linux kernel keyring, so
+/// they never need to touch the disk before being provisioned.
+///
+/// The secret `github` is the `user` key described as
+/// `buildsecret:github`, e.g. one added with
+/// `keyctl padd user buildsecret:github @u`. The kernel only
+/// lets the hook find and read keys whose permissions allow it,
+/// a key it can't search for is treated as missing and one it
+/// can't read is reported.
+pub struct Keyring {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Call `keyctl(2)`, returning its non-negative result.
+fn keyctl(
+    operation: libc::c_long,
+    args: [libc::c_long; 4],
+) -> Result<libc::c_long, std::io::Error> {
+    // SAFETY: every operation used here takes integers and, where
+    // noted by the caller, pointers valid for the given lengths
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            operation,
+            args[0],
+            args[1],
+            args[2],
+            args[3],
+        )
+    };
+
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(ret)
+}
+
+fn cstring(value: &str) -> Result<CString, BackendError> {
+    CString::new(value).map_err(|_| BackendError::NotFound)
+}
+
+/// Search `keyring`, and the keyrings linked to it, for a key.
+pub(crate) fn search(
+    keyring: libc::c_long,
+    key_type: &str,
+    description: &str,
+) -> Result<libc::c_long, BackendError> {
+    let key_type = cstring(key_type)?;
+    let description = cstring(description)?;
+
+    // The strings outlive the call
+    keyctl(
+        KEYCTL_SEARCH,
+        [
+            keyring,
+            key_type.as_ptr() as libc::c_long,
+            description.as_ptr() as libc::c_long,
+            0,
+        ],
+    )
+    .map_err(|err| match err.raw_os_error() {
+        Some(libc::ENOKEY | libc::EKEYREVOKED | libc::EKEYEXPIRED | libc::EACCES) => {
+            BackendError::NotFound
+        }
+        _ => BackendError::Rejected(format!("can't search keyring: {err}")),
+    })
+}
+
+/// Read the payload of `key`.
+pub(crate) fn read(key: libc::c_long) -> Result<Vec<u8>, BackendError> {
+    let to_error = |err: std::io::Error| match err.raw_os_error() {
+        Some(libc::EACCES | libc::EOPNOTSUPP) => {
+            BackendError::Rejected(format!("can't read key {key}: {err}"))
+        }
+        Some(libc::EKEYREVOKED | libc::EKEYEXPIRED) => BackendError::NotFound,
+        _ => BackendError::Decrypt(format!("can't read key {key}: {err}")),
+    };
+
+    let mut buffer = Vec::new();
+
+    // The payload can change size between calls, so retry until
+    // it fits
+    loop {
+        // The buffer is valid for its length
+        let length = keyctl(
+            KEYCTL_READ,
+            [
+                key,
+                buffer.as_mut_ptr() as libc::c_long,
+                libc::c_long::try_from(buffer.len()).unwrap_or(libc::c_long::MAX),
+                0,
+            ],
+        )
+        .map_err(to_error)?;
+        let length = usize::try_from(length).unwrap_or_default();
+
+        if length <= buffer.len() {
+            buffer.truncate(length);
+            return Ok(buffer);
+        }
+
+        buffer.resize(length, 0);
+    }
+}
+
+/// Add a key to `keyring`, replacing the payload of an existing
+/// key with the same type and description.
+pub(crate) fn add(
+    keyring: libc::c_long,
+    key_type: &str,
+    description: &str,
+    payload: &[u8],
+) -> Result<libc::c_long, BackendError> {
+    let key_type = cstring(key_type)?;
+    let description = cstring(description)?;
+
+    // SAFETY: the strings and payload outlive the call
+    let key = unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            key_type.as_ptr(),
+            description.as_ptr(),
+            payload.as_ptr(),
+            payload.len(),
+            keyring,
+        )
+    };
+
+    if key < 0 {
+        return Err(BackendError::Rejected(format!(
+            "can't add key: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    Ok(key)
+}
+
+impl Keyring {
+    /// Find the serial of the configured keyring.
+    fn keyring(&self) -> Result<libc::c_long, BackendError> {
+        match &self.config.keyring {
+            Target::User => Ok(KEY_SPEC_USER_KEYRING),
+            Target::Session => Ok(KEY_SPEC_SESSION_KEYRING),
+            Target::UserSession => Ok(KEY_SPEC_USER_SESSION_KEYRING),
+            // -1 is the calling user
+            Target::Persistent => {
+                keyctl(KEYCTL_GET_PERSISTENT, [-1, KEY_SPEC_PROCESS_KEYRING, 0, 0])
+                    .map_err(|err| BackendError::Rejected(format!("no persistent keyring: {err}")))
+            }
+            Target::Named(description) => search(KEY_SPEC_SESSION_KEYRING, "keyring", description)
+                .or_else(|_| search(KEY_SPEC_USER_KEYRING, "keyring", description))
+                .map_err(|_| BackendError::Rejected(format!("no keyring named \"{description}\""))),
+        }
+    }
+}
+
+impl Backend<'_> for Keyring {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let keyring = self.keyring()?;
+        let description = format!("{}{}", self.config.prefix, secret.name);
+
+        debug!("{}: searching for {description}", self.name);
+        let key = search(keyring, &self.config.key_type, &description)?;
+
+        read(key).map(SecretContent)
+    }
+}
+
+impl Keyring {
+    /// Creates a new Keyring backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Keyring {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a keyring backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, KEY_SPEC_USER_KEYRING, Keyring, Target, keyctl};
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::secret;
+    use std::ffi::CString;
+
+    const KEYCTL_INVALIDATE: libc::c_long = 21;
+
+    #[test]
+    fn reads_user_keys() {
+        let description =
+            CString::new(format!("buildsecret:test-{}", std::process::id())).expect("description");
+        let payload = b"hunter2";
+
+        // SAFETY: the strings and payload outlive the call
+        let key = unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                c"user".as_ptr(),
+                description.as_ptr(),
+                payload.as_ptr(),
+                payload.len(),
+                KEY_SPEC_USER_KEYRING,
+            )
+        };
+        assert!(
+            key >= 0,
+            "can't add a key: {}",
+            std::io::Error::last_os_error()
+        );
+
+        let backend = Keyring {
+            name: "keyring".to_string(),
+            config: BackendConfig {
+                keyring: Target::User,
+                prefix: "buildsecret:".to_string(),
+                key_type: "user".to_string(),
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        let name = format!("test-{}", std::process::id());
+        let result = backend.provision(&secret(&name), &derivation);
+        let missing = backend.provision(&secret("test-missing"), &derivation);
+        keyctl(KEYCTL_INVALIDATE, [key, 0, 0, 0]).expect("invalidate key");
+
+        assert_eq!(result.expect("key").0, payload);
+        assert!(matches!(missing, Err(BackendError::NotFound)));
+    }
+}
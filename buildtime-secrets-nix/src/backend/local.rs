@@ -0,0 +1,470 @@
+use crate::Config;
+use crate::backend::generated::Generated;
+use crate::backend::{Backend, BackendKind, DerivationInfo, client, keyring};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use age::secrecy::ExposeSecret;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::debug;
+
+/// Where the vault key comes from.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// An age identity file, only accessible by root.
+    File(PathBuf),
+    /// The description of a `user` key in root's user keyring
+    /// holding an age identity. The keyring doesn't survive a
+    /// reboot, so the key has to be added again at boot.
+    Keyring(String),
+}
+
+fn default_database() -> PathBuf {
+    PathBuf::from("/var/lib/buildtime-secrets/local.age")
+}
+
+fn default_key() -> KeySource {
+    KeySource::File(PathBuf::from("/var/lib/buildtime-secrets/local.key"))
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The encrypted database.
+    #[serde(default = "default_database")]
+    pub(crate) database: PathBuf,
+    #[serde(default = "default_key")]
+    pub(crate) key: KeySource,
+}
+
+/// The decrypted contents of a vault.
+#[derive(Default, Serialize, Deserialize)]
+struct Contents {
+    /// Base64 encoded values keyed by secret name.
+    secrets: BTreeMap<String, String>,
+}
+
+/// A single file secret store, encrypted with age to a key
+/// held in a root-only file or the kernel keyring.
+///
+/// Secrets are managed with the `vault` subcommands, every
+/// change rewrites the whole file under an exclusive lock.
+pub struct LocalVault {
+    config: BackendConfig,
+}
+
+fn missing_key() -> BackendError {
+    BackendError::BadKey("the vault key doesn't exist".to_string())
+}
+
+/// An exclusive lock on a vault, released when dropped.
+pub(crate) struct Lock {
+    _file: std::fs::File,
+}
+
+impl LocalVault {
+    pub(crate) fn with_config(config: BackendConfig) -> Self {
+        LocalVault { config }
+    }
+
+    /// Open the vault of `backend_config.<name>`, a local or
+    /// generated backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn open(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let instance = (BackendKind::Generated, name.to_string());
+        if crate::backend::instances(root_config).contains(&instance) {
+            return Ok(Generated::new(root_config, name)?.vault());
+        }
+
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(LocalVault { config })
+    }
+
+    fn path_error(path: &Path, err: &std::io::Error) -> BackendError {
+        BackendError::Decrypt(format!("\"{}\": {err}", path.display()))
+    }
+
+    /// Take the lock guarding changes to the vault.
+    pub(crate) fn lock(&self) -> Result<Lock, BackendError> {
+        let path = with_suffix(&self.config.database, ".lock");
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| Self::path_error(parent, &err))?;
+        }
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|err| Self::path_error(&path, &err))?;
+
+        // SAFETY: the descriptor is open for the duration of the call
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } < 0 {
+            return Err(Self::path_error(&path, &std::io::Error::last_os_error()));
+        }
+
+        Ok(Lock { _file: file })
+    }
+
+    /// Read the key, `None` if it hasn't been created yet.
+    fn read_key(&self) -> Result<Option<age::x25519::Identity>, BackendError> {
+        let key = match &self.config.key {
+            KeySource::File(path) => {
+                if !path.exists() {
+                    return Ok(None);
+                }
+                client::read_credential(path)?
+            }
+            KeySource::Keyring(description) => {
+                let key = match keyring::search(keyring::KEY_SPEC_USER_KEYRING, "user", description)
+                {
+                    Ok(key) => key,
+                    Err(BackendError::NotFound) => return Ok(None),
+                    Err(err) => return Err(err),
+                };
+                String::from_utf8(keyring::read(key)?)
+                    .map_err(|err| BackendError::BadKey(err.to_string()))?
+            }
+        };
+
+        age::x25519::Identity::from_str(key.trim())
+            .map(Some)
+            .map_err(|err| BackendError::BadKey(err.to_string()))
+    }
+
+    fn key(&self) -> Result<age::x25519::Identity, BackendError> {
+        self.read_key()?.ok_or_else(missing_key)
+    }
+
+    /// Store a new key, replacing any existing one.
+    fn write_key(&self, identity: &age::x25519::Identity) -> Result<(), BackendError> {
+        let key = identity.to_string();
+
+        match &self.config.key {
+            KeySource::File(path) => write_private(path, key.expose_secret().as_bytes()),
+            KeySource::Keyring(description) => keyring::add(
+                keyring::KEY_SPEC_USER_KEYRING,
+                "user",
+                description,
+                key.expose_secret().as_bytes(),
+            )
+            .map(|_| ()),
+        }
+    }
+
+    fn load(&self, identity: &age::x25519::Identity) -> Result<Contents, BackendError> {
+        let path = &self.config.database;
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Contents::default());
+            }
+            Err(err) => return Err(Self::path_error(path, &err)),
+        };
+
+        let decryptor = age::Decryptor::new(file).map_err(|err| Self::decrypt_error(path, err))?;
+        let mut reader = decryptor
+            .decrypt(std::iter::once(identity as &dyn age::Identity))
+            .map_err(|err| Self::decrypt_error(path, err))?;
+
+        let mut contents = Vec::new();
+        reader
+            .read_to_end(&mut contents)
+            .map_err(|err| Self::path_error(path, &err))?;
+
+        serde_json::from_slice(&contents)
+            .map_err(|err| BackendError::Decrypt(format!("\"{}\": {err}", path.display())))
+    }
+
+    fn decrypt_error(path: &Path, err: age::DecryptError) -> BackendError {
+        match err {
+            age::DecryptError::NoMatchingKeys => BackendError::BadKey(format!(
+                "the vault key can't decrypt \"{}\"",
+                path.display()
+            )),
+            err => BackendError::Decrypt(format!("\"{}\": {err}", path.display())),
+        }
+    }
+
+    fn save(
+        &self,
+        contents: &Contents,
+        recipients: &[age::x25519::Recipient],
+    ) -> Result<(), BackendError> {
+        let path = &self.config.database;
+        let encrypt_error = |err: &dyn std::fmt::Display| {
+            BackendError::Decrypt(format!("can't encrypt \"{}\": {err}", path.display()))
+        };
+
+        let encryptor = age::Encryptor::with_recipients(
+            recipients
+                .iter()
+                .map(|recipient| recipient as &dyn age::Recipient),
+        )
+        .map_err(|err| encrypt_error(&err))?;
+
+        let mut encrypted = Vec::new();
+        let mut writer = encryptor
+            .wrap_output(&mut encrypted)
+            .map_err(|err| encrypt_error(&err))?;
+        serde_json::to_writer(&mut writer, contents).map_err(|err| encrypt_error(&err))?;
+        writer.finish().map_err(|err| encrypt_error(&err))?;
+
+        write_private(path, &encrypted)
+    }
+
+    /// Apply `change` to the secrets, creating the key and the
+    /// vault if neither exists yet.
+    pub(crate) fn update<T>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, String>) -> T,
+    ) -> Result<T, BackendError> {
+        let _lock = self.lock()?;
+
+        let identity = match self.read_key()? {
+            Some(identity) => identity,
+            None if !self.config.database.exists() => {
+                debug!("creating a key for \"{}\"", self.config.database.display());
+                let identity = age::x25519::Identity::generate();
+                self.write_key(&identity)?;
+                identity
+            }
+            None => return Err(missing_key()),
+        };
+
+        let mut contents = self.load(&identity)?;
+        let result = change(&mut contents.secrets);
+        self.save(&contents, &[identity.to_public()])?;
+
+        Ok(result)
+    }
+
+    /// Read a secret.
+    ///
+    /// # Errors
+    ///
+    /// If the vault can't be decrypted.
+    pub fn get(&self, name: &str) -> Result<Option<Vec<u8>>, BackendError> {
+        if !self.config.database.exists() {
+            return Ok(None);
+        }
+
+        let contents = self.load(&self.key()?)?;
+
+        contents
+            .secrets
+            .get(name)
+            .map(|value| {
+                STANDARD
+                    .decode(value)
+                    .map_err(|err| BackendError::Decrypt(format!("\"{name}\": {err}")))
+            })
+            .transpose()
+    }
+
+    /// Add a secret, replacing any existing value.
+    ///
+    /// # Errors
+    ///
+    /// If the vault can't be decrypted or written.
+    pub fn insert(&self, name: &str, value: &[u8]) -> Result<(), BackendError> {
+        self.update(|secrets| {
+            secrets.insert(name.to_string(), STANDARD.encode(value));
+        })
+    }
+
+    /// Add a secret unless it already exists, returning the
+    /// stored value.
+    ///
+    /// # Errors
+    ///
+    /// If the vault can't be decrypted or written.
+    pub fn get_or_insert(&self, name: &str, value: &[u8]) -> Result<Vec<u8>, BackendError> {
+        let stored = self.update(|secrets| {
+            secrets
+                .entry(name.to_string())
+                .or_insert_with(|| STANDARD.encode(value))
+                .clone()
+        })?;
+
+        STANDARD
+            .decode(stored)
+            .map_err(|err| BackendError::Decrypt(format!("\"{name}\": {err}")))
+    }
+
+    /// Remove a secret, returning whether it existed.
+    ///
+    /// # Errors
+    ///
+    /// If the vault can't be decrypted or written.
+    pub fn remove(&self, name: &str) -> Result<bool, BackendError> {
+        self.update(|secrets| secrets.remove(name).is_some())
+    }
+
+    /// List the names of every secret.
+    ///
+    /// # Errors
+    ///
+    /// If the vault can't be decrypted.
+    pub fn list(&self) -> Result<Vec<String>, BackendError> {
+        if !self.config.database.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = self.load(&self.key()?)?;
+        Ok(contents.secrets.into_keys().collect())
+    }
+
+    /// Re-encrypt the vault with a freshly generated key.
+    ///
+    /// The vault is first encrypted to both keys, so it stays
+    /// readable whether or not the new key was stored.
+    ///
+    /// # Errors
+    ///
+    /// If the vault can't be decrypted or written, or the new
+    /// key can't be stored.
+    pub fn rotate_key(&self) -> Result<(), BackendError> {
+        let _lock = self.lock()?;
+
+        let old = self.key()?;
+        let new = age::x25519::Identity::generate();
+        let contents = self.load(&old)?;
+
+        self.save(&contents, &[old.to_public(), new.to_public()])?;
+        self.write_key(&new)?;
+        self.save(&contents, &[new.to_public()])
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Atomically replace `path` with a file only root can read.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), BackendError> {
+    let write_error =
+        |err: std::io::Error| BackendError::Rejected(format!("\"{}\": {err}", path.display()));
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(write_error)?;
+    }
+
+    let temporary = with_suffix(path, ".tmp");
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .mode(0o600)
+        .open(&temporary)
+        .map_err(write_error)?;
+    file.write_all(contents).map_err(write_error)?;
+    file.sync_all().map_err(write_error)?;
+
+    std::fs::rename(&temporary, path).map_err(write_error)
+}
+
+/// This backend reads secrets from the local vault, added with
+/// `buildtime-secrets-nix vault add`.
+pub struct Local {
+    vault: LocalVault,
+}
+
+impl Backend<'_> for Local {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        self.vault
+            .get(&secret.name)?
+            .map(SecretContent)
+            .ok_or(BackendError::NotFound)
+    }
+}
+
+impl Local {
+    /// Creates a new Local backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        Ok(Local {
+            vault: LocalVault::open(root_config, name)?,
+        })
+    }
+
+    /// Validate a local backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Local;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::{secret, vault};
+
+    #[test]
+    fn manages_and_provisions_secrets() {
+        let dir = std::env::temp_dir().join(format!("local-{}", std::process::id()));
+        let vault = vault(&dir);
+
+        assert!(vault.list().expect("empty list").is_empty());
+        vault.insert("github", b"hunter2").expect("insert");
+        vault.insert("signing-key", &[0, 1, 2]).expect("insert");
+        assert_eq!(vault.list().expect("list"), ["github", "signing-key"]);
+
+        vault.rotate_key().expect("rotate");
+        assert!(vault.remove("signing-key").expect("remove"));
+        assert!(!vault.remove("signing-key").expect("remove"));
+
+        let backend = Local { vault };
+        let derivation = DerivationInfo::default();
+
+        let content = backend
+            .provision(&secret("github"), &derivation)
+            .expect("secret");
+        assert_eq!(content.0, b"hunter2");
+        assert!(matches!(
+            backend.provision(&secret("signing-key"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+
+        // A key that didn't encrypt the vault is reported
+        std::fs::remove_file(dir.join("local.key")).expect("remove key");
+        backend
+            .vault
+            .write_key(&age::x25519::Identity::generate())
+            .expect("write key");
+        assert!(matches!(
+            backend.provision(&secret("github"), &derivation),
+            Err(BackendError::BadKey(_))
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
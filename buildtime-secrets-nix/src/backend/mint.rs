@@ -0,0 +1,473 @@
+use crate::Config;
+use crate::backend::client;
+use crate::backend::generated::fill_random;
+use crate::backend::util::hex;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use rcgen::{
+    CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, Issuer, KeyPair,
+    KeyUsagePurpose,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, Ed25519KeyPair};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use time::OffsetDateTime;
+use tracing::debug;
+
+fn default_validity_secs() -> u64 {
+    3600
+}
+
+fn default_ttl_secs() -> u64 {
+    300
+}
+
+/// A client certificate signed by a local CA.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CertificateConfig {
+    /// The PEM encoded CA certificate.
+    ca_certificate: PathBuf,
+    /// The PEM encoded PKCS#8 CA key.
+    ca_key: PathBuf,
+    /// Subject attributes keyed by their short name, one of `CN`,
+    /// `O`, `L`, `ST` or `C`. The organizational unit is the
+    /// derivation path.
+    subject: BTreeMap<String, String>,
+    #[serde(default)]
+    dns_names: Vec<String>,
+    #[serde(default = "default_validity_secs")]
+    validity_secs: u64,
+}
+
+/// A JWT signing algorithm.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Algorithm {
+    /// HMAC-SHA256, keyed by the raw bytes of the key file.
+    #[serde(rename = "HS256")]
+    Hs256,
+    /// Ed25519, with a PEM encoded PKCS#8 key.
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+    /// ECDSA P-256, with a PEM encoded PKCS#8 key.
+    #[serde(rename = "ES256")]
+    Es256,
+}
+
+/// A JWT signed by a local key.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct JwtConfig {
+    key: PathBuf,
+    algorithm: Algorithm,
+    /// The `kid` header.
+    key_id: Option<String>,
+    /// Claims added to every token.
+    #[serde(default)]
+    claims: serde_json::Map<String, serde_json::Value>,
+    #[serde(default = "default_ttl_secs")]
+    ttl_secs: u64,
+}
+
+/// A credential minted on every provisioning.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Credential {
+    Certificate(CertificateConfig),
+    Jwt(JwtConfig),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The credentials this backend mints.
+    credentials: HashMap<String, Credential>,
+}
+
+/// This backend mints a short-lived credential every time one is
+/// provisioned: a client certificate signed by a local CA, or a
+/// JWT signed by a local key.
+///
+/// A certificate is provisioned as the PEM encoded certificate
+/// followed by its fresh key, with the derivation path as its
+/// organizational unit. A JWT gets `iat`, `exp`, a random `jti`
+/// and the derivation path as `drv` on top of its configured
+/// claims. Since no two credentials are alike, they can't be
+/// pinned by `Secret.hash` and must be declared ephemeral.
+pub struct Mint {
+    name: String,
+    config: BackendConfig,
+}
+
+fn dn_type(attribute: &str) -> Option<DnType> {
+    match attribute {
+        "CN" => Some(DnType::CommonName),
+        "O" => Some(DnType::OrganizationName),
+        "L" => Some(DnType::LocalityName),
+        "ST" => Some(DnType::StateOrProvinceName),
+        "C" => Some(DnType::CountryName),
+        _ => None,
+    }
+}
+
+impl CertificateConfig {
+    fn mint(&self, derivation: &DerivationInfo) -> Result<Vec<u8>, BackendError> {
+        let certificate_error =
+            |err: rcgen::Error| BackendError::Rejected(format!("can't mint certificate: {err}"));
+
+        let ca_key = KeyPair::from_pem(&client::read_credential(&self.ca_key)?)
+            .map_err(|err| BackendError::BadKey(format!("CA key: {err}")))?;
+        let ca_certificate = std::fs::read_to_string(&self.ca_certificate).map_err(|err| {
+            BackendError::BadKey(format!("\"{}\": {err}", self.ca_certificate.display()))
+        })?;
+        let issuer = Issuer::from_ca_cert_pem(&ca_certificate, ca_key)
+            .map_err(|err| BackendError::BadKey(format!("CA certificate: {err}")))?;
+
+        let mut params =
+            CertificateParams::new(self.dns_names.clone()).map_err(certificate_error)?;
+        params.distinguished_name = DistinguishedName::new();
+        for (attribute, value) in &self.subject {
+            let dn_type = dn_type(attribute).ok_or_else(|| {
+                BackendError::Rejected(format!("unsupported subject attribute \"{attribute}\""))
+            })?;
+            params.distinguished_name.push(dn_type, value.as_str());
+        }
+        params
+            .distinguished_name
+            .push(DnType::OrganizationalUnitName, derivation.path.as_str());
+
+        // Leave some room for clocks that are behind
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::from_mins(5);
+        params.not_after = now + Duration::from_secs(self.validity_secs);
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.use_authority_key_identifier_extension = true;
+
+        let key = KeyPair::generate().map_err(certificate_error)?;
+        let certificate = params.signed_by(&key, &issuer).map_err(certificate_error)?;
+
+        Ok(format!("{}{}", certificate.pem(), key.serialize_pem()).into_bytes())
+    }
+}
+
+/// Read a PEM encoded PKCS#8 key.
+fn pkcs8_key(path: &Path) -> Result<Vec<u8>, BackendError> {
+    pem::parse(client::read_credential(path)?)
+        .map(pem::Pem::into_contents)
+        .map_err(|err| BackendError::BadKey(format!("\"{}\": {err}", path.display())))
+}
+
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Hs256 => "HS256",
+            Algorithm::EdDsa => "EdDSA",
+            Algorithm::Es256 => "ES256",
+        }
+    }
+
+    fn sign(self, key: &Path, message: &[u8]) -> Result<Vec<u8>, BackendError> {
+        let bad_key = |err: &dyn std::fmt::Display| {
+            BackendError::BadKey(format!("\"{}\": {err}", key.display()))
+        };
+
+        match self {
+            Algorithm::Hs256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&client::read_credential_bytes(key)?)
+                    .expect("hmac accepts any key length");
+                mac.update(message);
+                Ok(mac.finalize().into_bytes().to_vec())
+            }
+            Algorithm::EdDsa => {
+                let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8_key(key)?)
+                    .map_err(|err| bad_key(&err))?;
+                Ok(key_pair.sign(message).as_ref().to_vec())
+            }
+            Algorithm::Es256 => {
+                let rng = SystemRandom::new();
+                let key_pair = EcdsaKeyPair::from_pkcs8(
+                    &ECDSA_P256_SHA256_FIXED_SIGNING,
+                    &pkcs8_key(key)?,
+                    &rng,
+                )
+                .map_err(|err| bad_key(&err))?;
+                let signature = key_pair
+                    .sign(&rng, message)
+                    .map_err(|err| BackendError::Rejected(format!("can't sign: {err}")))?;
+                Ok(signature.as_ref().to_vec())
+            }
+        }
+    }
+}
+
+impl JwtConfig {
+    fn mint(&self, derivation: &DerivationInfo) -> Result<Vec<u8>, BackendError> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|err| BackendError::Rejected(format!("clock is before 1970: {err}")))?
+            .as_secs();
+        let mut jti = [0; 16];
+        fill_random(&mut jti)?;
+
+        let mut header = serde_json::Map::new();
+        header.insert("alg".to_string(), self.algorithm.name().into());
+        header.insert("typ".to_string(), "JWT".into());
+        if let Some(key_id) = &self.key_id {
+            header.insert("kid".to_string(), key_id.as_str().into());
+        }
+
+        let mut claims = self.claims.clone();
+        claims.insert("iat".to_string(), now.into());
+        claims.insert("exp".to_string(), (now + self.ttl_secs).into());
+        claims.insert("jti".to_string(), hex(&jti).into());
+        claims.insert("drv".to_string(), derivation.path.as_str().into());
+
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::Value::Object(header).to_string()),
+            URL_SAFE_NO_PAD.encode(serde_json::Value::Object(claims).to_string())
+        );
+        let signature = self.algorithm.sign(&self.key, signing_input.as_bytes())?;
+
+        Ok(format!("{signing_input}.{}", URL_SAFE_NO_PAD.encode(signature)).into_bytes())
+    }
+}
+
+impl Backend<'_> for Mint {
+    fn provision(
+        &self,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let Some(credential) = self.config.credentials.get(&secret.name) else {
+            return Err(BackendError::NotFound);
+        };
+
+        debug!(
+            "{}: minting {} for {}",
+            self.name, secret.name, derivation.name
+        );
+        match credential {
+            Credential::Certificate(certificate) => certificate.mint(derivation),
+            Credential::Jwt(jwt) => jwt.mint(derivation),
+        }
+        .map(SecretContent)
+    }
+}
+
+impl Mint {
+    /// Creates a new Mint backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Mint {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a mint backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        config
+            .credentials
+            .values()
+            .all(|credential| match credential {
+                Credential::Certificate(certificate) => certificate
+                    .subject
+                    .keys()
+                    .all(|attribute| dn_type(attribute).is_some()),
+                Credential::Jwt(_) => true,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, BackendConfig, CertificateConfig, Credential, JwtConfig, Mint};
+    use crate::backend::{Backend, BackendKind, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::credential;
+    use crate::test_support::secret;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use hmac::{Hmac, Mac};
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use ring::rand::SystemRandom;
+    use ring::signature::{ED25519, Ed25519KeyPair, KeyPair as _, UnparsedPublicKey};
+    use sha2::Sha256;
+    use std::collections::{BTreeMap, HashMap};
+    use std::os::unix::fs::PermissionsExt;
+
+    fn derivation() -> DerivationInfo {
+        DerivationInfo {
+            path: "/nix/store/aaaa-fetch-thing.drv".to_string(),
+            name: "fetch-thing".to_string(),
+            fixed_output: false,
+        }
+    }
+
+    #[test]
+    fn mints_client_certificates() {
+        let dir = std::env::temp_dir().join(format!("mint-ca-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+
+        let ca_key = KeyPair::generate().expect("generate CA key");
+        let mut ca_params = CertificateParams::new(Vec::new()).expect("CA params");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "Build CA");
+        let ca_certificate = ca_params.self_signed(&ca_key).expect("self sign");
+        std::fs::write(dir.join("ca.pem"), ca_certificate.pem()).expect("write CA");
+        credential(&dir.join("ca.key"), &ca_key.serialize_pem());
+
+        let backend = Mint {
+            name: "mint".to_string(),
+            config: BackendConfig {
+                credentials: HashMap::from([(
+                    "s3-client".to_string(),
+                    Credential::Certificate(CertificateConfig {
+                        ca_certificate: dir.join("ca.pem"),
+                        ca_key: dir.join("ca.key"),
+                        subject: BTreeMap::from([("CN".to_string(), "builder".to_string())]),
+                        dns_names: Vec::new(),
+                        validity_secs: 600,
+                    }),
+                )]),
+            },
+        };
+        assert!(!BackendKind::Mint.is_reproducible());
+
+        let mint = || {
+            let content = backend
+                .provision(&secret("s3-client"), &derivation())
+                .expect("mint");
+            pem::parse_many(content.0).expect("parse PEM")
+        };
+        let first = mint();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0].tag(), "CERTIFICATE");
+        assert_eq!(first[1].tag(), "PRIVATE KEY");
+        assert_ne!(first[0].contents(), mint()[0].contents());
+
+        let (_, certificate) =
+            x509_parser::parse_x509_certificate(first[0].contents()).expect("parse certificate");
+        assert_eq!(certificate.issuer().to_string(), "CN=Build CA");
+        assert_eq!(
+            certificate.subject().to_string(),
+            "CN=builder, OU=/nix/store/aaaa-fetch-thing.drv"
+        );
+        assert_eq!(
+            certificate.validity().not_after.timestamp()
+                - certificate.validity().not_before.timestamp(),
+            900
+        );
+        assert!(matches!(
+            backend.provision(&secret("other"), &derivation()),
+            Err(BackendError::NotFound)
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn mints_signed_jwts() {
+        let dir = std::env::temp_dir().join(format!("mint-jwt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("generate key");
+        let public_key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .expect("parse key")
+            .public_key()
+            .as_ref()
+            .to_vec();
+        credential(
+            &dir.join("jwt.key"),
+            &pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref())),
+        );
+
+        let backend = Mint {
+            name: "mint".to_string(),
+            config: BackendConfig {
+                credentials: HashMap::from([(
+                    "api-token".to_string(),
+                    Credential::Jwt(JwtConfig {
+                        key: dir.join("jwt.key"),
+                        algorithm: Algorithm::EdDsa,
+                        key_id: Some("build".to_string()),
+                        claims: serde_json::json!({"aud": "api"})
+                            .as_object()
+                            .expect("object")
+                            .clone(),
+                        ttl_secs: 60,
+                    }),
+                )]),
+            },
+        };
+
+        let token = backend
+            .provision(&secret("api-token"), &derivation())
+            .expect("mint");
+        let token = String::from_utf8(token.0).expect("utf-8");
+        let (signing_input, signature) = token.rsplit_once('.').expect("signature");
+        UnparsedPublicKey::new(&ED25519, &public_key)
+            .verify(
+                signing_input.as_bytes(),
+                &URL_SAFE_NO_PAD.decode(signature).expect("base64"),
+            )
+            .expect("valid signature");
+
+        let decode = |part: &str| {
+            serde_json::from_slice::<serde_json::Value>(
+                &URL_SAFE_NO_PAD.decode(part).expect("base64"),
+            )
+            .expect("json")
+        };
+        let (header, claims) = signing_input.split_once('.').expect("claims");
+        let (header, claims) = (decode(header), decode(claims));
+        assert_eq!(header["alg"], "EdDSA");
+        assert_eq!(header["kid"], "build");
+        assert_eq!(claims["aud"], "api");
+        assert_eq!(claims["drv"], "/nix/store/aaaa-fetch-thing.drv");
+        assert_eq!(
+            claims["exp"].as_u64().expect("exp") - claims["iat"].as_u64().expect("iat"),
+            60
+        );
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn hs256_keys_are_raw_bytes() {
+        let key_file = std::env::temp_dir().join(format!("mint-hs256-{}", std::process::id()));
+        // Not UTF-8, and the trailing newline is part of the key
+        let key = [0xff, 0x00, 0x9c, b'\n'];
+        std::fs::write(&key_file, key).expect("write key");
+        std::fs::set_permissions(&key_file, std::fs::Permissions::from_mode(0o600)).expect("chmod");
+
+        let signature = Algorithm::Hs256
+            .sign(&key_file, b"header.claims")
+            .expect("sign");
+        std::fs::remove_file(key_file).expect("remove key");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("hmac");
+        mac.update(b"header.claims");
+        assert_eq!(signature, mac.finalize().into_bytes().to_vec());
+    }
+}
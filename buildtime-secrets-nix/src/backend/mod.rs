@@ -1,9 +1,33 @@
+pub mod age;
+pub mod aws;
+pub mod bitwarden;
+pub mod client;
+pub mod coprocess;
+pub mod derived;
 pub mod executable;
+pub mod generated;
+pub mod gpg;
+pub mod http;
+pub mod keepass;
+pub mod keyring;
+pub mod local;
+pub mod mint;
+pub mod nix_settings;
+pub mod onepassword;
+pub mod pass;
+pub mod pkcs11;
+pub mod process;
+pub mod quorum;
+pub mod sandbox;
 pub mod sops;
+pub mod systemd_creds;
+pub mod template;
+pub(crate) mod util;
+pub mod vault;
 
-use crate::error::Result;
+use crate::error::{BackendError, Result};
 use crate::secret::{Secret, SecretContent};
-use crate::{Config, Error};
+use crate::{Config, Error, Session};
 use serde::{Deserialize, Serialize};
 use tracing::debug;
 
@@ -11,7 +35,27 @@ use tracing::debug;
 #[serde(rename_all = "lowercase")]
 pub enum BackendKind {
     Sops,
+    #[serde(rename = "systemd-creds")]
+    SystemdCreds,
     Executable,
+    Age,
+    Gpg,
+    Pass,
+    Vault,
+    Http,
+    Aws,
+    KeePass,
+    Keyring,
+    Pkcs11,
+    OnePassword,
+    Bitwarden,
+    #[serde(rename = "nix-settings")]
+    NixSettings,
+    Local,
+    Generated,
+    Derived,
+    Mint,
+    Quorum,
 }
 
 impl std::fmt::Display for BackendKind {
@@ -20,33 +64,267 @@ impl std::fmt::Display for BackendKind {
     }
 }
 
+impl BackendKind {
+    /// Whether what this kind of backend provisions can be pinned
+    /// by the declared `Secret.hash`. Backends minting a fresh value
+    /// each time can't, and only provision secrets declared
+    /// ephemeral.
+    #[must_use]
+    pub fn is_reproducible(self) -> bool {
+        self != BackendKind::Mint
+    }
+}
+
+/// The derivation secrets are being provisioned for.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Serialize, Deserialize)]
+pub struct DerivationInfo {
+    pub path: String,
+    pub name: String,
+    /// Whether the derivation's output is pinned by a declared
+    /// hash.
+    #[serde(default)]
+    pub fixed_output: bool,
+}
+
 pub trait Backend<'a> {
-    fn provision(&self, secret: &Secret) -> Option<SecretContent>;
+    /// Provision `secret` for `derivation`, failing if this
+    /// backend can't produce it.
+    ///
+    /// # Errors
+    ///
+    /// If the backend doesn't have the secret or can't produce it.
+    fn provision(
+        &self,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> std::result::Result<SecretContent, BackendError>;
 }
 
-/// Instantiate a new `backend_kind` backend.
+/// Instantiate a new `backend_kind` backend, configured by
+/// `backend_config.<backend_name>`, for a hook run's `session`.
 ///
 /// # Errors
 ///
 /// If the corrosponding constructor fails.
 pub fn create<'a>(
     backend_kind: BackendKind,
+    backend_name: &str,
     config: &'a Config,
+    session: &'a Session,
 ) -> Result<Box<dyn Backend<'a> + 'a>> {
-    debug!("creating backend {backend_kind}");
+    debug!("creating backend {backend_name} ({backend_kind})");
     match backend_kind {
-        BackendKind::Sops => Ok(Box::new(sops::Sops::new(config)?)),
-        BackendKind::Executable => Ok(Box::new(executable::Executable::new(config)?)),
+        BackendKind::Sops => Ok(Box::new(sops::Sops::new(config, backend_name)?)),
+        BackendKind::SystemdCreds => Ok(Box::new(systemd_creds::SystemdCreds::new(
+            config,
+            backend_name,
+        )?)),
+        BackendKind::Executable => Ok(Box::new(executable::Executable::new(
+            config,
+            backend_name,
+            session,
+        )?)),
+        BackendKind::Age => Ok(Box::new(age::Age::new(config, backend_name)?)),
+        BackendKind::Gpg => Ok(Box::new(gpg::Gpg::new(config, backend_name)?)),
+        BackendKind::Pass => Ok(Box::new(pass::Pass::new(config, backend_name)?)),
+        BackendKind::Vault => Ok(Box::new(vault::Vault::new(config, backend_name, session)?)),
+        BackendKind::Http => Ok(Box::new(http::Http::new(config, backend_name)?)),
+        BackendKind::Aws => Ok(Box::new(aws::Aws::new(config, backend_name)?)),
+        BackendKind::KeePass => Ok(Box::new(keepass::KeePass::new(
+            config,
+            backend_name,
+            session,
+        )?)),
+        BackendKind::Keyring => Ok(Box::new(keyring::Keyring::new(config, backend_name)?)),
+        BackendKind::Pkcs11 => Ok(Box::new(pkcs11::Pkcs11::new(config, backend_name)?)),
+        BackendKind::OnePassword => Ok(Box::new(onepassword::OnePassword::new(
+            config,
+            backend_name,
+        )?)),
+        BackendKind::Bitwarden => Ok(Box::new(bitwarden::Bitwarden::new(config, backend_name)?)),
+        BackendKind::NixSettings => Ok(Box::new(nix_settings::NixSettings::new(
+            config,
+            backend_name,
+        )?)),
+        BackendKind::Local => Ok(Box::new(local::Local::new(config, backend_name)?)),
+        BackendKind::Generated => Ok(Box::new(generated::Generated::new(config, backend_name)?)),
+        BackendKind::Derived => Ok(Box::new(derived::Derived::new(
+            config,
+            backend_name,
+            session,
+        )?)),
+        BackendKind::Mint => Ok(Box::new(mint::Mint::new(config, backend_name)?)),
+        BackendKind::Quorum => Ok(Box::new(quorum::Quorum::new(
+            config,
+            backend_name,
+            session,
+        )?)),
     }
 }
 
-/// Ask a backend to validate `config` meets its
-/// requirements.
-pub fn validate_config(backend_kind: BackendKind, config: &Config) -> bool {
-    debug!("validating config for {backend_kind}");
+/// Ask a backend to validate `backend_config.<backend_name>`
+/// meets its requirements.
+pub fn validate_config(backend_kind: BackendKind, backend_name: &str, config: &Config) -> bool {
+    debug!("validating config for {backend_name} ({backend_kind})");
     match backend_kind {
-        BackendKind::Sops => sops::Sops::validate_config(config),
-        BackendKind::Executable => executable::Executable::validate_config(config),
+        BackendKind::Sops => sops::Sops::validate_config(config, backend_name),
+        BackendKind::SystemdCreds => {
+            systemd_creds::SystemdCreds::validate_config(config, backend_name)
+        }
+        BackendKind::Executable => executable::Executable::validate_config(config, backend_name),
+        BackendKind::Age => age::Age::validate_config(config, backend_name),
+        BackendKind::Gpg => gpg::Gpg::validate_config(config, backend_name),
+        BackendKind::Pass => pass::Pass::validate_config(config, backend_name),
+        BackendKind::Vault => vault::Vault::validate_config(config, backend_name),
+        BackendKind::Http => http::Http::validate_config(config, backend_name),
+        BackendKind::Aws => aws::Aws::validate_config(config, backend_name),
+        BackendKind::KeePass => keepass::KeePass::validate_config(config, backend_name),
+        BackendKind::Keyring => keyring::Keyring::validate_config(config, backend_name),
+        BackendKind::Pkcs11 => pkcs11::Pkcs11::validate_config(config, backend_name),
+        BackendKind::OnePassword => onepassword::OnePassword::validate_config(config, backend_name),
+        BackendKind::Bitwarden => bitwarden::Bitwarden::validate_config(config, backend_name),
+        BackendKind::NixSettings => {
+            nix_settings::NixSettings::validate_config(config, backend_name)
+        }
+        BackendKind::Local => local::Local::validate_config(config, backend_name),
+        BackendKind::Generated => generated::Generated::validate_config(config, backend_name),
+        BackendKind::Derived => derived::Derived::validate_config(config, backend_name),
+        BackendKind::Mint => mint::Mint::validate_config(config, backend_name),
+        BackendKind::Quorum => quorum::Quorum::validate_config(config, backend_name),
+    }
+}
+
+/// Enumerate the configured backend instances, ordered by
+/// kind then name.
+///
+/// Every key of `backend_config` names an instance. Its kind
+/// is read from the instances "kind" field, falling back to
+/// the key itself, so `backend_config.sops` is a sops backend.
+#[must_use]
+pub fn instances(config: &Config) -> Vec<(BackendKind, String)> {
+    let Some(backend_configs) = &config.backend_config else {
+        debug!("cant find \"backend_config\"");
+        return Vec::new();
+    };
+
+    let mut instances = backend_configs
+        .iter()
+        .filter_map(|(name, backend_config)| {
+            let kind = backend_config
+                .get("kind")
+                .cloned()
+                .unwrap_or_else(|| serde_json::Value::String(name.clone()));
+
+            match serde_json::from_value::<BackendKind>(kind) {
+                Ok(kind) => Some((kind, name.clone())),
+                Err(err) => {
+                    debug!("can't determine the kind of backend {name}: {err}");
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    instances.sort();
+    instances
+}
+
+/// Find the kind of backend instance `name`.
+#[must_use]
+pub fn instance_kind(config: &Config, name: &str) -> Option<BackendKind> {
+    instances(config)
+        .into_iter()
+        .find_map(|(kind, instance)| (instance == name).then_some(kind))
+}
+
+/// Whether the instance `name` may provision the secret
+/// `secret_name`, when asked by the instance `via` if any.
+///
+/// Secrets guarded by a quorum are only provisioned by a quorum
+/// guarding them, or by the instances such a quorum asks, so
+/// that no single instance can serve them on its own.
+#[must_use]
+pub fn is_allowed(config: &Config, via: Option<&str>, name: &str, secret_name: &str) -> bool {
+    let guards = |instance: &str| {
+        instance_kind(config, instance) == Some(BackendKind::Quorum)
+            && quorum::Quorum::guards(config, instance, secret_name)
+    };
+
+    let is_guarded = instances(config)
+        .iter()
+        .any(|(_, instance)| guards(instance));
+
+    !is_guarded || guards(name) || via.is_some_and(guards)
+}
+
+/// Enumerate the backend instances that may provision `secret`.
+#[must_use]
+pub fn instances_for(config: &Config, secret: &Secret) -> Vec<(BackendKind, String)> {
+    instances(config)
+        .into_iter()
+        .filter(|(_, name)| {
+            let is_allowed = is_allowed(config, None, name, &secret.name);
+            if !is_allowed {
+                debug!(
+                    "\"{}\" is guarded by a quorum, skipping {name}",
+                    secret.name
+                );
+            }
+            is_allowed
+        })
+        .collect()
+}
+
+/// Provision `secret` from the instance `name` on behalf of the
+/// instance `via`. Every secret a backend fetches from another
+/// goes through here, so the quorum guard can't be bypassed.
+///
+/// # Errors
+///
+/// If the instance doesn't exist, its dependencies can't be
+/// resolved, it isn't configured correctly, may not provision
+/// the secret, or fails to.
+pub(crate) fn fetch(
+    config: &Config,
+    session: &Session,
+    via: &str,
+    name: &str,
+    secret: &Secret,
+    derivation: &DerivationInfo,
+) -> std::result::Result<SecretContent, BackendError> {
+    let kind = instance_kind(config, name)
+        .ok_or_else(|| BackendError::Rejected(format!("no backend instance \"{name}\"")))?;
+
+    if !is_allowed(config, Some(via), name, &secret.name) {
+        return Err(BackendError::Rejected(format!(
+            "\"{}\" is guarded by a quorum",
+            secret.name
+        )));
+    }
+
+    let config = session
+        .resolve(name, derivation)
+        .map_err(BackendError::Rejected)?;
+
+    if !validate_config(kind, name, &config) {
+        return Err(BackendError::Rejected(format!(
+            "{name} isn't configured correctly"
+        )));
+    }
+
+    debug!("{via}: fetching \"{}\" from {name}", secret.name);
+    let backend = create(kind, name, &config, session)
+        .map_err(|err| BackendError::Rejected(err.to_string()))?;
+    backend.provision(secret, derivation)
+}
+
+/// The instances the instance `name` fetches secrets from
+/// itself, besides those its `dependencies` name.
+pub(crate) fn upstream(config: &Config, kind: BackendKind, name: &str) -> Vec<String> {
+    match kind {
+        BackendKind::Derived => derived::Derived::upstream(config, name),
+        BackendKind::Quorum => quorum::Quorum::upstream(config, name),
+        _ => Vec::new(),
     }
 }
 
@@ -86,15 +364,70 @@ pub fn get_backend_config<T: serde::de::DeserializeOwned>(
     Ok(parsed)
 }
 
+/// Provision a secret from the stdout of `cmd`, retrying
+/// transient failures with exponential backoff. If given,
+/// `input` is written to the commands stdin.
+///
+/// # Errors
+///
+/// If the command can't be sandboxed, can't be run, fails, or the
+/// breaker for `backend_name` is open.
 pub fn provision_with_cmd(
+    backend_name: &str,
     secret: &Secret,
     cmd: &mut std::process::Command,
-) -> Option<SecretContent> {
-    let decrypt_output = match cmd.output() {
+    config: &process::ProcessConfig,
+    input: Option<&[u8]>,
+) -> std::result::Result<SecretContent, BackendError> {
+    if let Some(sandbox_config) = &config.sandbox {
+        sandbox::apply(cmd, sandbox_config)?;
+    }
+
+    let mut backoff = std::time::Duration::from_millis(config.retry_backoff_ms);
+    let mut attempt = 0;
+
+    loop {
+        if process::breaker_is_open(backend_name, config) {
+            debug!("skipping backend {backend_name}, its breaker is open");
+            return Err(BackendError::CircuitOpen);
+        }
+
+        let result = run_cmd(cmd, config, input);
+        process::breaker_record(
+            backend_name,
+            config,
+            matches!(result, Err(BackendError::Timeout(_))),
+        );
+
+        match result {
+            Ok(stdout) => {
+                debug!("successfully decrypted secret {}", secret.name);
+                return Ok(SecretContent(stdout));
+            }
+            Err(err) if attempt < config.retries && config.is_transient(&err) => {
+                attempt += 1;
+                debug!(
+                    "transient failure from {backend_name}: {err}, retry {attempt} in {}ms",
+                    backoff.as_millis()
+                );
+                std::thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn run_cmd(
+    cmd: &mut std::process::Command,
+    config: &process::ProcessConfig,
+    input: Option<&[u8]>,
+) -> std::result::Result<Vec<u8>, BackendError> {
+    let decrypt_output = match process::run(cmd, config, input) {
         Ok(out) => out,
         Err(err) => {
-            debug!("failed to instantiate executable: {err}");
-            return None;
+            debug!("failed to run executable: {err}");
+            return Err(err);
         }
     };
 
@@ -103,10 +436,11 @@ pub fn provision_with_cmd(
         debug!("failed to decrypt secret with executable:");
         debug!("    stdout: {}", from_utf8_lossy(&decrypt_output.stdout));
         debug!("    stderr: {}", from_utf8_lossy(&decrypt_output.stderr));
-        return None;
+        return Err(BackendError::CommandFailed {
+            status: decrypt_output.status,
+            stderr: from_utf8_lossy(&decrypt_output.stderr).trim().to_string(),
+        });
     }
 
-    debug!("successfully decrypted secret {}", secret.name);
-
-    Some(crate::secret::SecretContent(decrypt_output.stdout))
+    Ok(decrypt_output.stdout)
 }
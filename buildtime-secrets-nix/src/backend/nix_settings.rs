@@ -0,0 +1,290 @@
+use crate::Config;
+use crate::backend::util::split_field;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::debug;
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// Read this netrc file rather than nix's `netrc-file`.
+    netrc_file: Option<PathBuf>,
+    /// The netrc machines that may be served, no others are.
+    #[serde(default)]
+    machines: Vec<String>,
+    /// The hosts whose access tokens may be served, no others are.
+    #[serde(default)]
+    hosts: Vec<String>,
+}
+
+/// This backend serves the credentials nix itself is configured
+/// with, so they needn't be kept in two places.
+///
+/// The secret `netrc/cache.example.com` is the password of the
+/// `machine cache.example.com` entry of nix's `netrc-file`, and
+/// `netrc/cache.example.com:login` its login. The secret
+/// `access-tokens/github.com` is the token nix's `access-tokens`
+/// holds for `github.com`.
+///
+/// Every build that declares a secret can read it, so only the
+/// `machines` and `hosts` the config lists are served.
+pub struct NixSettings {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Split a netrc file into tokens, leaving out macro definitions,
+/// which run until a blank line.
+fn netrc_tokens(netrc: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+
+    for line in netrc.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+
+        for token in line.split_whitespace() {
+            if token == "macdef" {
+                in_macro = true;
+                break;
+            }
+            tokens.push(token);
+        }
+    }
+
+    tokens
+}
+
+/// Find a field of the `machine` entry in a netrc file.
+fn netrc_lookup(netrc: &str, machine: &str, field: &str) -> Option<String> {
+    let mut tokens = netrc_tokens(netrc).into_iter();
+    let mut current = None;
+    let mut fields = HashMap::new();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                if current == Some(machine) {
+                    break;
+                }
+                fields.clear();
+                current = if token == "machine" {
+                    tokens.next()
+                } else {
+                    None
+                };
+            }
+            "login" | "password" | "account" => {
+                if let Some(value) = tokens.next() {
+                    fields.insert(token, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if current != Some(machine) {
+        return None;
+    }
+
+    fields.get(field).map(ToString::to_string)
+}
+
+impl NixSettings {
+    fn netrc(&self, machine: &str, field: &str) -> Result<SecretContent, BackendError> {
+        let netrc_file = match &self.config.netrc_file {
+            Some(netrc_file) => netrc_file.clone(),
+            None => libnixstore::netrc_file()
+                .map_err(|err| BackendError::Rejected(format!("can't read nix settings: {err}")))?,
+        };
+
+        let netrc = match std::fs::read_to_string(&netrc_file) {
+            Ok(netrc) => netrc,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!("no netrc at \"{}\"", netrc_file.display());
+                return Err(BackendError::NotFound);
+            }
+            Err(err) => {
+                return Err(BackendError::Rejected(format!(
+                    "can't read \"{}\": {err}",
+                    netrc_file.display()
+                )));
+            }
+        };
+
+        netrc_lookup(&netrc, machine, field)
+            .map(|value| SecretContent(value.into_bytes()))
+            .ok_or(BackendError::NotFound)
+    }
+}
+
+fn access_token(host: &str) -> Result<SecretContent, BackendError> {
+    let mut tokens = libnixstore::access_tokens()
+        .map_err(|err| BackendError::Rejected(format!("can't read nix settings: {err}")))?;
+
+    tokens
+        .remove(host)
+        .map(|token| SecretContent(token.into_bytes()))
+        .ok_or(BackendError::NotFound)
+}
+
+impl Backend<'_> for NixSettings {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        debug!("{}: looking up {}", self.name, secret.name);
+
+        let denied = |what: &str| {
+            Err(BackendError::Rejected(format!(
+                "{what} isn't allowed by {}",
+                self.name
+            )))
+        };
+
+        match secret.name.split_once('/') {
+            Some(("netrc", entry)) => {
+                let (machine, field) = split_field(entry);
+                if !self
+                    .config
+                    .machines
+                    .iter()
+                    .any(|allowed| allowed == machine)
+                {
+                    return denied(&format!("the netrc machine \"{machine}\""));
+                }
+                self.netrc(machine, field.unwrap_or("password"))
+            }
+            Some(("access-tokens", host)) => {
+                if !self.config.hosts.iter().any(|allowed| allowed == host) {
+                    return denied(&format!("the access token for \"{host}\""));
+                }
+                access_token(host)
+            }
+            _ => Err(BackendError::NotFound),
+        }
+    }
+}
+
+impl NixSettings {
+    /// Creates a new `NixSettings` backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(NixSettings {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a nix-settings backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, NixSettings, netrc_lookup};
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::secret;
+
+    const NETRC: &str = "\
+machine cache.example.com
+    login nix
+    password hunter2
+
+macdef init
+machine evil.example.com password leaked
+
+machine git.example.com login git password t0ken account ops
+default login anonymous password guest
+";
+
+    #[test]
+    fn netrc_entries_are_found() {
+        let lookup = |machine, field| netrc_lookup(NETRC, machine, field);
+
+        assert_eq!(
+            lookup("cache.example.com", "password").as_deref(),
+            Some("hunter2")
+        );
+        assert_eq!(lookup("cache.example.com", "login").as_deref(), Some("nix"));
+        assert_eq!(lookup("git.example.com", "account").as_deref(), Some("ops"));
+        assert_eq!(
+            lookup("git.example.com", "password").as_deref(),
+            Some("t0ken")
+        );
+        assert_eq!(lookup("cache.example.com", "account"), None);
+        // Neither macro bodies nor the default entry match
+        assert_eq!(lookup("evil.example.com", "password"), None);
+        assert_eq!(lookup("other.example.com", "password"), None);
+    }
+
+    #[test]
+    fn serves_netrc_file() {
+        let netrc_file = std::env::temp_dir().join(format!("netrc-{}", std::process::id()));
+        std::fs::write(&netrc_file, NETRC).expect("write netrc");
+
+        let backend = NixSettings {
+            name: "nix-settings".to_string(),
+            config: BackendConfig {
+                netrc_file: Some(netrc_file.clone()),
+                machines: vec![
+                    "cache.example.com".to_string(),
+                    "other.example.com".to_string(),
+                ],
+                hosts: Vec::new(),
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        let read = |name: &str| backend.provision(&secret(name), &derivation);
+        assert_eq!(
+            read("netrc/cache.example.com").expect("password").0,
+            b"hunter2"
+        );
+        assert_eq!(
+            read("netrc/cache.example.com:login").expect("login").0,
+            b"nix"
+        );
+        assert!(matches!(
+            read("netrc/other.example.com"),
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            read("cache.example.com"),
+            Err(BackendError::NotFound)
+        ));
+
+        // Entries that aren't listed are denied, whether or not
+        // nix has them
+        assert!(matches!(
+            read("netrc/git.example.com"),
+            Err(BackendError::Rejected(_))
+        ));
+        assert!(matches!(
+            read("access-tokens/github.com"),
+            Err(BackendError::Rejected(_))
+        ));
+
+        std::fs::remove_file(netrc_file).expect("remove netrc");
+    }
+}
@@ -0,0 +1,229 @@
+use crate::Config;
+use crate::backend::client;
+use crate::backend::process::ProcessConfig;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing::debug;
+
+fn default_program() -> PathBuf {
+    PathBuf::from("op")
+}
+
+fn default_field() -> String {
+    "password".to_string()
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The `op` executable.
+    #[serde(default = "default_program")]
+    program: PathBuf,
+    /// A file holding a service account token.
+    service_account_token_file: Option<PathBuf>,
+    /// A file holding a session token from `op signin --raw`,
+    /// used along with `account`.
+    session_file: Option<PathBuf>,
+    /// The account shorthand or ID the session belongs to.
+    account: Option<String>,
+    /// The vault of secret names that don't name one.
+    vault: Option<String>,
+    /// The field read when the secret name doesn't select one.
+    #[serde(default = "default_field")]
+    field: String,
+    #[serde(flatten)]
+    process: ProcessConfig,
+}
+
+/// This backend reads secrets with the 1Password CLI, signed in
+/// with a service account token or a session token.
+///
+/// The secret `Build/github/credential` is read with
+/// `op read op://Build/github/credential`. A name with two parts,
+/// `Build/github`, reads the configured `field` of the item, and
+/// one with a single part is looked up in the configured `vault`.
+pub struct OnePassword {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Turn a failed `op` into a clearer error.
+fn classify_failure(err: BackendError) -> BackendError {
+    let BackendError::CommandFailed { stderr, .. } = &err else {
+        return err;
+    };
+
+    if stderr.contains("isn't an item")
+        || stderr.contains("isn't a vault")
+        || stderr.contains("isn't a field")
+        || stderr.contains("could not find")
+    {
+        BackendError::NotFound
+    } else if stderr.contains("not currently signed in")
+        || stderr.contains("session expired")
+        || stderr.contains("invalid session token")
+        || stderr.contains("authentication")
+    {
+        BackendError::BadKey(format!("1Password is locked: {}", stderr.trim()))
+    } else {
+        err
+    }
+}
+
+impl OnePassword {
+    /// Build the `op://` reference for a secret name.
+    fn reference(&self, name: &str) -> Option<String> {
+        let parts = name.split('/').collect::<Vec<_>>();
+        if parts.iter().any(|part| part.is_empty()) {
+            return None;
+        }
+
+        let reference = match (parts.as_slice(), &self.config.vault) {
+            ([item], Some(vault)) => format!("{vault}/{item}/{}", self.config.field),
+            ([vault, item], _) => format!("{vault}/{item}/{}", self.config.field),
+            ([_, _, ..], _) => name.to_string(),
+            _ => return None,
+        };
+
+        Some(format!("op://{reference}"))
+    }
+}
+
+impl Backend<'_> for OnePassword {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let Some(reference) = self.reference(&secret.name) else {
+            return Err(BackendError::NotFound);
+        };
+
+        let mut cmd = std::process::Command::new(&self.config.program);
+        cmd.args(["read", "--no-newline"]).arg(&reference);
+        cmd.env_clear();
+
+        // Retain parent process' PATH and HOME, `op` keeps its
+        // config under HOME
+        cmd.envs(std::env::vars().filter(|(key, _)| key == "PATH" || key == "HOME"));
+
+        if let Some(token_file) = &self.config.service_account_token_file {
+            cmd.env(
+                "OP_SERVICE_ACCOUNT_TOKEN",
+                client::read_credential(token_file)?,
+            );
+        }
+
+        if let (Some(session_file), Some(account)) =
+            (&self.config.session_file, &self.config.account)
+        {
+            cmd.env(
+                format!("OP_SESSION_{account}"),
+                client::read_credential(session_file)?,
+            );
+            cmd.arg("--account").arg(account);
+        }
+
+        debug!("{}: reading {reference}", self.name);
+        crate::backend::provision_with_cmd(&self.name, secret, &mut cmd, &self.config.process, None)
+            .map_err(classify_failure)
+    }
+}
+
+impl OnePassword {
+    /// Creates a new `OnePassword` backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(OnePassword {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a onepassword backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        config.service_account_token_file.is_some()
+            || (config.session_file.is_some() && config.account.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, OnePassword};
+    use crate::backend::process::ProcessConfig;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::{credential, fake_cli, secret};
+
+    #[test]
+    fn reads_references() {
+        let dir = std::env::temp_dir().join(format!("op-{}", std::process::id()));
+        let program = fake_cli(
+            &dir,
+            "op",
+            r#"[ "$OP_SERVICE_ACCOUNT_TOKEN" = ops_t0ken ] || {
+    echo '[ERROR] You are not currently signed in.' >&2; exit 1; }
+case "$3" in
+    op://Build/github/password) printf hunter2 ;;
+    op://Build/github/token) printf ghp_t0ken ;;
+    *) echo "[ERROR] \"${3#op://}\" isn't an item in the vault." >&2; exit 1 ;;
+esac"#,
+        );
+        credential(&dir.join("token"), "ops_t0ken\n");
+        credential(&dir.join("wrong"), "ops_wrong\n");
+
+        let mut backend = OnePassword {
+            name: "onepassword".to_string(),
+            config: BackendConfig {
+                program,
+                service_account_token_file: Some(dir.join("token")),
+                session_file: None,
+                account: None,
+                vault: Some("Build".to_string()),
+                field: "password".to_string(),
+                process: ProcessConfig {
+                    retries: 0,
+                    ..ProcessConfig::default()
+                },
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        let read =
+            |backend: &OnePassword, name: &str| backend.provision(&secret(name), &derivation);
+        assert_eq!(read(&backend, "github").expect("item").0, b"hunter2");
+        assert_eq!(
+            read(&backend, "Build/github/token").expect("field").0,
+            b"ghp_t0ken"
+        );
+        assert!(matches!(
+            read(&backend, "Build/gitlab"),
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            read(&backend, "Build//token"),
+            Err(BackendError::NotFound)
+        ));
+
+        backend.config.service_account_token_file = Some(dir.join("wrong"));
+        assert!(matches!(
+            read(&backend, "github"),
+            Err(BackendError::BadKey(_))
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
@@ -0,0 +1,196 @@
+use crate::Config;
+use crate::backend::gpg::{self, GpgOptions};
+use crate::backend::util::split_field;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+/// What part of an entry is provisioned when the secret
+/// name doesn't select a field.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Extract {
+    /// Only the first line, the password.
+    #[default]
+    Password,
+    /// The whole decrypted entry.
+    Entry,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The root of the password store.
+    store_dir: PathBuf,
+    #[serde(flatten)]
+    gpg: GpgOptions,
+    #[serde(default)]
+    extract: Extract,
+}
+
+/// This backend reads secrets from a
+/// [password-store](https://www.passwordstore.org) tree.
+///
+/// The secret `web/github` is read from `<store_dir>/web/github.gpg`.
+/// By default only the first line, the password, is provisioned.
+/// A `:<field>` suffix instead selects a `field: value` line from
+/// the rest of the entry, so `web/github:login` provisions the
+/// value of the `login:` line.
+pub struct Pass {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Pick the first line of an entry, or the value of one of its
+/// `field: value` lines.
+fn extract_field(entry: &[u8], field: Option<&str>) -> Option<Vec<u8>> {
+    let entry = String::from_utf8_lossy(entry);
+    let mut lines = entry.lines();
+    let password = lines.next().unwrap_or_default();
+
+    match field {
+        None | Some("password") => Some(password.as_bytes().to_vec()),
+        Some(field) => lines.find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == field).then(|| value.trim().as_bytes().to_vec())
+        }),
+    }
+}
+
+impl Pass {
+    /// Find the entry file for `entry`, refusing names that would
+    /// escape the store.
+    fn entry_file(&self, entry: &str) -> Option<PathBuf> {
+        let relative = Path::new(entry);
+
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+
+        Some(self.config.store_dir.join(format!("{entry}.gpg")))
+    }
+}
+
+impl Backend<'_> for Pass {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let (entry, field) = split_field(&secret.name);
+
+        let Some(path) = self.entry_file(entry) else {
+            return Err(BackendError::NotFound);
+        };
+
+        let decrypted = gpg::decrypt_file(&self.name, &self.config.gpg, secret, &path)?;
+
+        if field.is_none() && self.config.extract == Extract::Entry {
+            return Ok(decrypted);
+        }
+
+        extract_field(decrypted.as_ref(), field)
+            .map(SecretContent)
+            .ok_or(BackendError::NotFound)
+    }
+}
+
+impl Pass {
+    /// Creates a new Pass backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Pass {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a pass backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, Extract, Pass, extract_field};
+    use crate::backend::gpg::GpgOptions;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::{GpgKeyring, secret};
+
+    const ENTRY: &[u8] = b"hunter2\nlogin: octocat\nurl: https://github.com\n";
+
+    #[test]
+    fn name_suffix_selects_field() {
+        assert_eq!(extract_field(ENTRY, None).as_deref(), Some(&b"hunter2"[..]));
+        assert_eq!(
+            extract_field(ENTRY, Some("login")).as_deref(),
+            Some(&b"octocat"[..])
+        );
+        assert_eq!(
+            extract_field(ENTRY, Some("url")).as_deref(),
+            Some(&b"https://github.com"[..])
+        );
+        assert_eq!(extract_field(ENTRY, Some("email")), None);
+    }
+
+    #[test]
+    fn reads_store_entries() {
+        let keyring = GpgKeyring::new("pass");
+        let store_dir = keyring.0.join("store");
+        std::fs::create_dir_all(store_dir.join("web")).expect("create store");
+        keyring.encrypt(ENTRY, &store_dir.join("web/github.gpg"));
+
+        let mut backend = Pass {
+            name: "pass".to_string(),
+            config: BackendConfig {
+                store_dir,
+                gpg: GpgOptions {
+                    gnupg_home: keyring.0.clone(),
+                    ..GpgOptions::default()
+                },
+                extract: Extract::Password,
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        let password = backend
+            .provision(&secret("web/github"), &derivation)
+            .expect("password");
+        assert_eq!(password.0, b"hunter2");
+
+        let login = backend
+            .provision(&secret("web/github:login"), &derivation)
+            .expect("login");
+        assert_eq!(login.0, b"octocat");
+
+        assert!(matches!(
+            backend.provision(&secret("../web/github"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+
+        backend.config.extract = Extract::Entry;
+        let entry = backend
+            .provision(&secret("web/github"), &derivation)
+            .expect("entry");
+        assert_eq!(entry.0, ENTRY);
+    }
+}
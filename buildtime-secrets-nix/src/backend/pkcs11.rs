@@ -0,0 +1,383 @@
+use crate::Config;
+use crate::backend::client;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use cryptoki::context::{CInitializeArgs, CInitializeFlags, Pkcs11 as Context};
+use cryptoki::error::{Error as Pkcs11Error, RvError};
+use cryptoki::object::{Attribute, AttributeType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::slot::Slot;
+use cryptoki::types::AuthPin;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use tracing::{debug, warn};
+
+/// Modules already loaded and initialized by this process, keyed
+/// by path, as a module can only be initialized once.
+static CONTEXTS: LazyLock<Mutex<HashMap<PathBuf, Context>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The PKCS#11 module, e.g. `libsofthsm2.so`.
+    module: PathBuf,
+    /// The label of the token holding the secrets.
+    token_label: Option<String>,
+    /// The slot of the token. A config can't select the token by
+    /// both label and slot, the first initialized token is used
+    /// when it selects it by neither.
+    slot: Option<u64>,
+    /// A file holding the user PIN.
+    pin_file: PathBuf,
+    /// Prepended to the secret name to form the object label.
+    #[serde(default)]
+    prefix: String,
+}
+
+/// This backend reads secrets from a PKCS#11 token, such as an
+/// HSM or a smart card, logging in with the user PIN.
+///
+/// The secret `github` is the value of the data object labelled
+/// `github`. A secret key with that label is read instead when
+/// there's no such object, provided the token lets it be
+/// extracted.
+pub struct Pkcs11 {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Load and initialize a module, or reuse it if already loaded.
+fn context(module: &Path) -> Result<Context, BackendError> {
+    let mut contexts = CONTEXTS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+    if let Some(context) = contexts.get(module) {
+        return Ok(context.clone());
+    }
+
+    let context = Context::new(module).map_err(|err| {
+        BackendError::Rejected(format!("can't load \"{}\": {err}", module.display()))
+    })?;
+
+    match context.initialize(CInitializeArgs::new(CInitializeFlags::OS_LOCKING_OK)) {
+        Ok(()) | Err(Pkcs11Error::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => {}
+        Err(err) => return Err(to_error(err)),
+    }
+
+    contexts.insert(module.to_path_buf(), context.clone());
+    Ok(context)
+}
+
+/// Map a PKCS#11 failure to a backend error.
+fn to_error(err: Pkcs11Error) -> BackendError {
+    match err {
+        Pkcs11Error::Pkcs11(
+            RvError::PinIncorrect
+            | RvError::PinInvalid
+            | RvError::PinLenRange
+            | RvError::PinExpired
+            | RvError::PinLocked
+            | RvError::UserPinNotInitialized,
+            _,
+        ) => BackendError::BadKey(err.to_string()),
+        err => BackendError::Rejected(err.to_string()),
+    }
+}
+
+impl Pkcs11 {
+    /// Find the slot of the configured token.
+    fn slot(&self, context: &Context) -> Result<Slot, BackendError> {
+        let slots = context
+            .get_slots_with_initialized_token()
+            .map_err(to_error)?;
+
+        if let Some(label) = &self.config.token_label {
+            for slot in slots {
+                let info = context.get_token_info(slot).map_err(to_error)?;
+                if info.label() == label {
+                    return Ok(slot);
+                }
+            }
+
+            return Err(BackendError::Rejected(format!(
+                "no token labelled \"{label}\""
+            )));
+        }
+
+        match self.config.slot {
+            Some(id) => slots.into_iter().find(|slot| slot.id() == id),
+            None => slots.into_iter().next(),
+        }
+        .ok_or_else(|| BackendError::Rejected("no initialized token".to_string()))
+    }
+
+    fn session(&self) -> Result<Session, BackendError> {
+        let context = context(&self.config.module)?;
+        let slot = self.slot(&context)?;
+        let pin = client::read_credential(&self.config.pin_file)?;
+
+        let session = context.open_ro_session(slot).map_err(to_error)?;
+        match session.login(UserType::User, Some(&AuthPin::new(pin.into()))) {
+            // Logins are shared by every session of the token
+            Ok(()) | Err(Pkcs11Error::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => Ok(session),
+            Err(err) => Err(to_error(err)),
+        }
+    }
+}
+
+/// Find the object of `class` labelled `label`.
+fn find(
+    session: &Session,
+    class: ObjectClass,
+    label: &str,
+) -> Result<Option<ObjectHandle>, BackendError> {
+    let template = [
+        Attribute::Class(class),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ];
+
+    let mut objects = session.find_objects(&template).map_err(to_error)?;
+    if objects.len() > 1 {
+        return Err(BackendError::Rejected(format!(
+            "{} objects are labelled \"{label}\"",
+            objects.len()
+        )));
+    }
+
+    Ok(objects.pop())
+}
+
+impl Backend<'_> for Pkcs11 {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let label = format!("{}{}", self.config.prefix, secret.name);
+        let session = self.session()?;
+
+        debug!("{}: looking up \"{label}\"", self.name);
+        let object = match find(&session, ObjectClass::DATA, &label)? {
+            Some(object) => object,
+            None => {
+                find(&session, ObjectClass::SECRET_KEY, &label)?.ok_or(BackendError::NotFound)?
+            }
+        };
+
+        // Sensitive values are left out rather than failing the call
+        session
+            .get_attributes(object, &[AttributeType::Value])
+            .map_err(to_error)?
+            .into_iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Value(value) => Some(SecretContent(value)),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                BackendError::Rejected(format!("the object \"{label}\" can't be extracted"))
+            })
+    }
+}
+
+impl Pkcs11 {
+    /// Creates a new Pkcs11 backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Pkcs11 {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a pkcs11 backend config, rejecting one that selects
+    /// its token by both label and slot.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        if config.token_label.is_some() && config.slot.is_some() {
+            warn!("{name}: set either token_label or slot, not both");
+            return false;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, Pkcs11, context};
+    use crate::Config;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::secret;
+    use cryptoki::object::{Attribute, KeyType, ObjectClass};
+    use cryptoki::session::UserType;
+    use cryptoki::types::AuthPin;
+    use std::collections::HashMap;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    /// Find `SoftHSM`, `$SOFTHSM2_MODULE` or a usual install path.
+    fn softhsm() -> Option<PathBuf> {
+        std::env::var_os("SOFTHSM2_MODULE")
+            .map(PathBuf::from)
+            .into_iter()
+            .chain(
+                [
+                    "/usr/lib/softhsm/libsofthsm2.so",
+                    "/usr/lib64/softhsm/libsofthsm2.so",
+                    "/usr/local/lib/softhsm/libsofthsm2.so",
+                    "/run/current-system/sw/lib/softhsm/libsofthsm2.so",
+                ]
+                .map(PathBuf::from),
+            )
+            .find(|path| path.exists())
+    }
+
+    #[test]
+    fn token_is_selected_by_label_or_slot() {
+        let valid = |token: serde_json::Value| {
+            let mut backend = serde_json::json!({"module": "libsofthsm2.so", "pin_file": "/pin"});
+            backend
+                .as_object_mut()
+                .expect("object")
+                .extend(token.as_object().expect("object").clone());
+            let config = Config {
+                backend_config: Some(HashMap::from([("pkcs11".to_string(), backend)])),
+                ..Config::default()
+            };
+            Pkcs11::validate_config(&config, "pkcs11")
+        };
+
+        assert!(valid(serde_json::json!({})));
+        assert!(valid(serde_json::json!({"token_label": "ci"})));
+        assert!(valid(serde_json::json!({"slot": 1})));
+        assert!(!valid(serde_json::json!({"token_label": "ci", "slot": 1})));
+    }
+
+    /// The name of [`reads_data_objects_from_softhsm`] as the test
+    /// harness knows it.
+    const SOFTHSM_TEST: &str = "backend::pkcs11::tests::reads_data_objects_from_softhsm";
+
+    #[test]
+    #[ignore = "needs SoftHSM, which the devShell provides"]
+    fn reads_data_objects_from_softhsm() {
+        let module = softhsm().expect("SoftHSM isn't installed, set SOFTHSM2_MODULE");
+
+        // SoftHSM only reads its config path from the environment, so
+        // the test reruns itself with SOFTHSM2_CONF set on the child
+        // rather than changing the environment of this process
+        let Some(dir) = std::env::var_os("PKCS11_TEST_DIR").map(PathBuf::from) else {
+            let dir = std::env::temp_dir().join(format!("pkcs11-{}", std::process::id()));
+            std::fs::create_dir_all(dir.join("tokens")).expect("create dir");
+            std::fs::write(
+                dir.join("softhsm2.conf"),
+                format!("directories.tokendir = {}\n", dir.join("tokens").display()),
+            )
+            .expect("write softhsm config");
+
+            let status = Command::new(std::env::current_exe().expect("test binary"))
+                .args([SOFTHSM_TEST, "--exact", "--ignored", "--nocapture"])
+                .env("SOFTHSM2_CONF", dir.join("softhsm2.conf"))
+                .env("PKCS11_TEST_DIR", &dir)
+                .status()
+                .expect("rerun test");
+
+            std::fs::remove_dir_all(dir).expect("remove dir");
+            assert!(status.success());
+            return;
+        };
+
+        // Set up a token with a data object and a sensitive key
+        let hsm = context(&module).expect("load softhsm");
+        let slot = hsm.get_all_slots().expect("slots")[0];
+        let so_pin = AuthPin::new("12345678".into());
+        let user_pin = AuthPin::new("1234".into());
+        hsm.init_token(slot, &so_pin, "buildtime-secrets")
+            .expect("init token");
+        {
+            let session = hsm.open_rw_session(slot).expect("session");
+            session
+                .login(UserType::So, Some(&so_pin))
+                .expect("so login");
+            session.init_pin(&user_pin).expect("init pin");
+            session.logout().expect("logout");
+
+            session
+                .login(UserType::User, Some(&user_pin))
+                .expect("login");
+            session
+                .create_object(&[
+                    Attribute::Class(ObjectClass::DATA),
+                    Attribute::Token(true),
+                    Attribute::Private(true),
+                    Attribute::Label(b"build/github".to_vec()),
+                    Attribute::Value(b"hunter2".to_vec()),
+                ])
+                .expect("create data object");
+            session
+                .create_object(&[
+                    Attribute::Class(ObjectClass::SECRET_KEY),
+                    Attribute::KeyType(KeyType::GENERIC_SECRET),
+                    Attribute::Token(true),
+                    Attribute::Sensitive(true),
+                    Attribute::Extractable(false),
+                    Attribute::Label(b"build/signing".to_vec()),
+                    Attribute::Value(vec![0; 32]),
+                ])
+                .expect("create key");
+        }
+
+        std::fs::write(dir.join("pin"), "1234\n").expect("write pin");
+        std::fs::write(dir.join("wrong"), "4321\n").expect("write pin");
+        for pin in ["pin", "wrong"] {
+            std::fs::set_permissions(dir.join(pin), std::fs::Permissions::from_mode(0o600))
+                .expect("chmod");
+        }
+
+        let mut backend = Pkcs11 {
+            name: "pkcs11".to_string(),
+            config: BackendConfig {
+                module,
+                token_label: Some("buildtime-secrets".to_string()),
+                slot: None,
+                pin_file: dir.join("wrong"),
+                prefix: "build/".to_string(),
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        assert!(matches!(
+            backend.provision(&secret("github"), &derivation),
+            Err(BackendError::BadKey(_))
+        ));
+
+        backend.config.pin_file = dir.join("pin");
+        let content = backend
+            .provision(&secret("github"), &derivation)
+            .expect("data object");
+        assert_eq!(content.0, b"hunter2");
+        assert!(matches!(
+            backend.provision(&secret("gitlab"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            backend.provision(&secret("signing"), &derivation),
+            Err(BackendError::Rejected(_))
+        ));
+    }
+}
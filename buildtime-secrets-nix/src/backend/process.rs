@@ -0,0 +1,420 @@
+use crate::backend::sandbox::SandboxConfig;
+use crate::error::BackendError;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Limits applied to every process a backend spawns.
+///
+/// Flattened into the configuration of backends that
+/// shell out, e.g. `backend_config.sops.timeout_secs`.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessConfig {
+    /// Seconds before the process group is killed, `null`
+    /// disables the timeout.
+    pub timeout_secs: Option<u64>,
+    /// The largest secret, in bytes, read from stdout.
+    pub max_output_bytes: u64,
+    /// How many times a transient failure is retried.
+    pub retries: u32,
+    /// The delay before the first retry, doubled for each
+    /// subsequent retry.
+    pub retry_backoff_ms: u64,
+    /// Exit codes that indicate a transient failure, defaults
+    /// to `EX_TEMPFAIL`.
+    pub transient_exit_codes: Vec<i32>,
+    /// Whether a timeout is retried. Off by default, as each
+    /// retry waits out the whole timeout again.
+    pub retry_timeouts: bool,
+    /// Consecutive timeouts before the backend is skipped.
+    pub breaker_threshold: u32,
+    /// Seconds a tripped backend is skipped for.
+    pub breaker_cooldown_secs: u64,
+    /// Where breakers are kept between hook runs, so a backend
+    /// keeps being skipped across builds.
+    pub breaker_dir: PathBuf,
+    /// Run the process unprivileged and sandboxed.
+    pub sandbox: Option<SandboxConfig>,
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: Some(60),
+            max_output_bytes: 1024 * 1024,
+            retries: 2,
+            retry_backoff_ms: 250,
+            transient_exit_codes: vec![75],
+            retry_timeouts: false,
+            breaker_threshold: 3,
+            breaker_cooldown_secs: 60,
+            breaker_dir: PathBuf::from("/run/buildtime-secrets/breakers"),
+            sandbox: None,
+        }
+    }
+}
+
+impl ProcessConfig {
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Whether a failed attempt is worth retrying.
+    #[must_use]
+    pub fn is_transient(&self, err: &BackendError) -> bool {
+        match err {
+            BackendError::Timeout(_) => self.retry_timeouts,
+            BackendError::CommandFailed { status, .. } => status
+                .code()
+                .is_some_and(|code| self.transient_exit_codes.contains(&code)),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct BreakerState {
+    consecutive_timeouts: u32,
+    /// Unix time until which the backend is skipped.
+    open_until: Option<u64>,
+}
+
+// Serializes updates from the threads of a single hook run,
+// concurrent hook runs may lose an update to each other
+static BREAKER_LOCK: Mutex<()> = Mutex::new(());
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// The file the breaker for `backend_name` is kept in.
+fn breaker_path(backend_name: &str, config: &ProcessConfig) -> Option<PathBuf> {
+    let mut components = Path::new(backend_name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Some(config.breaker_dir.join(backend_name)),
+        _ => None,
+    }
+}
+
+fn load_breaker(path: &Path) -> BreakerState {
+    std::fs::read(path)
+        .ok()
+        .and_then(|state| serde_json::from_slice(&state).ok())
+        .unwrap_or_default()
+}
+
+fn store_breaker(path: &Path, state: &BreakerState) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+
+    // Renamed into place, so a concurrent hook run never reads
+    // a partial write
+    let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&partial, serde_json::to_vec(state)?)?;
+    std::fs::rename(partial, path)
+}
+
+/// Check whether the breaker for `backend_name` is open,
+/// skipping the backend.
+#[must_use]
+pub fn breaker_is_open(backend_name: &str, config: &ProcessConfig) -> bool {
+    let Some(path) = breaker_path(backend_name, config) else {
+        return false;
+    };
+
+    load_breaker(&path)
+        .open_until
+        .is_some_and(|open_until| unix_now() < open_until)
+}
+
+/// Record the outcome of an attempt, tripping the breaker for
+/// `backend_name` after too many consecutive timeouts.
+pub fn breaker_record(backend_name: &str, config: &ProcessConfig, timed_out: bool) {
+    let Some(path) = breaker_path(backend_name, config) else {
+        return;
+    };
+
+    let _guard = BREAKER_LOCK
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+    if !timed_out {
+        if path.exists()
+            && let Err(err) = std::fs::remove_file(&path)
+        {
+            warn!("can't reset the breaker for backend {backend_name}: {err}");
+        }
+        return;
+    }
+
+    let mut state = load_breaker(&path);
+    state.consecutive_timeouts += 1;
+
+    if state.consecutive_timeouts >= config.breaker_threshold {
+        warn!(
+            "backend {backend_name} timed out {} times in a row, skipping it for {}s",
+            state.consecutive_timeouts, config.breaker_cooldown_secs
+        );
+        state.open_until = Some(unix_now() + config.breaker_cooldown_secs);
+    }
+
+    if let Err(err) = store_breaker(&path, &state) {
+        warn!("can't store the breaker for backend {backend_name}: {err}");
+    }
+}
+
+pub(crate) fn kill_process_group(pgid: u32) {
+    let Ok(pgid) = libc::pid_t::try_from(pgid) else {
+        return;
+    };
+
+    // SAFETY: kill has no memory safety requirements, the negated
+    // pid addresses the process group we created for the child
+    unsafe {
+        libc::kill(-pgid, libc::SIGKILL);
+    }
+}
+
+fn read_limited<R: Read>(mut reader: R, limit: u64, exceeded: &AtomicBool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                buf.extend_from_slice(&chunk[..read]);
+                if buf.len() as u64 > limit {
+                    exceeded.store(true, Ordering::SeqCst);
+                    break;
+                }
+            }
+        }
+    }
+
+    buf
+}
+
+/// The output of a process that exited on its own.
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Run `cmd` once in its own process group, enforcing the
+/// timeout and output limits of `config`. If given, `input`
+/// is written to stdin, otherwise stdin is null.
+///
+/// # Errors
+///
+/// If the process can't be spawned, runs for too long or writes
+/// too much. The whole process group is killed in the latter cases.
+pub fn run(
+    cmd: &mut Command,
+    config: &ProcessConfig,
+    input: Option<&[u8]>,
+) -> Result<Output, BackendError> {
+    cmd.process_group(0)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = cmd.spawn().map_err(BackendError::Spawn)?;
+    let pgid = child.id();
+    let started = Instant::now();
+
+    // Written from a thread so a process that doesn't read its
+    // stdin can't block us past the timeout
+    if let Some(mut stdin) = child.stdin.take()
+        && let Some(input) = input
+    {
+        let input = input.to_vec();
+        std::thread::spawn(move || stdin.write_all(&input));
+    }
+
+    let exceeded = Arc::new(AtomicBool::new(false));
+
+    let stdout_reader = child.stdout.take().map(|stdout| {
+        let exceeded = Arc::clone(&exceeded);
+        let limit = config.max_output_bytes;
+        std::thread::spawn(move || read_limited(stdout, limit, &exceeded))
+    });
+
+    // Stderr is only logged, so it is capped at the same size
+    let stderr_reader = child.stderr.take().map(|stderr| {
+        let limit = config.max_output_bytes;
+        std::thread::spawn(move || read_limited(stderr, limit, &AtomicBool::new(false)))
+    });
+
+    let readers_done = || {
+        stdout_reader
+            .as_ref()
+            .is_none_or(std::thread::JoinHandle::is_finished)
+            && stderr_reader
+                .as_ref()
+                .is_none_or(std::thread::JoinHandle::is_finished)
+    };
+
+    // The leader isn't reaped until its pipes are closed, so that
+    // anything it left behind holding them can be killed with the
+    // group while the group id can't yet be reused
+    let status = loop {
+        if exceeded.load(Ordering::SeqCst) {
+            break Err(BackendError::OutputTooLarge(config.max_output_bytes));
+        }
+
+        if readers_done() {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => {}
+                Err(err) => break Err(BackendError::Spawn(err)),
+            }
+        }
+
+        if let Some(timeout) = config.timeout()
+            && started.elapsed() >= timeout
+        {
+            break Err(BackendError::Timeout(timeout));
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    if status.is_err() {
+        kill_process_group(pgid);
+        debug!("killed process group {pgid}");
+        let _ = child.wait();
+    }
+
+    let stdout = stdout_reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    let stderr = stderr_reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+
+    if exceeded.load(Ordering::SeqCst) {
+        return Err(BackendError::OutputTooLarge(config.max_output_bytes));
+    }
+
+    Ok(Output {
+        status: status?,
+        stdout,
+        stderr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProcessConfig, breaker_is_open, breaker_record, run};
+    use crate::error::BackendError;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    fn sh(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    #[test]
+    fn captures_stdout() {
+        let output = run(&mut sh("printf meow"), &ProcessConfig::default(), None).expect("run");
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"meow");
+    }
+
+    #[test]
+    fn writes_input_to_stdin() {
+        let output = run(&mut sh("cat"), &ProcessConfig::default(), Some(b"purr")).expect("run");
+
+        assert_eq!(output.stdout, b"purr");
+    }
+
+    #[test]
+    fn timeout_kills_process_group() {
+        let config = ProcessConfig {
+            timeout_secs: Some(1),
+            ..ProcessConfig::default()
+        };
+        let started = Instant::now();
+
+        // The background sleep holds stdout open, it must be killed too
+        let result = run(&mut sh("sleep 30 & sleep 30"), &config, None);
+
+        assert!(matches!(result, Err(BackendError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn timeouts_are_only_retried_when_configured() {
+        let timeout = BackendError::Timeout(Duration::from_secs(1));
+
+        assert!(!ProcessConfig::default().is_transient(&timeout));
+        assert!(
+            ProcessConfig {
+                retry_timeouts: true,
+                ..ProcessConfig::default()
+            }
+            .is_transient(&timeout)
+        );
+    }
+
+    #[test]
+    fn breaker_outlives_the_hook_run() {
+        let config = ProcessConfig {
+            breaker_threshold: 2,
+            breaker_dir: std::env::temp_dir().join(format!("breakers-{}", std::process::id())),
+            ..ProcessConfig::default()
+        };
+
+        breaker_record("slow", &config, true);
+        assert!(!breaker_is_open("slow", &config));
+        breaker_record("slow", &config, true);
+        assert!(breaker_is_open("slow", &config));
+
+        // Kept on disk for the next hook run
+        assert!(config.breaker_dir.join("slow").exists());
+
+        breaker_record("slow", &config, false);
+        assert!(!breaker_is_open("slow", &config));
+
+        std::fs::remove_dir_all(&config.breaker_dir).expect("remove dir");
+    }
+
+    #[test]
+    fn output_over_limit_is_rejected() {
+        let config = ProcessConfig {
+            max_output_bytes: 16,
+            ..ProcessConfig::default()
+        };
+
+        let result = run(&mut sh("yes"), &config, None);
+
+        assert!(matches!(result, Err(BackendError::OutputTooLarge(16))));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::backend::{Backend, BackendKind, DerivationInfo, instance_kind};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use crate::{Config, Session};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The backend instances cross-checked, tried in order.
+    instances: Vec<String>,
+    /// How many instances must provision the secret, all of them
+    /// if unset.
+    threshold: Option<usize>,
+    /// The secrets this quorum guards, which no instance may
+    /// provision on its own.
+    secrets: Vec<String>,
+}
+
+/// This backend provisions a secret only when enough of its
+/// instances agree on it byte for byte, which catches a stale
+/// replica or a tampered source.
+///
+/// Instances are tried in order until `threshold` of them have
+/// provisioned the secret. When they disagree, the instances are
+/// reported grouped by the value they provisioned, without the
+/// values themselves.
+pub struct Quorum<'a> {
+    name: String,
+    root_config: &'a Config,
+    config: BackendConfig,
+    session: &'a Session,
+}
+
+impl Quorum<'_> {
+    /// Whether the quorum instance `name` guards the secret
+    /// `secret_name`.
+    pub(crate) fn guards(root_config: &Config, name: &str, secret_name: &str) -> bool {
+        crate::backend::get_backend_config::<BackendConfig>(root_config, name)
+            .is_ok_and(|config| config.secrets.iter().any(|secret| secret == secret_name))
+    }
+
+    /// The instances the quorum instance `name` cross-checks.
+    pub(crate) fn upstream(root_config: &Config, name: &str) -> Vec<String> {
+        crate::backend::get_backend_config::<BackendConfig>(root_config, name)
+            .map(|config| config.instances)
+            .unwrap_or_default()
+    }
+
+    fn threshold(&self) -> usize {
+        self.config.threshold.unwrap_or(self.config.instances.len())
+    }
+
+    fn provision_from(
+        &self,
+        instance: &str,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let kind = instance_kind(self.root_config, instance)
+            .ok_or_else(|| BackendError::Rejected(format!("no backend instance \"{instance}\"")))?;
+
+        if kind == BackendKind::Quorum {
+            return Err(BackendError::Rejected(
+                "a quorum can't include another quorum".to_string(),
+            ));
+        }
+
+        crate::backend::fetch(
+            self.root_config,
+            self.session,
+            &self.name,
+            instance,
+            secret,
+            derivation,
+        )
+    }
+}
+
+impl Backend<'_> for Quorum<'_> {
+    fn provision(
+        &self,
+        secret: &Secret,
+        derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        if !self.config.secrets.contains(&secret.name) {
+            return Err(BackendError::NotFound);
+        }
+
+        let threshold = self.threshold();
+        let mut values = Vec::<(SecretContent, Vec<String>)>::new();
+        let mut provisioned = 0;
+        let mut failures = Vec::new();
+
+        for instance in &self.config.instances {
+            if provisioned == threshold {
+                break;
+            }
+
+            match self.provision_from(instance, secret, derivation) {
+                Ok(content) => {
+                    provisioned += 1;
+                    match values.iter_mut().find(|(value, _)| *value == content) {
+                        Some((_, group)) => group.push(instance.clone()),
+                        None => values.push((content, vec![instance.clone()])),
+                    }
+                }
+                Err(BackendError::NotFound) => {
+                    debug!("{}: {instance} doesn't have \"{}\"", self.name, secret.name);
+                }
+                Err(err) => {
+                    debug!("{}: {instance} failed: {err}", self.name);
+                    failures.push(format!("{instance}: {err}"));
+                }
+            }
+        }
+
+        if values.len() > 1 {
+            let groups = values
+                .into_iter()
+                .map(|(_, group)| group)
+                .collect::<Vec<_>>();
+            warn!(
+                "{}: instances disagree on \"{}\": {groups:?}",
+                self.name, secret.name
+            );
+            return Err(BackendError::Disagreement(groups));
+        }
+
+        if provisioned < threshold {
+            if provisioned == 0 && failures.is_empty() {
+                return Err(BackendError::NotFound);
+            }
+
+            let mut msg = format!("only {provisioned} of the {threshold} instances needed agree");
+            if !failures.is_empty() {
+                msg = format!("{msg} ({})", failures.join("; "));
+            }
+            return Err(BackendError::Rejected(msg));
+        }
+
+        Ok(values.remove(0).0)
+    }
+}
+
+impl<'a> Quorum<'a> {
+    /// Creates a new Quorum backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &'a Config, name: &str, session: &'a Session) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Quorum {
+            name: name.to_string(),
+            root_config,
+            config,
+            session,
+        })
+    }
+
+    /// Validate a quorum backend config. Its instances must each
+    /// be able to reproduce a secret for their values to agree.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(config) = parse_result else {
+            return false;
+        };
+
+        let threshold = config.threshold.unwrap_or(config.instances.len());
+
+        (1..=config.instances.len()).contains(&threshold)
+            && config.instances.iter().all(|instance| {
+                instance_kind(root_config, instance)
+                    .is_some_and(|kind| kind != BackendKind::Quorum && kind.is_reproducible())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quorum;
+    use crate::backend::{Backend, BackendKind, DerivationInfo, instances_for};
+    use crate::error::BackendError;
+    use crate::test_support::secret;
+    use crate::test_support::vault;
+    use crate::{Config, Session};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn local(dir: &Path) -> serde_json::Value {
+        serde_json::json!({
+            "kind": "local",
+            "database": dir.join("local.age"),
+            "key": {"file": dir.join("local.key")},
+        })
+    }
+
+    fn quorum(instances: &[&str], threshold: Option<usize>) -> serde_json::Value {
+        serde_json::json!({
+            "kind": "quorum",
+            "instances": instances,
+            "threshold": threshold,
+            "secrets": ["db"],
+        })
+    }
+
+    #[test]
+    fn instances_must_agree() {
+        let dir = std::env::temp_dir().join(format!("quorum-{}", std::process::id()));
+        for (replica, value) in [("a", "hunter2"), ("b", "hunter2"), ("c", "hunter3")] {
+            vault(&dir.join(replica))
+                .insert("db", value.as_bytes())
+                .expect("insert");
+        }
+        vault(&dir.join("d")).insert("other", b"").expect("insert");
+
+        let config = Config {
+            backend_config: Some(HashMap::from([
+                ("a".to_string(), local(&dir.join("a"))),
+                ("b".to_string(), local(&dir.join("b"))),
+                ("c".to_string(), local(&dir.join("c"))),
+                ("d".to_string(), local(&dir.join("d"))),
+                ("agree".to_string(), quorum(&["a", "b"], None)),
+                ("tampered".to_string(), quorum(&["a", "c", "b"], None)),
+                (
+                    "two-of-three".to_string(),
+                    quorum(&["d", "a", "b"], Some(2)),
+                ),
+                ("all-three".to_string(), quorum(&["d", "a", "b"], None)),
+                ("minted".to_string(), serde_json::json!({"kind": "mint"})),
+                ("with-mint".to_string(), quorum(&["a", "minted"], Some(1))),
+            ])),
+            ..Config::default()
+        };
+        let derivation = DerivationInfo::default();
+        let session = Session::new(&config).expect("session");
+        let read = |name: &str, secret_name: &str| {
+            assert!(Quorum::validate_config(&config, name));
+            Quorum::new(&config, name, &session)
+                .expect("backend")
+                .provision(&secret(secret_name), &derivation)
+                .map(|content| content.0)
+        };
+
+        assert!(!Quorum::validate_config(&config, "with-mint"));
+
+        assert_eq!(read("agree", "db").expect("agreed"), b"hunter2");
+        assert_eq!(read("two-of-three", "db").expect("agreed"), b"hunter2");
+        assert!(matches!(
+            read("tampered", "db"),
+            Err(BackendError::Disagreement(groups))
+                if groups == [vec!["a".to_string(), "b".to_string()], vec!["c".to_string()]]
+        ));
+        assert!(matches!(
+            read("all-three", "db"),
+            Err(BackendError::Rejected(_))
+        ));
+        assert!(matches!(
+            read("agree", "other"),
+            Err(BackendError::NotFound)
+        ));
+
+        // Guarded secrets are only provisioned through a quorum
+        assert!(
+            instances_for(&config, &secret("db"))
+                .iter()
+                .all(|(kind, _)| *kind == BackendKind::Quorum)
+        );
+        assert_eq!(instances_for(&config, &secret("other")).len(), 10);
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
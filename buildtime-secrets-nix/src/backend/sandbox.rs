@@ -0,0 +1,375 @@
+use crate::error::BackendError;
+use landlock::{
+    ABI, Access, AccessFs, CompatLevel, Compatible, Ruleset, RulesetAttr, RulesetCreated,
+    RulesetCreatedAttr, RulesetStatus, path_beneath_rules,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::{CString, OsString};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+use tracing::debug;
+
+const LANDLOCK_ABI: ABI = ABI::V2;
+
+/// Paths any backend process needs to be able to execute
+/// programs and load libraries.
+const SYSTEM_PATHS: [&str; 8] = [
+    "/nix/store",
+    "/run/current-system/sw",
+    "/etc",
+    "/usr",
+    "/bin",
+    "/lib",
+    "/lib64",
+    "/dev/urandom",
+];
+
+/// System calls a backend process has no business making.
+const DENIED_SYSCALLS: [libc::c_long; 14] = [
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_setns,
+    libc::SYS_unshare,
+    libc::SYS_kexec_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+];
+
+/// Restrictions placed on the processes a backend spawns.
+///
+/// The hook itself runs as root, backend processes are
+/// instead run as `user` with no new privileges, a cleared
+/// environment and a filesystem view limited by Landlock to
+/// the system paths plus `readable_paths` and `writable_paths`.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// The user (name or uid) the process runs as.
+    pub user: String,
+    /// The group (name or gid) the process runs as, defaults to
+    /// the primary group of `user`.
+    pub group: Option<String>,
+    /// Key files and directories the backend may read.
+    pub readable_paths: Vec<PathBuf>,
+    /// Files and directories the backend may write to.
+    pub writable_paths: Vec<PathBuf>,
+    /// Environment variables kept from the hooks environment.
+    pub pass_environment: Vec<String>,
+    /// Restrict the filesystem with Landlock.
+    pub landlock: bool,
+    /// Deny system calls used to escape or inspect other processes.
+    pub seccomp: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            user: "nobody".to_string(),
+            group: None,
+            readable_paths: Vec::new(),
+            writable_paths: Vec::new(),
+            pass_environment: vec!["PATH".to_string()],
+            landlock: true,
+            seccomp: false,
+        }
+    }
+}
+
+impl SandboxConfig {
+    /// The uid and gid the process runs as.
+    ///
+    /// # Errors
+    ///
+    /// If the user or group don't exist.
+    pub(crate) fn owner(&self) -> Result<(u32, u32), BackendError> {
+        let (uid, user_gid) = lookup_user(&self.user)?;
+        let gid = match &self.group {
+            Some(group) => lookup_group(group)?,
+            None => user_gid,
+        };
+        Ok((uid, gid))
+    }
+}
+
+fn sandbox_err<S: Into<String>>(msg: S) -> BackendError {
+    BackendError::Sandbox(msg.into())
+}
+
+/// Look up a user by name or uid, returning its uid and
+/// primary gid.
+fn lookup_user(user: &str) -> Result<(u32, u32), BackendError> {
+    // SAFETY: passwd is plain old data, all zeroes is a valid value
+    // and it's only read after getpwuid_r or getpwnam_r fill it in
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+
+    let ret = if let Ok(uid) = user.parse::<u32>() {
+        // SAFETY: every pointer references a live, correctly sized buffer
+        unsafe {
+            libc::getpwuid_r(
+                uid,
+                &raw mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &raw mut result,
+            )
+        }
+    } else {
+        let name = CString::new(user).map_err(|_| sandbox_err("user name contains a nul byte"))?;
+        // SAFETY: every pointer references a live, correctly sized buffer
+        unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &raw mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &raw mut result,
+            )
+        }
+    };
+
+    if ret != 0 || result.is_null() {
+        return Err(sandbox_err(format!("unknown user \"{user}\"")));
+    }
+
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+/// Look up a group by name or gid, returning its gid.
+fn lookup_group(group: &str) -> Result<u32, BackendError> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+
+    // SAFETY: group is plain old data, all zeroes is a valid value
+    // and it's only read after getgrnam_r fills it in
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let name = CString::new(group).map_err(|_| sandbox_err("group name contains a nul byte"))?;
+
+    // SAFETY: every pointer references a live, correctly sized buffer
+    let ret = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &raw mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &raw mut result,
+        )
+    };
+
+    if ret != 0 || result.is_null() {
+        return Err(sandbox_err(format!("unknown group \"{group}\"")));
+    }
+
+    Ok(grp.gr_gid)
+}
+
+/// Build the Landlock ruleset, failing rather than running the
+/// backend unconfined on a kernel without Landlock.
+fn build_ruleset(config: &SandboxConfig) -> Result<RulesetCreated, BackendError> {
+    let read = AccessFs::from_read(LANDLOCK_ABI);
+    let all = AccessFs::from_all(LANDLOCK_ABI);
+
+    Ruleset::default()
+        .set_compatibility(CompatLevel::HardRequirement)
+        .handle_access(all)
+        .and_then(Ruleset::create)
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(SYSTEM_PATHS, read)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(["/dev/null"], all)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&config.readable_paths, read)))
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&config.writable_paths, all)))
+        .map_err(|err| sandbox_err(format!("failed to build landlock ruleset: {err}")))
+}
+
+fn build_seccomp_filter() -> Result<seccompiler::BpfProgram, BackendError> {
+    let arch = std::env::consts::ARCH
+        .try_into()
+        .map_err(|err| sandbox_err(format!("seccomp is unsupported: {err}")))?;
+
+    let rules = DENIED_SYSCALLS
+        .iter()
+        .map(|&syscall| (syscall, Vec::new()))
+        .collect::<BTreeMap<_, _>>();
+
+    let filter = seccompiler::SeccompFilter::new(
+        rules,
+        seccompiler::SeccompAction::Allow,
+        seccompiler::SeccompAction::Errno(libc::EPERM.cast_unsigned()),
+        arch,
+    )
+    .map_err(|err| sandbox_err(format!("failed to build seccomp filter: {err}")))?;
+
+    filter
+        .try_into()
+        .map_err(|err| sandbox_err(format!("failed to compile seccomp filter: {err}")))
+}
+
+fn set_no_new_privs() -> std::io::Result<()> {
+    // SAFETY: prctl with PR_SET_NO_NEW_PRIVS takes no pointers
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Configure `cmd` to run inside the sandbox described by `config`.
+///
+/// Everything that can fail or allocate is prepared here, in the
+/// parent. Only system calls are made between fork and exec.
+///
+/// # Errors
+///
+/// If the user or group don't exist, or if the Landlock ruleset or
+/// seccomp filter can't be built.
+pub fn apply(cmd: &mut Command, config: &SandboxConfig) -> Result<(), BackendError> {
+    let (uid, gid) = config.owner()?;
+
+    debug!("sandboxing {:?} as {uid}:{gid}", cmd.get_program());
+
+    // Keep variables the backend set on the command itself
+    let explicit_envs = cmd
+        .get_envs()
+        .filter_map(|(key, value)| Some((key.to_owned(), value?.to_owned())))
+        .collect::<Vec<(OsString, OsString)>>();
+
+    cmd.env_clear();
+    cmd.envs(
+        config
+            .pass_environment
+            .iter()
+            .filter_map(|key| Some((key, std::env::var_os(key)?))),
+    );
+    cmd.envs(explicit_envs);
+    cmd.current_dir("/");
+
+    // Dropping supplementary groups is done by std when uid is set
+    cmd.uid(uid).gid(gid);
+
+    let ruleset = config.landlock.then(|| build_ruleset(config)).transpose()?;
+    let ruleset = Mutex::new(ruleset);
+
+    let seccomp_filter = config.seccomp.then(build_seccomp_filter).transpose()?;
+
+    // SAFETY: the closure runs between fork and exec, it only locks a
+    // mutex no other thread can hold and makes system calls
+    unsafe {
+        cmd.pre_exec(move || {
+            set_no_new_privs()?;
+
+            if let Some(ruleset) = ruleset.lock().ok().and_then(|mut ruleset| ruleset.take()) {
+                let status = ruleset
+                    .restrict_self()
+                    .map_err(|_| std::io::Error::from_raw_os_error(libc::EPERM))?;
+
+                if status.ruleset != RulesetStatus::FullyEnforced {
+                    return Err(std::io::Error::from_raw_os_error(libc::EPERM));
+                }
+            }
+
+            if let Some(filter) = &seccomp_filter {
+                seccompiler::apply_filter(filter)
+                    .map_err(|_| std::io::Error::from_raw_os_error(libc::EPERM))?;
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SandboxConfig, apply};
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    fn config() -> SandboxConfig {
+        SandboxConfig {
+            // SAFETY: getuid has no preconditions
+            user: unsafe { libc::getuid() }.to_string(),
+            ..SandboxConfig::default()
+        }
+    }
+
+    #[test]
+    fn only_readable_paths_can_be_read() {
+        let dir = std::env::temp_dir().join(format!("sandbox-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("allowed")).expect("create dir");
+        std::fs::write(dir.join("allowed/key"), "hunter2").expect("write key");
+        std::fs::write(dir.join("denied"), "hunter3").expect("write key");
+
+        let read = |path: &str| {
+            let mut cmd = Command::new("cat");
+            cmd.arg(dir.join(path)).stderr(Stdio::null());
+            apply(
+                &mut cmd,
+                &SandboxConfig {
+                    readable_paths: vec![dir.join("allowed")],
+                    ..config()
+                },
+            )
+            .expect("sandbox");
+            cmd.output().expect("run cat")
+        };
+
+        let allowed = read("allowed/key");
+        assert!(allowed.status.success());
+        assert_eq!(allowed.stdout, b"hunter2");
+
+        let denied = read("denied");
+        assert!(!denied.status.success());
+        assert!(denied.stdout.is_empty());
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn seccomp_denies_syscalls() {
+        let spawn = |seccomp: bool| {
+            let mut cmd = Command::new("true");
+            apply(
+                &mut cmd,
+                &SandboxConfig {
+                    landlock: false,
+                    seccomp,
+                    ..config()
+                },
+            )
+            .expect("sandbox");
+
+            // SAFETY: only makes a system call, after the sandbox's own
+            // pre_exec has installed the filter. unshare(0) changes nothing
+            unsafe {
+                cmd.pre_exec(|| {
+                    if libc::syscall(libc::SYS_unshare, 0) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+
+            cmd.status().map(|status| status.success())
+        };
+
+        assert!(spawn(false).expect("unfiltered"));
+        assert_eq!(
+            spawn(true).expect_err("filtered").raw_os_error(),
+            Some(libc::EPERM)
+        );
+    }
+}
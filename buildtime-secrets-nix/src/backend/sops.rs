@@ -1,7 +1,9 @@
-use crate::backend::Backend;
+use crate::Config;
+use crate::backend::process::ProcessConfig;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
 use crate::secret::Secret;
 use crate::secret::SecretContent;
-use crate::{Config, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -10,6 +12,8 @@ use std::path::PathBuf;
 pub struct BackendConfig {
     sops_file: PathBuf,
     environment: Option<HashMap<String, String>>,
+    #[serde(flatten)]
+    process: ProcessConfig,
 }
 
 /// This backend will ask
@@ -20,11 +24,16 @@ pub struct BackendConfig {
 /// passed in the backend configuration, along with environment
 /// variables that will be set in the sops process.
 pub struct Sops {
+    name: String,
     config: BackendConfig,
 }
 
 impl Backend<'_> for Sops {
-    fn provision(&self, secret: &Secret) -> Option<SecretContent> {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
         let mut cmd = std::process::Command::new("sops");
         cmd.args(["--extract", format!("[\"{}\"]", secret.name).as_ref()]);
         cmd.args(["-d".as_ref(), self.config.sops_file.as_os_str()]);
@@ -41,7 +50,7 @@ impl Backend<'_> for Sops {
             cmd.envs(envs);
         }
 
-        crate::backend::provision_with_cmd(secret, &mut cmd)
+        crate::backend::provision_with_cmd(&self.name, secret, &mut cmd, &self.config.process, None)
     }
 }
 
@@ -51,15 +60,18 @@ impl Sops {
     /// # Errors
     ///
     /// If the associated config can't be parsed.
-    pub fn new(root_config: &Config) -> Result<Self> {
-        let config = crate::backend::get_backend_config(root_config, "sops")?;
-        Ok(Sops { config })
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Sops {
+            name: name.to_string(),
+            config,
+        })
     }
 
     /// Validate an exacutable backend config.
     #[must_use]
-    pub fn validate_config(root_config: &Config) -> bool {
-        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, "sops");
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
         let Ok(_config) = parse_result else {
             return false;
         };
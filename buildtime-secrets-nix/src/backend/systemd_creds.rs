@@ -0,0 +1,224 @@
+use crate::Config;
+use crate::backend::process::ProcessConfig;
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::debug;
+
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// A directory of `<credential name>.cred` files.
+    credential_dir: PathBuf,
+    /// Credential names keyed by secret name, secrets not listed
+    /// use their own name.
+    #[serde(default)]
+    credentials: HashMap<String, String>,
+    /// Overrides the host key, `/var/lib/systemd/credential.secret`.
+    host_key_file: Option<PathBuf>,
+    #[serde(flatten)]
+    process: ProcessConfig,
+}
+
+/// This backend decrypts credentials made with
+/// `systemd-creds encrypt`, bound to the host key, the TPM or both.
+///
+/// The secret `github` is decrypted from
+/// `<credential_dir>/github.cred`. The credential name is passed
+/// with `--name`, so `systemd-creds` refuses a credential that was
+/// encrypted under another name, e.g. one copied over another file.
+pub struct SystemdCreds {
+    name: String,
+    config: BackendConfig,
+}
+
+/// Turn a failed `systemd-creds` into a clearer error. It exits
+/// with 1 whatever went wrong, so the messages it starts its
+/// lines with are matched instead.
+fn classify_failure(err: BackendError) -> BackendError {
+    let BackendError::CommandFailed { stderr, .. } = &err else {
+        return err;
+    };
+
+    let has = |prefix: &str| stderr.lines().any(|line| line.starts_with(prefix));
+
+    if has("Failed to determine local credential key") {
+        BackendError::BadKey(format!(
+            "the host key is missing or unreadable, is `systemd-creds setup` done? {stderr}"
+        ))
+    } else if has("Embedded credential name") {
+        BackendError::Rejected(stderr.clone())
+    } else if has("Decryption failed")
+        || has("Failed to read encrypted credential data")
+        || has("Failed to unseal")
+        || has("TPM2 support")
+    {
+        BackendError::Decrypt(stderr.clone())
+    } else {
+        err
+    }
+}
+
+impl Backend<'_> for SystemdCreds {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let credential = self
+            .config
+            .credentials
+            .get(&secret.name)
+            .unwrap_or(&secret.name);
+
+        // Don't let a credential name escape the credential directory
+        if credential.contains('/') || credential.starts_with('.') {
+            return Err(BackendError::NotFound);
+        }
+
+        let path = self
+            .config
+            .credential_dir
+            .join(format!("{credential}.cred"));
+        if !path.exists() {
+            debug!("no credential at \"{}\"", path.display());
+            return Err(BackendError::NotFound);
+        }
+
+        let mut cmd = std::process::Command::new("systemd-creds");
+        cmd.arg("decrypt")
+            .arg(format!("--name={credential}"))
+            .arg(&path)
+            .arg("-");
+        cmd.env_clear();
+
+        // Retain parent process' PATH
+        cmd.envs(
+            std::env::vars()
+                .filter(|(key, _)| key == "PATH")
+                .collect::<HashMap<_, _>>(),
+        );
+
+        if let Some(host_key_file) = &self.config.host_key_file {
+            cmd.env("SYSTEMD_CREDENTIAL_SECRET", host_key_file);
+        }
+
+        crate::backend::provision_with_cmd(&self.name, secret, &mut cmd, &self.config.process, None)
+            .map_err(classify_failure)
+    }
+}
+
+impl SystemdCreds {
+    /// Creates a new `SystemdCreds` backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(SystemdCreds {
+            name: name.to_string(),
+            config,
+        })
+    }
+
+    /// Validate a systemd-creds backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendConfig, SystemdCreds};
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::secret;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn decrypts_host_credentials() {
+        let dir = std::env::temp_dir().join(format!("creds-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        let host_key_file = dir.join("credential.secret");
+
+        let encrypt = |name: &str, file: &str| {
+            let mut child = Command::new("systemd-creds")
+                .args(["encrypt", "--with-key=host", &format!("--name={name}"), "-"])
+                .arg(dir.join(file))
+                .env("SYSTEMD_CREDENTIAL_SECRET", &host_key_file)
+                .stdin(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("spawn systemd-creds");
+            child
+                .stdin
+                .take()
+                .expect("stdin")
+                .write_all(b"hunter2")
+                .expect("write credential");
+            assert!(child.wait().expect("wait").success());
+        };
+        encrypt("github", "github.cred");
+        // Encrypted under one name, stored under another
+        encrypt("github", "gitlab.cred");
+        encrypt("tampered", "tampered.cred");
+        let mut tampered = std::fs::read(dir.join("tampered.cred")).expect("read credential");
+        let last = tampered.len() - 5;
+        tampered[last] ^= 1;
+        std::fs::write(dir.join("tampered.cred"), tampered).expect("tamper credential");
+        std::fs::write(dir.join("garbage.cred"), "not a credential").expect("write garbage");
+
+        let mut backend = SystemdCreds {
+            name: "systemd-creds".to_string(),
+            config: BackendConfig {
+                credential_dir: dir.clone(),
+                credentials: HashMap::from([("gh".to_string(), "github".to_string())]),
+                host_key_file: Some(host_key_file),
+                ..BackendConfig::default()
+            },
+        };
+        let derivation = DerivationInfo::default();
+
+        let content = backend
+            .provision(&secret("gh"), &derivation)
+            .expect("credential");
+        assert_eq!(content.0, b"hunter2");
+
+        assert!(matches!(
+            backend.provision(&secret("gitlab"), &derivation),
+            Err(BackendError::Rejected(_))
+        ));
+        assert!(matches!(
+            backend.provision(&secret("bitbucket"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            backend.provision(&secret("tampered"), &derivation),
+            Err(BackendError::Decrypt(_))
+        ));
+        assert!(matches!(
+            backend.provision(&secret("garbage"), &derivation),
+            Err(BackendError::Decrypt(_))
+        ));
+
+        backend.config.host_key_file = Some(dir.join("missing.secret"));
+        assert!(matches!(
+            backend.provision(&secret("github"), &derivation),
+            Err(BackendError::BadKey(_))
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
@@ -0,0 +1,176 @@
+use crate::backend::DerivationInfo;
+use crate::error::BackendError;
+use crate::secret::Secret;
+
+/// The values placeholders in a template are replaced with.
+///
+/// | Placeholder  | Value                              |
+/// |--------------|------------------------------------|
+/// | `{name}`     | the secrets name                   |
+/// | `{hash}`     | the secrets declared hash          |
+/// | `{drv_name}` | the name of the derivation         |
+/// | `{drv_path}` | the store path of the derivation   |
+/// | `{backend}`  | the name of the backend instance   |
+///
+/// `{{` and `}}` render a literal brace.
+pub struct Placeholders<'a> {
+    pub secret: Option<&'a Secret>,
+    pub derivation: &'a DerivationInfo,
+    pub backend: &'a str,
+}
+
+impl Placeholders<'_> {
+    fn lookup(&self, placeholder: &str) -> Option<&str> {
+        match placeholder {
+            "name" => self.secret.map(|secret| secret.name.as_str()),
+            "hash" => self.secret.map(|secret| secret.hash.as_str()),
+            "drv_name" => Some(&self.derivation.name),
+            "drv_path" => Some(&self.derivation.path),
+            "backend" => Some(self.backend),
+            _ => None,
+        }
+    }
+
+    /// Render a single template.
+    ///
+    /// # Errors
+    ///
+    /// If the template has an unterminated or unknown placeholder, or
+    /// uses a secret placeholder when there is no secret.
+    pub fn render(&self, template: &str) -> Result<String, BackendError> {
+        let mut rendered = String::with_capacity(template.len());
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    rendered.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    rendered.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(BackendError::Template(format!(
+                                    "unterminated placeholder \"{{{placeholder}\" in \"{template}\""
+                                )));
+                            }
+                        }
+                    }
+
+                    let Some(value) = self.lookup(&placeholder) else {
+                        return Err(BackendError::Template(format!(
+                            "unknown placeholder \"{{{placeholder}}}\" in \"{template}\""
+                        )));
+                    };
+
+                    rendered.push_str(value);
+                }
+                '}' => {
+                    return Err(BackendError::Template(format!(
+                        "unmatched \"}}\" in \"{template}\""
+                    )));
+                }
+                c => rendered.push(c),
+            }
+        }
+
+        Ok(rendered)
+    }
+
+    /// Render every template in `templates`.
+    ///
+    /// # Errors
+    ///
+    /// If any template fails to render.
+    pub fn render_all<'t, I>(&self, templates: I) -> Result<Vec<String>, BackendError>
+    where
+        I: IntoIterator<Item = &'t String>,
+    {
+        templates
+            .into_iter()
+            .map(|template| self.render(template))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Placeholders;
+    use crate::backend::DerivationInfo;
+    use crate::error::BackendError;
+    use crate::secret::{Secret, SecretKind};
+
+    fn secret() -> Secret {
+        Secret {
+            name: "aws-credentials".to_string(),
+            hash: "sha256-AAAA".to_string(),
+            backend_hint: None,
+            kind: SecretKind::Pinned,
+        }
+    }
+
+    fn derivation() -> DerivationInfo {
+        DerivationInfo {
+            path: "/nix/store/aaaa-fetch.drv".to_string(),
+            name: "fetch".to_string(),
+            fixed_output: false,
+        }
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let secret = secret();
+        let derivation = derivation();
+        let placeholders = Placeholders {
+            secret: Some(&secret),
+            derivation: &derivation,
+            backend: "pass",
+        };
+
+        let rendered = placeholders
+            .render("builds/{drv_name}/{name} via {backend} {{literal}}")
+            .expect("render");
+
+        assert_eq!(rendered, "builds/fetch/aws-credentials via pass {literal}");
+    }
+
+    #[test]
+    fn secret_placeholders_need_a_secret() {
+        let derivation = derivation();
+        let placeholders = Placeholders {
+            secret: None,
+            derivation: &derivation,
+            backend: "pass",
+        };
+
+        assert!(matches!(
+            placeholders.render("{name}"),
+            Err(BackendError::Template(_))
+        ));
+        assert_eq!(placeholders.render("{drv_name}").expect("render"), "fetch");
+    }
+
+    #[test]
+    fn unterminated_placeholder_is_rejected() {
+        let secret = secret();
+        let derivation = derivation();
+        let placeholders = Placeholders {
+            secret: Some(&secret),
+            derivation: &derivation,
+            backend: "pass",
+        };
+
+        assert!(matches!(
+            placeholders.render("--name={name"),
+            Err(BackendError::Template(msg)) if msg.contains("unterminated")
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt::Write;
+
+/// Split a secret name into the entry it names and the field
+/// selected by its `:` suffix, if any.
+pub(crate) fn split_field(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once(':') {
+        Some((entry, field)) if !entry.is_empty() && !field.is_empty() => (entry, Some(field)),
+        _ => (name, None),
+    }
+}
+
+/// Percent encode everything in `name` but unreserved characters
+/// and slashes, so it can be used as a URL path.
+pub(crate) fn encode_name(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                char::from(byte).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Lower case hex encode `bytes`.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_name, split_field};
+
+    #[test]
+    fn name_suffix_selects_field() {
+        assert_eq!(
+            split_field("web/github:login"),
+            ("web/github", Some("login"))
+        );
+        assert_eq!(split_field("web/github"), ("web/github", None));
+        assert_eq!(split_field("web/github:"), ("web/github:", None));
+    }
+
+    #[test]
+    fn names_are_encoded() {
+        assert_eq!(encode_name("db/pass word?x=1"), "db/pass%20word%3Fx%3D1");
+    }
+}
@@ -0,0 +1,543 @@
+use crate::backend::client::{self, ClientConfig};
+use crate::backend::util::{encode_name, split_field};
+use crate::backend::{Backend, DerivationInfo};
+use crate::error::BackendError;
+use crate::secret::Secret;
+use crate::secret::SecretContent;
+use crate::{Config, Session};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use tracing::debug;
+
+/// The `AppRole` tokens logged in for during a hook run, keyed by
+/// backend name, so a run logs in once rather than per secret.
+pub(crate) type Tokens = Mutex<HashMap<String, String>>;
+
+/// How the backend logs in to Vault.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Auth {
+    /// A token given inline. The hook config is world readable, so
+    /// it must be injected by an inline `dependencies` entry with
+    /// the pointer `/auth/token`, never written as a literal.
+    Token { token: String },
+    /// A token read from a file.
+    TokenFile { path: PathBuf },
+    /// An `AppRole` login, exchanging a role and secret id for
+    /// a token.
+    Approle {
+        role_id: String,
+        secret_id_file: PathBuf,
+        #[serde(default = "default_approle_mount")]
+        mount: String,
+    },
+}
+
+fn default_approle_mount() -> String {
+    "approle".to_string()
+}
+
+fn default_mount() -> String {
+    "secret".to_string()
+}
+
+fn default_field() -> String {
+    "value".to_string()
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BackendConfig {
+    /// The Vault server, e.g. `https://vault.example.com:8200`.
+    address: String,
+    /// Where the KV v2 engine is mounted.
+    #[serde(default = "default_mount")]
+    mount: String,
+    /// Prepended to every secret path.
+    #[serde(default)]
+    path_prefix: String,
+    /// The Vault Enterprise namespace to use.
+    namespace: Option<String>,
+    auth: Auth,
+    /// The field provisioned when the secret name doesn't
+    /// select one.
+    #[serde(default = "default_field")]
+    field: String,
+    /// Secret versions to read instead of the latest, keyed
+    /// by secret path.
+    #[serde(default)]
+    versions: HashMap<String, u64>,
+    #[serde(flatten)]
+    client: ClientConfig,
+}
+
+#[derive(Deserialize)]
+struct KvResponse {
+    data: KvData,
+}
+
+#[derive(Deserialize)]
+struct KvData {
+    data: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    auth: LoginAuth,
+}
+
+#[derive(Deserialize)]
+struct LoginAuth {
+    client_token: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+/// This backend reads secrets from a `HashiCorp` Vault KV v2
+/// secrets engine.
+///
+/// The secret `app/db` is read from
+/// `<address>/v1/<mount>/data/<path_prefix>app/db`, and the
+/// `field` of its data is provisioned. Like [`super::pass::Pass`],
+/// a `:<field>` suffix selects another field, so `app/db:user`
+/// provisions the `user` field.
+pub struct Vault<'a> {
+    name: String,
+    config: BackendConfig,
+    session: &'a Session,
+}
+
+/// Turn an error status into a [`BackendError`], using the
+/// messages Vault puts in the body.
+fn status_error(status: u16, body: &[u8]) -> BackendError {
+    let message = serde_json::from_slice::<ErrorResponse>(body).map_or_else(
+        |_| String::from_utf8_lossy(body).trim().to_string(),
+        |response| response.errors.join(", "),
+    );
+
+    match status {
+        404 => BackendError::NotFound,
+        401 | 403 => BackendError::Rejected(message),
+        status => BackendError::Http(format!("vault answered {status}: {message}")),
+    }
+}
+
+impl Vault<'_> {
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/v1/{}",
+            self.config.address.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    /// Get a token, logging in if this backend hasn't yet.
+    fn token(&self, agent: &ureq::Agent) -> Result<String, BackendError> {
+        let (role_id, secret_id_file, mount) = match &self.config.auth {
+            Auth::Token { token } => return Ok(token.clone()),
+            Auth::TokenFile { path } => return client::read_credential(path),
+            Auth::Approle {
+                role_id,
+                secret_id_file,
+                mount,
+            } => (role_id, secret_id_file, mount),
+        };
+
+        let mut tokens = self
+            .session
+            .vault_tokens()
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if let Some(token) = tokens.get(&self.name) {
+            return Ok(token.clone());
+        }
+
+        debug!("{}: logging in with approle", self.name);
+        let login = serde_json::json!({
+            "role_id": role_id,
+            "secret_id": client::read_credential(secret_id_file)?,
+        });
+
+        let mut request = agent.post(self.url(&format!("auth/{mount}/login")));
+        if let Some(namespace) = &self.config.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+
+        let mut response = request
+            .header("Content-Type", "application/json")
+            .send(login.to_string())
+            .map_err(client::error)?;
+        let body = client::read_body(&mut response, &self.config.client)?;
+
+        if !response.status().is_success() {
+            return Err(match status_error(response.status().as_u16(), &body) {
+                BackendError::NotFound => {
+                    BackendError::Rejected(format!("no approle auth at \"{mount}\""))
+                }
+                err => err,
+            });
+        }
+
+        let token = serde_json::from_slice::<LoginResponse>(&body)
+            .map(|login| login.auth.client_token)
+            .map_err(|err| BackendError::InvalidResponse(err.to_string()))?;
+
+        tokens.insert(self.name.clone(), token.clone());
+        Ok(token)
+    }
+
+    /// Forget a token Vault refused, unless another secret has
+    /// already logged in again.
+    fn evict(&self, token: &str) {
+        let mut tokens = self
+            .session
+            .vault_tokens()
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if tokens.get(&self.name).is_some_and(|cached| cached == token) {
+            tokens.remove(&self.name);
+        }
+    }
+
+    /// Read the secret at `path`, returning the status and body.
+    fn read(
+        &self,
+        agent: &ureq::Agent,
+        path: &str,
+        token: &str,
+    ) -> Result<(u16, Vec<u8>), BackendError> {
+        let mut request = agent
+            .get(self.url(&format!(
+                "{}/data/{}{}",
+                self.config.mount.trim_matches('/'),
+                self.config.path_prefix,
+                encode_name(path)
+            )))
+            .header("X-Vault-Token", token);
+        if let Some(namespace) = &self.config.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        if let Some(version) = self.config.versions.get(path) {
+            debug!("reading version {version} of {path}");
+            request = request.query("version", version.to_string());
+        }
+
+        let mut response = request.call().map_err(client::error)?;
+        let body = client::read_body(&mut response, &self.config.client)?;
+        Ok((response.status().as_u16(), body))
+    }
+}
+
+impl Backend<'_> for Vault<'_> {
+    fn provision(
+        &self,
+        secret: &Secret,
+        _derivation: &DerivationInfo,
+    ) -> Result<SecretContent, BackendError> {
+        let (path, field) = split_field(&secret.name);
+        let field = field.unwrap_or(&self.config.field);
+
+        if !Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(BackendError::NotFound);
+        }
+
+        debug!("{}: reading {path} from {}", self.name, self.config.address);
+        let agent = client::agent(&self.config.client)?;
+        let token = self.token(&agent)?;
+        let (mut status, mut body) = self.read(&agent, path, &token)?;
+
+        // An `AppRole` token may have expired, so log in once more
+        if status == 403 && matches!(self.config.auth, Auth::Approle { .. }) {
+            debug!("{}: token refused, logging in again", self.name);
+            self.evict(&token);
+            let token = self.token(&agent)?;
+            (status, body) = self.read(&agent, path, &token)?;
+        }
+
+        if !(200..300).contains(&status) {
+            return Err(status_error(status, &body));
+        }
+
+        let data = serde_json::from_slice::<KvResponse>(&body)
+            .map_err(|err| BackendError::InvalidResponse(err.to_string()))?
+            .data
+            .data
+            // A deleted or destroyed version has no data
+            .ok_or(BackendError::NotFound)?;
+
+        match data.get(field) {
+            Some(serde_json::Value::String(value)) => Ok(SecretContent(value.as_bytes().to_vec())),
+            Some(value) => Ok(SecretContent(value.to_string().into_bytes())),
+            None => Err(BackendError::NotFound),
+        }
+    }
+}
+
+impl<'a> Vault<'a> {
+    /// Creates a new Vault backend.
+    ///
+    /// # Errors
+    ///
+    /// If the associated config can't be parsed.
+    pub fn new(root_config: &Config, name: &str, session: &'a Session) -> crate::Result<Self> {
+        let config = crate::backend::get_backend_config(root_config, name)?;
+        Ok(Vault {
+            name: name.to_string(),
+            config,
+            session,
+        })
+    }
+
+    /// Validate a vault backend config.
+    #[must_use]
+    pub fn validate_config(root_config: &Config, name: &str) -> bool {
+        let parse_result = crate::backend::get_backend_config::<BackendConfig>(root_config, name);
+        let Ok(_config) = parse_result else {
+            return false;
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Auth, BackendConfig, Vault};
+    use crate::backend::client::ClientConfig;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::BackendError;
+    use crate::test_support::{credential, secret, serve, vault};
+    use crate::{Config, Session};
+    use std::collections::HashMap;
+    use std::os::unix::fs::PermissionsExt;
+
+    const SECRET: &str =
+        r#"{"data": {"data": {"value": "hunter2", "user": "octocat", "port": 5432}}}"#;
+
+    fn backend(address: String, auth: Auth, session: &Session) -> Vault<'_> {
+        Vault {
+            name: "vault".to_string(),
+            config: BackendConfig {
+                address,
+                mount: "kv".to_string(),
+                path_prefix: "ci/".to_string(),
+                namespace: Some("team".to_string()),
+                auth,
+                field: "value".to_string(),
+                versions: HashMap::from([("app/db".to_string(), 3)]),
+                client: ClientConfig::default(),
+            },
+            session,
+        }
+    }
+
+    #[test]
+    fn reads_fields_with_token() {
+        let (address, server) = serve(vec![
+            (200, SECRET.to_string()),
+            (200, SECRET.to_string()),
+            (200, SECRET.to_string()),
+            (404, r#"{"errors": []}"#.to_string()),
+            (403, r#"{"errors": ["permission denied"]}"#.to_string()),
+        ]);
+        let token_file = std::env::temp_dir().join(format!("vault-token-{}", std::process::id()));
+        credential(&token_file, "s.root\n");
+        let session = Session::new(&Config::default()).expect("session");
+        let backend = backend(
+            address,
+            Auth::TokenFile {
+                path: token_file.clone(),
+            },
+            &session,
+        );
+        let derivation = DerivationInfo::default();
+
+        let value = backend
+            .provision(&secret("app/db"), &derivation)
+            .expect("value");
+        assert_eq!(value.0, b"hunter2");
+
+        let user = backend
+            .provision(&secret("app/db:user"), &derivation)
+            .expect("user");
+        assert_eq!(user.0, b"octocat");
+
+        let port = backend
+            .provision(&secret("app/api:port"), &derivation)
+            .expect("port");
+        assert_eq!(port.0, b"5432");
+
+        assert!(matches!(
+            backend.provision(&secret("app/gone"), &derivation),
+            Err(BackendError::NotFound)
+        ));
+        assert!(matches!(
+            backend.provision(&secret("app/denied"), &derivation),
+            Err(BackendError::Rejected(message)) if message == "permission denied"
+        ));
+
+        let requests = server.join().expect("server");
+        std::fs::remove_file(token_file).expect("remove token");
+
+        assert_eq!(
+            requests[0].line,
+            "GET /v1/kv/data/ci/app/db?version=3 HTTP/1.1"
+        );
+        assert_eq!(requests[0].header("x-vault-token"), Some("s.root"));
+        assert_eq!(requests[0].header("x-vault-namespace"), Some("team"));
+        assert_eq!(requests[2].line, "GET /v1/kv/data/ci/app/api HTTP/1.1");
+    }
+
+    #[test]
+    fn token_is_injected_by_a_dependency() {
+        let dir = std::env::temp_dir().join(format!("vault-dependency-{}", std::process::id()));
+        vault(&dir)
+            .insert("vault-token", b"s.root")
+            .expect("insert");
+
+        let (address, server) = serve(vec![(200, SECRET.to_string())]);
+        let config = Config {
+            backend_config: Some(HashMap::from([
+                (
+                    "keys".to_string(),
+                    serde_json::json!({
+                        "kind": "local",
+                        "database": dir.join("local.age"),
+                        "key": {"file": dir.join("local.key")},
+                    }),
+                ),
+                (
+                    "vault".to_string(),
+                    serde_json::json!({
+                        "address": address,
+                        "auth": {"method": "token"},
+                        "dependencies": [{
+                            "backend": "keys",
+                            "secret": "vault-token",
+                            "pointer": "/auth/token",
+                            "inline": true,
+                        }],
+                    }),
+                ),
+            ])),
+            ..Config::default()
+        };
+        let derivation = DerivationInfo::default();
+        let session = Session::new(&config).expect("session");
+        let resolved = session.resolve("vault", &derivation).expect("resolve");
+        assert!(Vault::validate_config(&resolved, "vault"));
+
+        // Names are encoded into the path
+        Vault::new(&resolved, "vault", &session)
+            .expect("backend")
+            .provision(&secret("app/db pass?version=1#x"), &derivation)
+            .expect("value");
+
+        let requests = server.join().expect("server");
+        std::fs::remove_dir_all(dir).expect("remove dir");
+
+        assert_eq!(
+            requests[0].line,
+            "GET /v1/secret/data/app/db%20pass%3Fversion%3D1%23x HTTP/1.1"
+        );
+        assert_eq!(requests[0].header("x-vault-token"), Some("s.root"));
+    }
+
+    #[test]
+    fn logs_in_with_approle() {
+        let secret_id_file =
+            std::env::temp_dir().join(format!("vault-secret-id-{}", std::process::id()));
+        credential(&secret_id_file, "s3cr3t\n");
+
+        let login = |token: &str| (200, format!(r#"{{"auth": {{"client_token": "{token}"}}}}"#));
+        let (address, server) = serve(vec![
+            login("s.approle"),
+            (200, SECRET.to_string()),
+            (200, SECRET.to_string()),
+            (403, r#"{"errors": ["permission denied"]}"#.to_string()),
+            login("s.renewed"),
+            (200, SECRET.to_string()),
+            login("s.other"),
+            (200, SECRET.to_string()),
+        ]);
+        let auth = Auth::Approle {
+            role_id: "role".to_string(),
+            secret_id_file: secret_id_file.clone(),
+            mount: "approle".to_string(),
+        };
+        let session = Session::new(&Config::default()).expect("session");
+        let backend = backend(address, auth, &session);
+        let derivation = DerivationInfo::default();
+
+        std::fs::set_permissions(&secret_id_file, std::fs::Permissions::from_mode(0o644))
+            .expect("chmod");
+        assert!(matches!(
+            backend.provision(&secret("app/db"), &derivation),
+            Err(BackendError::BadKey(_))
+        ));
+        std::fs::set_permissions(&secret_id_file, std::fs::Permissions::from_mode(0o600))
+            .expect("chmod");
+
+        let value = backend
+            .provision(&secret("app/db"), &derivation)
+            .expect("value");
+        assert_eq!(value.0, b"hunter2");
+
+        // The token is reused rather than logging in again
+        backend
+            .provision(&secret("app/db:user"), &derivation)
+            .expect("user");
+
+        // A refused token is dropped and the backend logs in again
+        backend
+            .provision(&secret("app/db"), &derivation)
+            .expect("value after logging in again");
+
+        // Another hook run logs in for itself
+        let other_session = Session::new(&Config::default()).expect("session");
+        let other_run = Vault {
+            session: &other_session,
+            ..backend
+        };
+        other_run
+            .provision(&secret("app/db"), &derivation)
+            .expect("value in another run");
+
+        let requests = server.join().expect("server");
+        std::fs::remove_file(&secret_id_file).expect("remove secret id");
+
+        assert_eq!(requests[0].line, "POST /v1/auth/approle/login HTTP/1.1");
+        let login = serde_json::from_slice::<serde_json::Value>(&requests[0].body).expect("login");
+        assert_eq!(
+            login,
+            serde_json::json!({"role_id": "role", "secret_id": "s3cr3t"})
+        );
+        let tokens = requests
+            .iter()
+            .map(|request| request.header("x-vault-token"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                None,
+                Some("s.approle"),
+                Some("s.approle"),
+                Some("s.approle"),
+                None,
+                Some("s.renewed"),
+                None,
+                Some("s.other"),
+            ]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
 #[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
@@ -8,6 +9,15 @@ pub struct Config {
     pub derivation: String,
     pub secret_dir: PathBuf,
     pub backend_config: Option<HashMap<String, serde_json::Value>>,
+    /// The maximum number of secrets provisioned at once, defaults
+    /// to the available parallelism.
+    pub max_concurrency: Option<NonZeroUsize>,
+    /// The number of seconds all of a derivations secrets must be
+    /// provisioned within.
+    pub deadline_secs: Option<u64>,
+    /// The secrets derivations may declare ephemeral, which skips
+    /// verifying their hash.
+    pub ephemeral_secrets: Vec<String>,
 }
 
 impl std::fmt::Display for Config {
@@ -0,0 +1,679 @@
+use crate::backend::sandbox::SandboxConfig;
+use crate::backend::{self, BackendKind, DerivationInfo};
+use crate::error::{BackendError, Error, Result};
+use crate::secret::{Secret, SecretKind};
+use crate::{Config, Session};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::{debug, warn};
+
+static RESOLUTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The prefix of the directories in `secret_dir` holding a hook
+/// run's dependencies, followed by its pid.
+const DIR_PREFIX: &str = "buildtime-secrets-";
+
+/// A secret a backend config depends on, like the key a backend
+/// decrypts with, declared in its `dependencies`.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Dependency {
+    /// The backend instance the secret is provisioned by.
+    backend: String,
+    secret: String,
+    /// A JSON pointer into the dependent config, e.g.
+    /// `/environment/SOPS_AGE_KEY_FILE`, where the path of a
+    /// private file holding the secret is written.
+    pointer: String,
+    /// Write the secret itself rather than the path of a file
+    /// holding it, e.g. for an environment variable.
+    #[serde(default)]
+    inline: bool,
+}
+
+/// A backend's config with its dependencies resolved, or why
+/// they couldn't be.
+type Resolution = std::result::Result<Arc<Config>, String>;
+
+/// The uid and gid a sandboxed backend runs as.
+type Owner = (u32, u32);
+
+/// The secrets backend configs depend on for a hook run. Each
+/// backend's are resolved the first time it's used, then kept for
+/// the rest of the run. The files holding them are written to
+/// private directories in `secret_dir`, one per sandbox user they
+/// are handed to, and removed when it's dropped.
+pub(crate) struct Dependencies {
+    config: Config,
+    resolutions: Mutex<HashMap<String, Arc<OnceLock<Resolution>>>>,
+    dirs: Mutex<HashMap<Option<Owner>, PathBuf>>,
+    files: AtomicUsize,
+}
+
+/// Write `value` at `pointer`, creating missing objects along
+/// the way.
+fn insert(target: &mut serde_json::Value, pointer: &str, value: serde_json::Value) -> bool {
+    let Some(path) = pointer.strip_prefix('/') else {
+        return false;
+    };
+
+    let mut current = target;
+    let mut tokens = path
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .peekable();
+
+    while let Some(token) = tokens.next() {
+        if current.is_null() {
+            *current = serde_json::Value::Object(serde_json::Map::new());
+        }
+
+        let serde_json::Value::Object(object) = current else {
+            return false;
+        };
+
+        if tokens.peek().is_none() {
+            object.insert(token, value);
+            return true;
+        }
+
+        current = object.entry(token).or_insert(serde_json::Value::Null);
+    }
+
+    false
+}
+
+/// Let a sandboxed backend read the dependency file at `path`.
+fn allow_read(backend_config: &mut serde_json::Value, path: &str) {
+    let Some(sandbox) = backend_config
+        .get_mut("sandbox")
+        .and_then(serde_json::Value::as_object_mut)
+    else {
+        return;
+    };
+
+    if let serde_json::Value::Array(paths) = sandbox
+        .entry("readable_paths")
+        .or_insert_with(|| serde_json::Value::Array(Vec::new()))
+    {
+        paths.push(path.into());
+    }
+}
+
+/// The instances the backend `name` fetches secrets from. A
+/// malformed `dependencies` array is left out until it's used.
+fn upstream(config: &Config, kind: BackendKind, name: &str) -> Vec<String> {
+    let dependencies = config
+        .backend_config
+        .as_ref()
+        .and_then(|backend_configs| backend_configs.get(name))
+        .and_then(|backend_config| backend_config.get("dependencies"))
+        .and_then(|dependencies| {
+            serde_json::from_value::<Vec<Dependency>>(dependencies.clone()).ok()
+        })
+        .unwrap_or_default();
+
+    dependencies
+        .into_iter()
+        .map(|dependency| dependency.backend)
+        .chain(backend::upstream(config, kind, name))
+        .collect()
+}
+
+/// Find a cycle of backends fetching secrets from each other.
+fn find_cycle(config: &Config) -> Option<Vec<String>> {
+    fn visit(
+        edges: &HashMap<String, Vec<String>>,
+        name: &str,
+        stack: &mut Vec<String>,
+        visited: &mut HashSet<String>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = stack.iter().position(|backend| backend == name) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(name.to_string());
+            return Some(cycle);
+        }
+
+        if !visited.insert(name.to_string()) {
+            return None;
+        }
+
+        stack.push(name.to_string());
+        for next in edges.get(name).into_iter().flatten() {
+            if let Some(cycle) = visit(edges, next, stack, visited) {
+                return Some(cycle);
+            }
+        }
+        stack.pop();
+
+        None
+    }
+
+    let edges = backend::instances(config)
+        .iter()
+        .map(|(kind, name)| (name.clone(), upstream(config, *kind, name)))
+        .collect::<HashMap<_, _>>();
+
+    let mut names = edges.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+
+    let mut visited = HashSet::new();
+    names
+        .iter()
+        .find_map(|name| visit(&edges, name, &mut Vec::new(), &mut visited))
+}
+
+/// Remove the dependency directories in `secret_dir` left behind
+/// by hook runs that have exited without removing them.
+fn remove_stale(secret_dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(secret_dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let Some(pid) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(DIR_PREFIX))
+            .and_then(|rest| rest.split('-').next())
+            .and_then(|pid| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        if Path::new("/proc").join(pid.to_string()).exists() {
+            continue;
+        }
+
+        debug!("removing stale dependencies \"{}\"", entry.path().display());
+        if let Err(err) = std::fs::remove_dir_all(entry.path()) {
+            warn!("can't remove \"{}\": {err}", entry.path().display());
+        }
+    }
+}
+
+impl Dependencies {
+    /// Prepare to resolve the dependencies of the backends in
+    /// `config`, removing those left behind by earlier runs.
+    ///
+    /// # Errors
+    ///
+    /// If backends fetch secrets from each other in a cycle.
+    pub(crate) fn new(config: &Config) -> Result<Self> {
+        if let Some(cycle) = find_cycle(config) {
+            return Err(Error::DependencyCycle(cycle));
+        }
+
+        remove_stale(&config.secret_dir);
+
+        Ok(Dependencies {
+            config: config.clone(),
+            resolutions: Mutex::default(),
+            dirs: Mutex::default(),
+            files: AtomicUsize::new(0),
+        })
+    }
+
+    /// The config the backend `name` is created with, with its
+    /// dependencies resolved on first use. A backend whose
+    /// dependencies can't be resolved isn't retried.
+    pub(crate) fn resolve(
+        &self,
+        session: &Session,
+        name: &str,
+        derivation: &DerivationInfo,
+    ) -> Resolution {
+        let resolution = {
+            let mut resolutions = self
+                .resolutions
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            Arc::clone(resolutions.entry(name.to_string()).or_default())
+        };
+
+        resolution
+            .get_or_init(|| {
+                self.resolve_uncached(session, name, derivation)
+                    .map(Arc::new)
+                    .map_err(|err| {
+                        warn!("can't resolve the dependencies of {name}: {err}");
+                        err.to_string()
+                    })
+            })
+            .clone()
+    }
+
+    /// Remove the files holding dependencies.
+    pub(crate) fn remove_files(&self) {
+        let dirs = std::mem::take(
+            &mut *self
+                .dirs
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        );
+
+        for dir in dirs.into_values() {
+            if let Err(err) = std::fs::remove_dir_all(&dir) {
+                warn!("can't remove \"{}\": {err}", dir.display());
+            }
+        }
+    }
+
+    /// Write a dependency to a file only `owner` can read, or only
+    /// this process without one.
+    fn write_file(&self, content: &[u8], owner: Option<Owner>) -> std::io::Result<PathBuf> {
+        let chown = |path: &Path| match owner {
+            Some((uid, gid)) => std::os::unix::fs::chown(path, Some(uid), Some(gid)),
+            None => Ok(()),
+        };
+
+        let dir = {
+            let mut dirs = self
+                .dirs
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+            if let Some(dir) = dirs.get(&owner) {
+                dir.clone()
+            } else {
+                let created = self.config.secret_dir.join(format!(
+                    "{DIR_PREFIX}{}-{}",
+                    std::process::id(),
+                    RESOLUTIONS.fetch_add(1, Ordering::Relaxed)
+                ));
+                std::fs::create_dir_all(&self.config.secret_dir)?;
+                std::fs::DirBuilder::new().mode(0o700).create(&created)?;
+                dirs.insert(owner, created.clone());
+                chown(&created)?;
+                created
+            }
+        };
+
+        let file = self.files.fetch_add(1, Ordering::Relaxed) + 1;
+        let path = dir.join(format!("dependency-{file}"));
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        chown(&path)?;
+        file.write_all(content)?;
+
+        Ok(path)
+    }
+
+    /// Provision `dependency` for the backend `name`, through the
+    /// quorum guard like any other backend-to-backend fetch.
+    fn provision(
+        &self,
+        session: &Session,
+        name: &str,
+        dependency: &Dependency,
+        derivation: &DerivationInfo,
+    ) -> Result<Vec<u8>> {
+        // Dependencies aren't declared by derivations, so there's
+        // no hash to verify them against
+        let secret = Secret {
+            name: dependency.secret.clone(),
+            hash: String::new(),
+            backend_hint: None,
+            kind: SecretKind::Pinned,
+        };
+
+        backend::fetch(
+            &self.config,
+            session,
+            name,
+            &dependency.backend,
+            &secret,
+            derivation,
+        )
+        .map(|content| content.0)
+        .map_err(|source| Error::Dependency {
+            backend: dependency.backend.clone(),
+            secret: dependency.secret.clone(),
+            source,
+        })
+    }
+
+    fn resolve_uncached(
+        &self,
+        session: &Session,
+        name: &str,
+        derivation: &DerivationInfo,
+    ) -> Result<Config> {
+        let Some(backend_config) = self
+            .config
+            .backend_config
+            .as_ref()
+            .and_then(|backend_configs| backend_configs.get(name))
+        else {
+            return Err(Error::NoBackendConfig(name.to_string()));
+        };
+
+        let mut backend_config = backend_config.clone();
+        let dependencies =
+            match backend_config
+                .as_object_mut()
+                .and_then(|object| object.remove("dependencies"))
+            {
+                Some(dependencies) => serde_json::from_value::<Vec<Dependency>>(dependencies)
+                    .map_err(|source| Error::ParseDependencies {
+                        backend: name.to_string(),
+                        source,
+                    })?,
+                None => return Ok(self.config.clone()),
+            };
+
+        // Files handed to a sandboxed backend must be readable by
+        // its user
+        let sandbox = backend_config
+            .get("sandbox")
+            .and_then(|sandbox| serde_json::from_value::<SandboxConfig>(sandbox.clone()).ok());
+
+        for dependency in &dependencies {
+            debug!(
+                "{name} depends on \"{}\" from {}",
+                dependency.secret, dependency.backend
+            );
+            let content = self.provision(session, name, dependency, derivation)?;
+
+            let value = if dependency.inline {
+                String::from_utf8(content).map_err(|_| Error::Dependency {
+                    backend: dependency.backend.clone(),
+                    secret: dependency.secret.clone(),
+                    source: BackendError::InvalidResponse("not UTF-8".to_string()),
+                })?
+            } else {
+                let owner = sandbox
+                    .as_ref()
+                    .map(SandboxConfig::owner)
+                    .transpose()
+                    .map_err(|source| Error::Dependency {
+                        backend: dependency.backend.clone(),
+                        secret: dependency.secret.clone(),
+                        source,
+                    })?;
+                let path = self
+                    .write_file(&content, owner)
+                    .map_err(|source| Error::WriteDependency {
+                        secret: dependency.secret.clone(),
+                        source,
+                    })?
+                    .to_string_lossy()
+                    .into_owned();
+                allow_read(&mut backend_config, &path);
+                path
+            };
+
+            if !insert(&mut backend_config, &dependency.pointer, value.into()) {
+                return Err(Error::DependencyPointer {
+                    backend: name.to_string(),
+                    pointer: dependency.pointer.clone(),
+                });
+            }
+        }
+
+        let mut config = self.config.clone();
+        if let Some(backend_configs) = &mut config.backend_config {
+            backend_configs.insert(name.to_string(), backend_config);
+        }
+        Ok(config)
+    }
+}
+
+impl Drop for Dependencies {
+    fn drop(&mut self) {
+        self.remove_files();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dependencies;
+    use crate::backend::local::Local;
+    use crate::backend::{Backend, DerivationInfo};
+    use crate::error::Error;
+    use crate::test_support::{scratch_dir, secret, vault};
+    use crate::{Config, Session};
+    use std::collections::HashMap;
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::Arc;
+
+    #[test]
+    fn dependencies_are_passed_to_backends() {
+        let dir = std::env::temp_dir().join(format!("dependency-{}", std::process::id()));
+
+        // The inner vault's key is kept in the outer vault
+        let inner = vault(&dir.join("inner"));
+        inner.insert("db", b"hunter2").expect("insert");
+        let inner_key = std::fs::read(dir.join("inner/local.key")).expect("read key");
+        std::fs::remove_file(dir.join("inner/local.key")).expect("remove key");
+        vault(&dir.join("outer"))
+            .insert("inner-key", &inner_key)
+            .expect("insert key");
+
+        let config = Config {
+            secret_dir: dir.join("run"),
+            backend_config: Some(HashMap::from([
+                (
+                    "outer".to_string(),
+                    serde_json::json!({
+                        "kind": "local",
+                        "database": dir.join("outer/local.age"),
+                        "key": {"file": dir.join("outer/local.key")},
+                    }),
+                ),
+                (
+                    "inner".to_string(),
+                    serde_json::json!({
+                        "kind": "local",
+                        "database": dir.join("inner/local.age"),
+                        "dependencies": [
+                            {"backend": "outer", "secret": "inner-key", "pointer": "/key/file"},
+                            {
+                                "backend": "outer",
+                                "secret": "inner-key",
+                                "pointer": "/environment/KEY",
+                                "inline": true,
+                            },
+                        ],
+                    }),
+                ),
+                (
+                    "broken".to_string(),
+                    serde_json::json!({"kind": "local", "dependencies": "not a list"}),
+                ),
+            ])),
+            ..Config::default()
+        };
+
+        // "broken" is never used, so its dependencies aren't resolved
+        let derivation = DerivationInfo::default();
+        let session = Session::new(&config).expect("session");
+        let resolved = session.resolve("inner", &derivation).expect("resolve");
+        let inner = &resolved.backend_config.as_ref().expect("backends")["inner"];
+        assert_eq!(
+            inner["environment"]["KEY"],
+            String::from_utf8(inner_key).expect("utf-8")
+        );
+        assert!(inner.get("dependencies").is_none());
+
+        // Dependencies are resolved once per run
+        assert!(Arc::ptr_eq(
+            &resolved,
+            &session.resolve("inner", &derivation).expect("resolve")
+        ));
+
+        let key_file = inner["key"]["file"].as_str().expect("key file").to_string();
+        assert!(key_file.starts_with(&dir.join("run").to_string_lossy().into_owned()));
+        let backend = Local::new(&resolved, "inner").expect("backend");
+        assert_eq!(
+            backend
+                .provision(&secret("db"), &derivation)
+                .expect("provision")
+                .0,
+            b"hunter2"
+        );
+
+        drop(session);
+        assert!(!std::path::Path::new(&key_file).exists());
+
+        // A dependency guarded by a quorum can't be read around it
+        let mut guarded = config.clone();
+        guarded.backend_config.as_mut().expect("backends").insert(
+            "quorum".to_string(),
+            serde_json::json!({"instances": ["outer"], "secrets": ["inner-key"]}),
+        );
+        let session = Session::new(&guarded).expect("session");
+        assert!(session.resolve("inner", &derivation).is_err());
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn files_are_handed_to_sandboxed_backends() {
+        let dir = scratch_dir("dependency-sandbox");
+        vault(&dir.join("outer"))
+            .insert("sops-key", b"AGE-SECRET-KEY-1")
+            .expect("insert");
+        // SAFETY: getuid has no preconditions
+        let uid = unsafe { libc::getuid() };
+
+        let config = Config {
+            secret_dir: dir.join("run"),
+            backend_config: Some(HashMap::from([
+                (
+                    "outer".to_string(),
+                    serde_json::json!({
+                        "kind": "local",
+                        "database": dir.join("outer/local.age"),
+                        "key": {"file": dir.join("outer/local.key")},
+                    }),
+                ),
+                (
+                    "sops".to_string(),
+                    serde_json::json!({
+                        "sandbox": {"user": uid.to_string(), "readable_paths": ["/etc/sops.yaml"]},
+                        "dependencies": [{
+                            "backend": "outer",
+                            "secret": "sops-key",
+                            "pointer": "/environment/SOPS_AGE_KEY_FILE",
+                        }],
+                    }),
+                ),
+            ])),
+            ..Config::default()
+        };
+
+        let session = Session::new(&config).expect("session");
+        let resolved = session
+            .resolve("sops", &DerivationInfo::default())
+            .expect("resolve");
+        let sops = &resolved.backend_config.as_ref().expect("backends")["sops"];
+        let key_file = sops["environment"]["SOPS_AGE_KEY_FILE"]
+            .as_str()
+            .expect("key file");
+
+        assert_eq!(
+            sops["sandbox"]["readable_paths"],
+            serde_json::json!(["/etc/sops.yaml", key_file])
+        );
+        let key_file = std::path::Path::new(key_file);
+        for (path, mode) in [(key_file, 0o600), (key_file.parent().expect("dir"), 0o700)] {
+            let metadata = std::fs::metadata(path).expect("metadata");
+            assert_eq!(metadata.uid(), uid);
+            assert_eq!(metadata.mode() & 0o777, mode);
+        }
+
+        session.close();
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn malformed_dependencies_are_reported() {
+        let config = Config {
+            backend_config: Some(HashMap::from([(
+                "broken".to_string(),
+                serde_json::json!({"kind": "local", "dependencies": "not a list"}),
+            )])),
+            ..Config::default()
+        };
+        let session = Session::new(&config).expect("session");
+        let dependencies = Dependencies::new(&config).expect("dependencies");
+
+        assert!(matches!(
+            dependencies.resolve_uncached(&session, "broken", &DerivationInfo::default()),
+            Err(Error::ParseDependencies { backend, .. }) if backend == "broken"
+        ));
+    }
+
+    #[test]
+    fn stale_dependencies_are_removed() {
+        let dir = std::env::temp_dir().join(format!("stale-{}", std::process::id()));
+        let stale = dir.join(format!("buildtime-secrets-{}-0", u32::MAX));
+        let running = dir.join(format!("buildtime-secrets-{}-0", std::process::id()));
+        let derivation = dir.join("aaaa-fetch");
+        for path in [&stale, &running, &derivation] {
+            std::fs::create_dir_all(path).expect("create dir");
+        }
+
+        let config = Config {
+            secret_dir: dir.clone(),
+            ..Config::default()
+        };
+        Session::new(&config).expect("session");
+
+        assert!(!stale.exists());
+        assert!(running.exists());
+        assert!(derivation.exists());
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        let depends_on = |backend: &str| {
+            serde_json::json!({
+                "kind": "local",
+                "dependencies": [{"backend": backend, "secret": "key", "pointer": "/key/file"}],
+            })
+        };
+        let config = Config {
+            backend_config: Some(HashMap::from([
+                ("a".to_string(), depends_on("b")),
+                ("b".to_string(), depends_on("a")),
+            ])),
+            ..Config::default()
+        };
+
+        assert!(matches!(
+            Session::new(&config),
+            Err(Error::DependencyCycle(cycle)) if cycle == ["a", "b", "a"]
+        ));
+
+        // Including through a derived backend's master key
+        let config = Config {
+            backend_config: Some(HashMap::from([
+                ("a".to_string(), depends_on("derived")),
+                (
+                    "derived".to_string(),
+                    serde_json::json!({
+                        "master": {"backend": "a", "secret": "master"},
+                        "secrets": {"key": {}},
+                    }),
+                ),
+            ])),
+            ..Config::default()
+        };
+
+        assert!(matches!(
+            Session::new(&config),
+            Err(Error::DependencyCycle(cycle)) if cycle == ["a", "derived", "a"]
+        ));
+    }
+}
@@ -1,6 +1,8 @@
 use crate::Secret;
 use std::io;
 use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -10,21 +12,59 @@ pub enum Error {
     ParseSecret(serde_json::Error),
     NoConfigForBackends,
     NoBackendConfig(String),
-    NoSuccessfulBackends(Secret),
-    CreateSecretFile { path: PathBuf, source: io::Error },
-    WriteSecret { path: PathBuf, source: io::Error },
+    NoSuccessfulBackends {
+        secret: Secret,
+        failures: Vec<(String, BackendError)>,
+    },
+    CreateSecretFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    WriteSecret {
+        path: PathBuf,
+        source: io::Error,
+    },
     StorePathIsNotDerivation,
-    CreateDrvSecretDir { path: PathBuf, source: io::Error },
+    CreateDrvSecretDir {
+        path: PathBuf,
+        source: io::Error,
+    },
+    DeadlineExceeded(Secret),
+    ProvisionPanicked(Secret),
+    InvalidSecretName(String),
+    ProvisionFailures(Vec<Error>),
+    EphemeralNotAllowed(String),
+    EphemeralNotFixedOutput(String),
+    Dependency {
+        backend: String,
+        secret: String,
+        source: BackendError,
+    },
+    DependencyCycle(Vec<String>),
+    ParseDependencies {
+        backend: String,
+        source: serde_json::Error,
+    },
+    DependencyPointer {
+        backend: String,
+        pointer: String,
+    },
+    WriteDependency {
+        secret: String,
+        source: io::Error,
+    },
 }
 
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::NixError(source) => Some(source),
-            Error::ParseSecret(source) => Some(source),
+            Error::ParseSecret(source) | Error::ParseDependencies { source, .. } => Some(source),
             Error::CreateSecretFile { source, .. }
             | Error::WriteSecret { source, .. }
-            | Error::CreateDrvSecretDir { source, .. } => Some(source),
+            | Error::CreateDrvSecretDir { source, .. }
+            | Error::WriteDependency { source, .. } => Some(source),
+            Error::Dependency { source, .. } => Some(source),
             _ => None,
         }
     }
@@ -40,8 +80,17 @@ impl std::fmt::Display for Error {
                     format!("an error occurred while interfacing with nix: {source}"),
                 Error::ParseSecret(source) => format!("failed to parse secret: {source}"),
                 Error::NoConfigForBackends => "no \"backend_config\" in config".to_string(),
-                Error::NoSuccessfulBackends(secret) =>
+                Error::NoSuccessfulBackends { secret, failures } if failures.is_empty() =>
                     format!("no backends could decrypt the secret \"{}\"", secret.name),
+                Error::NoSuccessfulBackends { secret, failures } => format!(
+                    "no backends could decrypt the secret \"{}\": {}",
+                    secret.name,
+                    failures
+                        .iter()
+                        .map(|(backend, failure)| format!("{backend}: {failure}"))
+                        .collect::<Vec<_>>()
+                        .join("; ")
+                ),
                 Error::NoBackendConfig(backend) => format!("no backend config for {backend}"),
                 Error::CreateSecretFile { path, source } => format!(
                     "can't create secret file \"{}\": {source}",
@@ -56,6 +105,42 @@ impl std::fmt::Display for Error {
                     "can't create derivation secret directory \"{}\": {source}",
                     path.to_string_lossy()
                 ),
+                Error::InvalidSecretName(name) =>
+                    format!("secret name \"{name}\" escapes the secret directory"),
+                Error::EphemeralNotAllowed(name) =>
+                    format!("secret \"{name}\" isn't allowed to be ephemeral"),
+                Error::EphemeralNotFixedOutput(name) => format!(
+                    "secret \"{name}\" is ephemeral, which only fixed-output derivations may use"
+                ),
+                Error::Dependency {
+                    backend,
+                    secret,
+                    source,
+                } =>
+                    format!("can't provision the dependency \"{secret}\" from {backend}: {source}"),
+                Error::DependencyCycle(cycle) =>
+                    format!("backends depend on each other: {}", cycle.join(" -> ")),
+                Error::ParseDependencies { backend, source } =>
+                    format!("can't parse the dependencies of {backend}: {source}"),
+                Error::DependencyPointer { backend, pointer } =>
+                    format!("can't write a dependency to \"{pointer}\" in the {backend} config"),
+                Error::WriteDependency { secret, source } =>
+                    format!("can't write the dependency \"{secret}\": {source}"),
+                Error::DeadlineExceeded(secret) => format!(
+                    "deadline exceeded before the secret \"{}\" was provisioned",
+                    secret.name
+                ),
+                Error::ProvisionPanicked(secret) =>
+                    format!("provisioning the secret \"{}\" panicked", secret.name),
+                Error::ProvisionFailures(errors) => format!(
+                    "{} secrets failed to provision: {}",
+                    errors.len(),
+                    errors
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("; ")
+                ),
             }
         )
     }
@@ -72,3 +157,76 @@ impl From<serde_json::Error> for Error {
         Error::ParseSecret(value)
     }
 }
+
+/// Reasons a backend failed to provision a secret.
+#[derive(Debug)]
+pub enum BackendError {
+    Spawn(io::Error),
+    Timeout(Duration),
+    OutputTooLarge(u64),
+    CommandFailed { status: ExitStatus, stderr: String },
+    CircuitOpen,
+    Sandbox(String),
+    NotFound,
+    Rejected(String),
+    Expired(String),
+    InvalidResponse(String),
+    CoprocessExited(String),
+    Template(String),
+    BadKey(String),
+    Decrypt(String),
+    Http(String),
+    HashMismatch,
+    InvalidHash(String),
+    Disagreement(Vec<Vec<String>>),
+}
+
+impl std::error::Error for BackendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BackendError::Spawn(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{}",
+            match self {
+                BackendError::Spawn(source) => format!("failed to run backend command: {source}"),
+                BackendError::Timeout(timeout) =>
+                    format!("backend command timed out after {}s", timeout.as_secs()),
+                BackendError::OutputTooLarge(limit) =>
+                    format!("backend command wrote more than {limit} bytes"),
+                BackendError::CommandFailed { status, stderr } =>
+                    format!("backend command failed ({status}): {stderr}"),
+                BackendError::CircuitOpen => "backend skipped after repeated timeouts".to_string(),
+                BackendError::Sandbox(msg) => format!("failed to sandbox backend command: {msg}"),
+                BackendError::NotFound => "secret not found".to_string(),
+                BackendError::Rejected(msg) => format!("backend reported an error: {msg}"),
+                BackendError::Expired(msg) => format!("secret has expired: {msg}"),
+                BackendError::InvalidResponse(msg) => format!("invalid backend response: {msg}"),
+                BackendError::CoprocessExited(backend) =>
+                    format!("co-process for {backend} keeps exiting"),
+                BackendError::Template(msg) => format!("failed to render template: {msg}"),
+                BackendError::BadKey(msg) => format!("unusable key: {msg}"),
+                BackendError::Decrypt(msg) => format!("failed to decrypt secret: {msg}"),
+                BackendError::Http(msg) => format!("request failed: {msg}"),
+                BackendError::HashMismatch => "secret doesn't match its declared hash".to_string(),
+                BackendError::Disagreement(groups) => format!(
+                    "instances provisioned different values: {}",
+                    groups
+                        .iter()
+                        .map(|group| format!("[{}]", group.join(", ")))
+                        .collect::<Vec<_>>()
+                        .join(" vs ")
+                ),
+                BackendError::InvalidHash(hash) =>
+                    format!("unsupported hash \"{hash}\", expected \"sha256-<base64>\""),
+            }
+        )
+    }
+}
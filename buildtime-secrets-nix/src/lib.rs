@@ -3,23 +3,35 @@
 
 pub mod backend;
 pub mod config;
+mod dependency;
 pub mod error;
+mod pool;
 pub mod secret;
+pub mod session;
+#[cfg(test)]
+mod test_support;
 
 pub use config::Config;
 pub use error::Error;
 pub use secret::Secret;
+pub use session::Session;
 
-use backend::BackendKind;
-use error::Result;
+use backend::{BackendKind, DerivationInfo};
+use error::{BackendError, Result};
 use libnixstore::Store;
-use secret::{ProvisionedSecret, SecretContent};
+use pool::Outcome;
+use secret::{ProvisionedSecret, SecretContent, SecretKind};
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
-use tracing::{debug, warn};
+use std::num::NonZeroUsize;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 
-const BACKEND_KINDS: [BackendKind; 2] = [BackendKind::Sops, BackendKind::Executable];
+/// The tracing target of audit records, which are logged
+/// whatever the log level.
+pub const AUDIT_TARGET: &str = "audit";
 
 /// The context used when provisioning a derivations
 /// declared secrets.
@@ -27,6 +39,8 @@ pub struct Provisioner<'a> {
     config: &'a Config,
     store: libnixstore::Store,
     derivation: libnixstore::StorePath,
+    derivation_info: DerivationInfo,
+    session: Arc<Session>,
 }
 
 impl<'a> Provisioner<'a> {
@@ -36,7 +50,8 @@ impl<'a> Provisioner<'a> {
     /// # Errors
     ///
     /// If we can't parse the derivation path or get
-    /// the derivation name.
+    /// the derivation name, or if backends fetch secrets
+    /// from each other in a cycle.
     pub fn new(config: &'a Config) -> Result<Self> {
         let store = Store::new()?;
 
@@ -47,10 +62,19 @@ impl<'a> Provisioner<'a> {
         let derivation_name = store.derivation_name(&derivation)?;
         debug!("derivation name: {}", derivation_name);
 
+        let fixed_output = store.is_fixed_output(&derivation)?;
+        debug!("fixed-output: {fixed_output}");
+
         Ok(Self {
             config,
             store,
             derivation,
+            derivation_info: DerivationInfo {
+                path: config.derivation.clone(),
+                name: derivation_name,
+                fixed_output,
+            },
+            session: Arc::new(Session::new(config)?),
         })
     }
 
@@ -68,66 +92,33 @@ impl<'a> Provisioner<'a> {
         Ok(secret_dir)
     }
 
-    /// Attempt to provision a secret using a specific backend
-    /// returning the contents if successful.
-    ///
-    /// # Errors
-    ///
-    /// If the backend kind can't be instantiated.
-    fn try_provision(
-        &self,
-        backend_kind: BackendKind,
-        secret: &Secret,
-    ) -> Result<Option<SecretContent>> {
-        if !backend::validate_config(backend_kind, self.config) {
-            return Ok(None);
-        }
-
-        Ok(backend::create(backend_kind, self.config)?.provision(secret))
-    }
-
-    /// Provision a secret. This method will enumerate
-    /// backends until one is successful.
+    /// Provision a secret. This method will enumerate backends
+    /// until one is successful.
     ///
     /// # Errors
     ///
     /// If no backends can successfully decrypt the secret.
     pub fn provision<'s>(&self, secret: &'s Secret) -> Result<ProvisionedSecret<'s>> {
-        debug!("provisioning secret: {:?}", secret);
-
-        if let Some(backend_hint) = secret.backend_hint {
-            debug!("found backend hint, trying backend {:?}", backend_hint);
-            if let Some(content) = self.try_provision(backend_hint, secret)? {
-                // TODO: verify hash
-                return self.write_secret_content(secret, content);
-            }
-        }
-
-        for backend_kind in BACKEND_KINDS {
-            if let Some(backend_hint) = secret.backend_hint
-                && backend_hint == backend_kind
-            {
-                continue;
-            }
-
-            if let Some(content) = self.try_provision(backend_kind, secret)? {
-                // TODO: verify hash
-                return self.write_secret_content(secret, content);
-            }
-        }
-
-        Err(Error::NoSuccessfulBackends(secret.clone()))
+        let content =
+            fetch_secret_content(self.config, &self.session, &self.derivation_info, secret)?;
+        self.write_secret_content(secret, content)
     }
 
     /// Provision all the secrets required by the derivation. This method
     /// reads the "requiredSecrets" field of the derivation environment
     /// containing secret declarations.
     ///
+    /// Secrets are fetched concurrently, at most `max_concurrency` at a
+    /// time, and must all be fetched before `deadline_secs` elapses. They
+    /// are then written out in declaration order.
+    ///
     /// # Errors
     ///
     /// If the "requiredSecrets" field contains secret declarations that
-    /// are unparsable.
+    /// are unparsable, or if any secret fails to provision. When more than one secret fails,
+    /// every failure is reported in declaration order.
     pub fn provision_all(&self) -> Result<()> {
+        let started = Instant::now();
         let required_secrets = self.required_secrets()?;
 
         let Some(required_secrets_serialized) = required_secrets else {
@@ -137,13 +128,55 @@ impl<'a> Provisioner<'a> {
 
         debug!("requiredSecrets: {}", required_secrets_serialized);
 
-        for serialized_secret in required_secrets_serialized.split(' ') {
-            let secret: Secret =
-                serde_json::from_str(serialized_secret).map_err(Error::ParseSecret)?;
-            self.provision(&secret)?;
+        let secrets = required_secrets_serialized
+            .split(' ')
+            .map(serde_json::from_str::<Secret>)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Error::ParseSecret)?;
+
+        let deadline = self
+            .config
+            .deadline_secs
+            .map(|secs| started + Duration::from_secs(secs));
+
+        let config = Arc::new(self.config.clone());
+        let session = Arc::clone(&self.session);
+        let derivation_info = Arc::new(self.derivation_info.clone());
+        let fetched = pool::run_bounded(
+            secrets.clone(),
+            self.concurrency_limit(),
+            deadline,
+            move |secret| fetch_secret_content(&config, &session, &derivation_info, &secret),
+        );
+
+        let mut errors = Vec::new();
+
+        for (secret, fetched) in secrets.iter().zip(fetched) {
+            let result = match fetched {
+                Outcome::Finished(content) => {
+                    content.and_then(|content| self.write_secret_content(secret, content))
+                }
+                Outcome::Panicked => Err(Error::ProvisionPanicked(secret.clone())),
+                Outcome::TimedOut => Err(Error::DeadlineExceeded(secret.clone())),
+            };
+
+            if let Err(err) = result {
+                warn!("failed to provision secret \"{}\": {err}", secret.name);
+                errors.push(err);
+            }
+        }
+
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(Error::ProvisionFailures(errors)),
         }
+    }
 
-        Ok(())
+    fn concurrency_limit(&self) -> NonZeroUsize {
+        self.config
+            .max_concurrency
+            .unwrap_or_else(|| std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN))
     }
 
     /// Fetch the "requiredSecrets" field from the derivations environment.
@@ -183,20 +216,430 @@ impl<'a> Provisioner<'a> {
     }
 
     fn allocate_decrypted_file_path(&self, secret: &Secret) -> Result<PathBuf> {
-        let secret_dir = self.derivation_secret_directory()?;
+        let secret_file = secret_file_path(&self.derivation_secret_directory()?, secret)?;
 
-        if !secret_dir.exists()
-            && let Err(source) = std::fs::create_dir_all(&secret_dir)
+        if let Some(parent) = secret_file.parent()
+            && !parent.exists()
+            && let Err(source) = std::fs::create_dir_all(parent)
         {
             return Err(Error::CreateDrvSecretDir {
-                path: secret_dir,
+                path: parent.to_path_buf(),
                 source,
             });
         }
 
-        let mut secret_file = secret_dir;
-        secret_file.push(&secret.name);
-
         Ok(secret_file)
     }
 }
+
+/// The path `secret` is written to inside `secret_dir`. Names may
+/// be nested, like "web/github", but must stay inside the secret
+/// directory.
+fn secret_file_path(secret_dir: &Path, secret: &Secret) -> Result<PathBuf> {
+    check_secret_name(secret)?;
+    Ok(secret_dir.join(&secret.name))
+}
+
+/// Check the secret's name is relative and free of `.` and `..`
+/// components.
+///
+/// # Errors
+///
+/// If the name could escape the secret directory.
+fn check_secret_name(secret: &Secret) -> Result<()> {
+    let is_contained = Path::new(&secret.name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    if secret.name.is_empty() || !is_contained {
+        return Err(Error::InvalidSecretName(secret.name.clone()));
+    }
+
+    Ok(())
+}
+
+impl Drop for Provisioner<'_> {
+    /// Shut down the co-processes started while provisioning, and
+    /// remove the files holding dependencies.
+    fn drop(&mut self) {
+        self.session.close();
+    }
+}
+
+/// Attempt to provision a secret using a specific backend
+/// instance returning the contents if successful, or `None`
+/// if the instance isn't correctly configured. The secrets
+/// its config depends on are resolved the first time it's
+/// tried. The contents of pinned secrets are checked against
+/// the declared hash, and backends that can't reproduce them
+/// aren't asked for them at all.
+///
+/// # Errors
+///
+/// If the backend kind can't be instantiated.
+fn try_provision(
+    session: &Session,
+    backend_kind: BackendKind,
+    backend_name: &str,
+    derivation: &DerivationInfo,
+    secret: &Secret,
+) -> Result<Option<std::result::Result<SecretContent, BackendError>>> {
+    if secret.kind == SecretKind::Pinned && !backend_kind.is_reproducible() {
+        return Ok(Some(Err(BackendError::Rejected(format!(
+            "{backend_name} can only provision ephemeral secrets"
+        )))));
+    }
+
+    let config = match session.resolve(backend_name, derivation) {
+        Ok(config) => config,
+        Err(err) => return Ok(Some(Err(BackendError::Rejected(err)))),
+    };
+
+    if !backend::validate_config(backend_kind, backend_name, &config) {
+        return Ok(None);
+    }
+
+    let backend = backend::create(backend_kind, backend_name, &config, session)?;
+    let content = backend.provision(secret, derivation);
+
+    if secret.kind == SecretKind::Ephemeral {
+        debug!("\"{}\" is ephemeral, not verifying its hash", secret.name);
+        return Ok(Some(content));
+    }
+
+    Ok(Some(content.and_then(|content| {
+        secret.verify(&content)?;
+        Ok(content)
+    })))
+}
+
+/// Check an ephemeral secret may be provisioned: it must be
+/// allowed by the config, and the derivation's output must
+/// be pinned instead.
+///
+/// # Errors
+///
+/// If the secret isn't allowed to be ephemeral.
+fn check_ephemeral(config: &Config, derivation: &DerivationInfo, secret: &Secret) -> Result<()> {
+    if !config.ephemeral_secrets.contains(&secret.name) {
+        return Err(Error::EphemeralNotAllowed(secret.name.clone()));
+    }
+
+    if !derivation.fixed_output {
+        return Err(Error::EphemeralNotFixedOutput(secret.name.clone()));
+    }
+
+    Ok(())
+}
+
+/// Fetch the content of a secret, enumerating backend
+/// instances until one is successful. Instances of the
+/// hinted backend kind are tried first.
+///
+/// # Errors
+///
+/// If no backends can successfully decrypt the secret, or
+/// the secret can't be ephemeral.
+fn fetch_secret_content(
+    config: &Config,
+    session: &Session,
+    derivation: &DerivationInfo,
+    secret: &Secret,
+) -> Result<SecretContent> {
+    debug!("provisioning secret: {:?}", secret);
+    check_secret_name(secret)?;
+
+    if secret.kind == SecretKind::Ephemeral {
+        check_ephemeral(config, derivation, secret)?;
+    }
+
+    let mut instances = backend::instances_for(config, secret);
+
+    if let Some(backend_hint) = secret.backend_hint {
+        debug!("found backend hint, trying backend {:?}", backend_hint);
+        instances.sort_by_key(|(backend_kind, _)| *backend_kind != backend_hint);
+    }
+
+    let mut failures = Vec::new();
+
+    for (backend_kind, backend_name) in instances {
+        match try_provision(session, backend_kind, &backend_name, derivation, secret)? {
+            Some(Ok(content)) => {
+                if secret.kind == SecretKind::Ephemeral {
+                    info!(
+                        target: AUDIT_TARGET,
+                        secret = secret.name,
+                        derivation = derivation.path,
+                        backend = backend_name,
+                        "provisioned ephemeral secret without verifying its hash"
+                    );
+                }
+                return Ok(content);
+            }
+            Some(Err(BackendError::NotFound)) => {
+                debug!("backend {backend_name} doesn't have \"{}\"", secret.name);
+            }
+            Some(Err(err)) => {
+                debug!(
+                    "backend {backend_name} failed to provision \"{}\": {err}",
+                    secret.name
+                );
+                failures.push((backend_name, err));
+            }
+            None => {}
+        }
+    }
+
+    Err(Error::NoSuccessfulBackends {
+        secret: secret.clone(),
+        failures,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fetch_secret_content, secret_file_path, try_provision};
+    use crate::backend::derived::Derived;
+    use crate::backend::generated::Generated;
+    use crate::backend::{BackendKind, DerivationInfo};
+    use crate::error::{BackendError, Error};
+    use crate::secret::{Secret, SecretKind};
+    use crate::test_support::{credential, fake_cli, scratch_dir, secret, vault};
+    use crate::{Config, Session};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    #[test]
+    fn secret_names_stay_inside_the_secret_directory() {
+        let dir = Path::new("/run/buildtime-secrets/aaaa-fetch");
+
+        assert_eq!(
+            secret_file_path(dir, &secret("web/github")).expect("nested name"),
+            dir.join("web/github")
+        );
+
+        for name in [
+            "",
+            "../escape",
+            "web/../../escape",
+            "/etc/shadow",
+            "./token",
+        ] {
+            assert!(
+                matches!(
+                    secret_file_path(dir, &secret(name)),
+                    Err(Error::InvalidSecretName(_))
+                ),
+                "{name:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_names_are_refused_before_any_backend() {
+        let dir = scratch_dir("invalid-name");
+        let marker = dir.join("queried");
+        let probe = fake_cli(&dir, "probe", &format!("touch {}", marker.display()));
+
+        let config = Config {
+            backend_config: Some(HashMap::from([(
+                "executable".to_string(),
+                serde_json::json!({"file": probe}),
+            )])),
+            ..Config::default()
+        };
+        let session = Session::new(&config).expect("session");
+
+        assert!(matches!(
+            fetch_secret_content(
+                &config,
+                &session,
+                &DerivationInfo::default(),
+                &secret("../escape")
+            ),
+            Err(Error::InvalidSecretName(name)) if name == "../escape"
+        ));
+        assert!(!marker.exists());
+
+        session.close();
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn regenerated_secrets_provision_with_their_hash() {
+        let dir = scratch_dir("regenerated");
+        let config = Config {
+            backend_config: Some(HashMap::from([(
+                "generated".to_string(),
+                serde_json::json!({
+                    "database": dir.join("generated.age"),
+                    "key": {"file": dir.join("generated.key")},
+                    "secrets": {"salt": {"format": "hex"}},
+                }),
+            )])),
+            ..Config::default()
+        };
+        let session = Session::new(&config).expect("session");
+        let derivation = DerivationInfo::default();
+
+        let hash = Generated::new(&config, "generated")
+            .expect("backend")
+            .regenerate("salt")
+            .expect("regenerate");
+        let pinned = Secret {
+            hash,
+            ..secret("salt")
+        };
+        let content =
+            fetch_secret_content(&config, &session, &derivation, &pinned).expect("provision");
+        assert_eq!(content.0.len(), 64);
+
+        // Regenerating changes the hash the secret is declared by
+        Generated::new(&config, "generated")
+            .expect("backend")
+            .regenerate("salt")
+            .expect("regenerate");
+        assert!(matches!(
+            fetch_secret_content(&config, &session, &derivation, &pinned),
+            Err(Error::NoSuccessfulBackends { .. })
+        ));
+
+        session.close();
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn derived_secrets_provision_with_their_hash() {
+        let dir = scratch_dir("derived-hash");
+        vault(&dir).insert("master", b"s3cret").expect("insert");
+        let config = Config {
+            backend_config: Some(HashMap::from([
+                (
+                    "keys".to_string(),
+                    serde_json::json!({
+                        "kind": "local",
+                        "database": dir.join("local.age"),
+                        "key": {"file": dir.join("local.key")},
+                    }),
+                ),
+                (
+                    "derived".to_string(),
+                    serde_json::json!({
+                        "master": {"backend": "keys", "secret": "master"},
+                        "secrets": {"cache-key": {"per_derivation": true}},
+                    }),
+                ),
+            ])),
+            ..Config::default()
+        };
+        let session = Session::new(&config).expect("session");
+        let derivation = |name: &str| DerivationInfo {
+            name: name.to_string(),
+            ..DerivationInfo::default()
+        };
+
+        let backend = Derived::new(&config, "derived", &session).expect("backend");
+        assert!(matches!(
+            backend.hash("cache-key", None),
+            Err(BackendError::Rejected(_))
+        ));
+        let pinned = Secret {
+            hash: backend.hash("cache-key", Some("hello")).expect("hash"),
+            ..secret("cache-key")
+        };
+        let provision = |drv: &str| {
+            try_provision(
+                &session,
+                BackendKind::Derived,
+                "derived",
+                &derivation(drv),
+                &pinned,
+            )
+            .expect("backend")
+            .expect("configured")
+        };
+
+        assert!(provision("hello").is_ok());
+        assert!(matches!(
+            provision("goodbye"),
+            Err(BackendError::HashMismatch)
+        ));
+
+        session.close();
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn ephemeral_secrets_are_restricted() {
+        let dir = std::env::temp_dir().join(format!("ephemeral-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        credential(&dir.join("jwt.key"), "s3cret");
+
+        let mut config = Config {
+            backend_config: Some(HashMap::from([(
+                "mint".to_string(),
+                serde_json::json!({
+                    "credentials": {
+                        "api-token": {"jwt": {"key": dir.join("jwt.key"), "algorithm": "HS256"}},
+                    },
+                }),
+            )])),
+            ..Config::default()
+        };
+        let mut derivation = DerivationInfo {
+            path: "/nix/store/aaaa-fetch.drv".to_string(),
+            name: "fetch".to_string(),
+            fixed_output: true,
+        };
+        let session = Session::new(&config).expect("session");
+        let secret = |kind| Secret {
+            name: "api-token".to_string(),
+            hash: String::new(),
+            backend_hint: None,
+            kind,
+        };
+
+        assert!(matches!(
+            fetch_secret_content(
+                &config,
+                &session,
+                &derivation,
+                &secret(SecretKind::Ephemeral)
+            ),
+            Err(Error::EphemeralNotAllowed(_))
+        ));
+
+        config.ephemeral_secrets = vec!["api-token".to_string()];
+        assert!(
+            fetch_secret_content(
+                &config,
+                &session,
+                &derivation,
+                &secret(SecretKind::Ephemeral)
+            )
+            .is_ok()
+        );
+
+        // A minted token can never match a pinned hash, so mint
+        // isn't asked for one, which would fail without its key
+        std::fs::remove_file(dir.join("jwt.key")).expect("remove key");
+        assert!(matches!(
+            fetch_secret_content(&config, &session, &derivation, &secret(SecretKind::Pinned)),
+            Err(Error::NoSuccessfulBackends { failures, .. })
+                if matches!(failures[..], [(_, BackendError::Rejected(_))])
+        ));
+
+        derivation.fixed_output = false;
+        assert!(matches!(
+            fetch_secret_content(
+                &config,
+                &session,
+                &derivation,
+                &secret(SecretKind::Ephemeral)
+            ),
+            Err(Error::EphemeralNotFixedOutput(_))
+        ));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
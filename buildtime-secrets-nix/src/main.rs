@@ -1,8 +1,12 @@
 #![warn(clippy::all)]
 #![warn(clippy::pedantic)]
 
-use buildtime_secrets_nix::Provisioner;
-use std::io::Write;
+use buildtime_secrets_nix::backend::derived::Derived;
+use buildtime_secrets_nix::backend::generated::Generated;
+use buildtime_secrets_nix::backend::local::LocalVault;
+use buildtime_secrets_nix::error::BackendError;
+use buildtime_secrets_nix::{AUDIT_TARGET, Provisioner, Session};
+use std::io::{IsTerminal, Read, Write};
 use std::sync::Mutex;
 use tracing::{Subscriber, debug, warn};
 use tracing_subscriber::{
@@ -11,6 +15,11 @@ use tracing_subscriber::{
 
 pub const DEFAULT_LOG_FILE: &str = "/var/log/buildtime-secrets-nix/log";
 
+const VAULT_USAGE: &str = "usage: buildtime-secrets-nix vault [--backend <name>] \
+    (add <name> | rm <name> | list | rotate-key)
+       buildtime-secrets-nix regenerate [--backend <name>] <name>
+       buildtime-secrets-nix hash [--backend <name>] [--derivation <name>] <name>";
+
 #[derive(thiserror::Error, Debug)]
 enum Error {
     #[error("cannot read CONFIG_FILE environment variable: {0}")]
@@ -27,6 +36,21 @@ enum Error {
 
     #[error("error during provisioning: {0}")]
     ProvisionSecrets(#[from] buildtime_secrets_nix::Error),
+
+    #[error("{VAULT_USAGE}")]
+    VaultUsage,
+
+    #[error("cannot open vault: {0}")]
+    OpenVault(buildtime_secrets_nix::Error),
+
+    #[error("cannot read secret from stdin: {0}")]
+    ReadStdin(std::io::Error),
+
+    #[error("no secret named \"{0}\" in the vault")]
+    NoSuchSecret(String),
+
+    #[error("{0}")]
+    Vault(#[from] BackendError),
 }
 
 // Hijack the error reporting system!!
@@ -53,6 +77,28 @@ where
 }
 
 fn main() {
+    tracing_subscriber::registry()
+        .with(build_log_file_layer())
+        .init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+
+    // Subcommands are run by an admin, not by nix
+    let subcommand = match args.get(1).map(String::as_str) {
+        Some("vault") => Some(run_vault as fn(&[String]) -> Result<(), Error>),
+        Some("regenerate") => Some(run_regenerate as fn(&[String]) -> Result<(), Error>),
+        Some("hash") => Some(run_hash as fn(&[String]) -> Result<(), Error>),
+        _ => None,
+    };
+
+    if let Some(subcommand) = subcommand {
+        if let Err(err) = subcommand(&args[2..]) {
+            eprintln!("buildtime-secrets-nix: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     match run() {
         Ok(()) => {}
         Err(err) => {
@@ -100,22 +146,112 @@ where
             tracing_subscriber::fmt::layer()
                 .with_writer(Mutex::new(log_file))
                 .with_ansi(false)
-                .with_filter(EnvFilter::from_default_env())
+                .with_filter(
+                    EnvFilter::from_default_env().add_directive(
+                        format!("{AUDIT_TARGET}=info")
+                            .parse()
+                            .expect("audit directive is valid"),
+                    ),
+                )
                 .boxed()
         })
         .ok()
 }
 
-fn run() -> Result<(), Error> {
-    tracing_subscriber::registry()
-        .with(build_log_file_layer())
-        .init();
-
+fn read_config() -> Result<buildtime_secrets_nix::Config, Error> {
     let config_path = std::env::var("CONFIG_FILE")?;
     debug!("reading config file at {config_path:?}");
 
     let config_string = std::fs::read_to_string(&config_path)?;
-    let mut config: buildtime_secrets_nix::Config = serde_json::from_str(&config_string)?;
+    Ok(serde_json::from_str(&config_string)?)
+}
+
+/// Read a secret from stdin, so it never appears in argv.
+fn read_secret() -> Result<Vec<u8>, Error> {
+    let mut stdin = std::io::stdin().lock();
+    let mut secret = Vec::new();
+    stdin.read_to_end(&mut secret).map_err(Error::ReadStdin)?;
+
+    // Typed secrets end with the newline before ^D
+    if stdin.is_terminal() && secret.ends_with(b"\n") {
+        secret.pop();
+    }
+
+    Ok(secret)
+}
+
+/// Split off a leading `--backend <name>`.
+fn backend_arg<'a>(args: &'a [String], default: &'a str) -> (&'a str, &'a [String]) {
+    match args {
+        [flag, backend_name, args @ ..] if flag == "--backend" => (backend_name.as_str(), args),
+        args => (default, args),
+    }
+}
+
+fn run_regenerate(args: &[String]) -> Result<(), Error> {
+    let (backend_name, args) = backend_arg(args, "generated");
+    let [name] = args else {
+        return Err(Error::VaultUsage);
+    };
+
+    let config = read_config()?;
+    let generated = Generated::new(&config, backend_name).map_err(Error::OpenVault)?;
+    println!("{}", generated.regenerate(name)?);
+
+    Ok(())
+}
+
+/// Print the hash of a derived secret, which no one ever sees.
+fn run_hash(args: &[String]) -> Result<(), Error> {
+    let (backend_name, args) = backend_arg(args, "derived");
+    let (derivation, args) = match args {
+        [flag, derivation, args @ ..] if flag == "--derivation" => {
+            (Some(derivation.as_str()), args)
+        }
+        args => (None, args),
+    };
+    let [name] = args else {
+        return Err(Error::VaultUsage);
+    };
+
+    let config = read_config()?;
+    let session = Session::new(&config)?;
+    let hash = Derived::new(&config, backend_name, &session)
+        .map_err(Error::from)
+        .and_then(|derived| Ok(derived.hash(name, derivation)?));
+    session.close();
+    println!("{}", hash?);
+
+    Ok(())
+}
+
+fn run_vault(args: &[String]) -> Result<(), Error> {
+    let (backend_name, args) = backend_arg(args, "local");
+
+    let config = read_config()?;
+    let vault = LocalVault::open(&config, backend_name).map_err(Error::OpenVault)?;
+
+    match args {
+        [command, name] if command == "add" => vault.insert(name, &read_secret()?)?,
+        [command, name] if command == "rm" => {
+            if !vault.remove(name)? {
+                return Err(Error::NoSuchSecret(name.clone()));
+            }
+        }
+        [command] if command == "list" => {
+            for name in vault.list()? {
+                println!("{name}");
+            }
+        }
+        [command] if command == "rotate-key" => vault.rotate_key()?,
+        _ => return Err(Error::VaultUsage),
+    }
+
+    Ok(())
+}
+
+fn run() -> Result<(), Error> {
+    let mut config = read_config()?;
 
     let mut args = std::env::args();
     let args_len = args.len();
@@ -0,0 +1,160 @@
+use std::num::NonZeroUsize;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// What became of an item run by `run_bounded`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Outcome<R> {
+    Finished(R),
+    /// `f` panicked on the item.
+    Panicked,
+    /// The item didn't finish before the deadline.
+    TimedOut,
+}
+
+/// Run `f` over every item using at most `limit` worker threads.
+///
+/// Outcomes are returned in the same order as `items`. Items that
+/// didn't finish before `deadline` time out; workers that are
+/// still busy at that point are left to run detached, their
+/// results are discarded. A panic in `f` is caught, so its worker
+/// carries on with the next item.
+pub(crate) fn run_bounded<T, R, F>(
+    items: Vec<T>,
+    limit: NonZeroUsize,
+    deadline: Option<Instant>,
+    f: F,
+) -> Vec<Outcome<R>>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let item_count = items.len();
+    let mut results: Vec<Option<Outcome<R>>> =
+        std::iter::repeat_with(|| None).take(item_count).collect();
+
+    if item_count == 0 {
+        return Vec::new();
+    }
+
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (sender, receiver) = mpsc::channel();
+
+    for _ in 0..limit.get().min(item_count) {
+        let queue = Arc::clone(&queue);
+        let f = Arc::clone(&f);
+        let sender = sender.clone();
+
+        std::thread::spawn(move || {
+            loop {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    break;
+                }
+
+                // Don't hold the lock while running `f`
+                let next = queue.lock().map(|mut queue| queue.next());
+                let Ok(Some((index, item))) = next else {
+                    break;
+                };
+
+                let outcome = match std::panic::catch_unwind(AssertUnwindSafe(|| f(item))) {
+                    Ok(result) => Outcome::Finished(result),
+                    Err(_) => Outcome::Panicked,
+                };
+
+                if sender.send((index, outcome)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // Only the workers hold senders now, so the channel disconnects
+    // once they have all finished
+    drop(sender);
+
+    let mut finished = 0;
+    while finished < item_count {
+        let next = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                receiver.recv_timeout(remaining).ok()
+            }
+            None => receiver.recv().ok(),
+        };
+
+        let Some((index, outcome)) = next else {
+            break;
+        };
+
+        results[index] = Some(outcome);
+        finished += 1;
+    }
+
+    // Items left over were abandoned by workers stopping at the
+    // deadline, or were still running when it passed
+    results
+        .into_iter()
+        .map(|outcome| outcome.unwrap_or(Outcome::TimedOut))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Outcome, run_bounded};
+    use std::num::NonZeroUsize;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn results_keep_item_order() {
+        let limit = NonZeroUsize::new(4).expect("non zero");
+
+        let results = run_bounded((0..16u64).collect(), limit, None, |n| {
+            std::thread::sleep(Duration::from_millis(16 - n));
+            n * 2
+        });
+
+        assert_eq!(
+            results,
+            (0..16u64)
+                .map(|n| Outcome::Finished(n * 2))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn unfinished_items_past_deadline_time_out() {
+        let limit = NonZeroUsize::new(2).expect("non zero");
+        let deadline = Instant::now() + Duration::from_millis(200);
+
+        let results = run_bounded(vec![0, 10_000], limit, Some(deadline), |ms| {
+            std::thread::sleep(Duration::from_millis(ms));
+            ms
+        });
+
+        assert_eq!(results, vec![Outcome::Finished(0), Outcome::TimedOut]);
+    }
+
+    #[test]
+    fn panics_are_reported_without_a_deadline() {
+        let limit = NonZeroUsize::new(1).expect("non zero");
+
+        let results = run_bounded(vec![1, 0, 2], limit, None, |n| {
+            assert!(n != 0, "boom");
+            n
+        });
+
+        assert_eq!(
+            results,
+            vec![
+                Outcome::Finished(1),
+                Outcome::Panicked,
+                Outcome::Finished(2)
+            ]
+        );
+    }
+}
@@ -1,13 +1,35 @@
 use crate::backend::BackendKind;
+use crate::error::BackendError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::path::PathBuf;
 
+/// Whether a secret is pinned by its declared hash.
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretKind {
+    /// The secret must match `Secret.hash`.
+    #[default]
+    Pinned,
+    /// The secret changes every time it's provisioned, like a
+    /// short-lived token, so it isn't verified. Only allowed for
+    /// fixed-output derivations, whose output is still pinned.
+    Ephemeral,
+}
+
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Secret {
     pub name: String,
+    #[serde(default)]
     pub hash: String,
     pub backend_hint: Option<BackendKind>,
+    #[serde(default)]
+    pub kind: SecretKind,
 }
 
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
@@ -26,6 +48,14 @@ impl std::fmt::Debug for SecretContent {
     }
 }
 
+impl SecretContent {
+    /// The SHA-256 SRI hash derivations declare the content by.
+    #[must_use]
+    pub fn sri_hash(&self) -> String {
+        format!("sha256-{}", STANDARD.encode(Sha256::digest(&self.0)))
+    }
+}
+
 impl AsRef<[u8]> for SecretContent {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -37,3 +67,54 @@ impl AsRef<Secret> for Secret {
         self
     }
 }
+
+impl Secret {
+    /// Check `content` against the declared hash, an SRI hash
+    /// like `sha256-<base64>`.
+    ///
+    /// # Errors
+    ///
+    /// If the hash isn't a SHA-256 SRI hash or doesn't match.
+    pub fn verify(&self, content: &SecretContent) -> Result<(), BackendError> {
+        let expected = self
+            .hash
+            .strip_prefix("sha256-")
+            .and_then(|digest| STANDARD.decode(digest).ok())
+            .ok_or_else(|| BackendError::InvalidHash(self.hash.clone()))?;
+
+        if Sha256::digest(&content.0).as_slice() == expected {
+            Ok(())
+        } else {
+            Err(BackendError::HashMismatch)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Secret, SecretContent, SecretKind};
+    use crate::error::BackendError;
+
+    #[test]
+    fn verifies_sri_hashes() {
+        let secret = |hash: &str| Secret {
+            name: "token".to_string(),
+            hash: hash.to_string(),
+            backend_hint: None,
+            kind: SecretKind::Pinned,
+        };
+        // echo -n hunter2 | sha256sum
+        let pinned = secret("sha256-9S+9MrKzuG/4jvbEkGKChfSCrxXdyylUH5S89Saj9sc=");
+
+        assert!(pinned.verify(&SecretContent(b"hunter2".to_vec())).is_ok());
+        assert_eq!(SecretContent(b"hunter2".to_vec()).sri_hash(), pinned.hash);
+        assert!(matches!(
+            pinned.verify(&SecretContent(b"hunter3".to_vec())),
+            Err(BackendError::HashMismatch)
+        ));
+        assert!(matches!(
+            secret("md5-AAAA").verify(&SecretContent(b"hunter2".to_vec())),
+            Err(BackendError::InvalidHash(_))
+        ));
+    }
+}
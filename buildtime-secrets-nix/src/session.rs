@@ -0,0 +1,66 @@
+use crate::Config;
+use crate::backend::DerivationInfo;
+use crate::backend::coprocess::Coprocesses;
+use crate::backend::keepass::Databases;
+use crate::backend::vault::Tokens;
+use crate::dependency::Dependencies;
+use crate::error::Result;
+use std::sync::Arc;
+
+/// What the backends provisioning a derivation's secrets share
+/// for the length of a hook run.
+pub struct Session {
+    coprocesses: Coprocesses,
+    dependencies: Dependencies,
+    databases: Databases,
+    vault_tokens: Tokens,
+}
+
+impl Session {
+    /// Start a hook run provisioning secrets with `config`.
+    ///
+    /// # Errors
+    ///
+    /// If backends fetch secrets from each other in a cycle.
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Session {
+            coprocesses: Coprocesses::default(),
+            dependencies: Dependencies::new(config)?,
+            databases: Databases::default(),
+            vault_tokens: Tokens::default(),
+        })
+    }
+
+    /// The co-processes started by executable backends.
+    pub(crate) fn coprocesses(&self) -> &Coprocesses {
+        &self.coprocesses
+    }
+
+    /// The databases unlocked by `KeePass` backends.
+    pub(crate) fn databases(&self) -> &Databases {
+        &self.databases
+    }
+
+    /// The tokens Vault backends logged in for.
+    pub(crate) fn vault_tokens(&self) -> &Tokens {
+        &self.vault_tokens
+    }
+
+    /// The config the backend instance `name` is created with,
+    /// with the secrets its config depends on resolved the first
+    /// time it's used.
+    pub(crate) fn resolve(
+        &self,
+        name: &str,
+        derivation: &DerivationInfo,
+    ) -> std::result::Result<Arc<Config>, String> {
+        self.dependencies.resolve(self, name, derivation)
+    }
+
+    /// Shut down everything the session's backends started, and
+    /// remove the files holding dependencies.
+    pub fn close(&self) {
+        self.coprocesses.shutdown_all();
+        self.dependencies.remove_files();
+    }
+}
@@ -0,0 +1,216 @@
+use crate::Config;
+use crate::backend::local::LocalVault;
+use crate::secret::{Secret, SecretKind};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::JoinHandle;
+
+const KEY_UID: &str = "buildtime-secrets-test@example.invalid";
+
+pub(crate) fn secret(name: &str) -> Secret {
+    Secret {
+        name: name.to_string(),
+        hash: String::new(),
+        backend_hint: None,
+        kind: SecretKind::Pinned,
+    }
+}
+
+fn gpg(home: &Path) -> Command {
+    let mut cmd = Command::new("gpg");
+    cmd.args(["--batch", "--quiet", "--pinentry-mode", "loopback"])
+        .env("GNUPGHOME", home);
+    cmd
+}
+
+/// A throwaway gpg keyring holding a single passphraseless key,
+/// removed when dropped.
+pub(crate) struct GpgKeyring(pub(crate) PathBuf);
+
+impl GpgKeyring {
+    pub(crate) fn new(name: &str) -> Self {
+        // Kept short, gpg-agent socket paths are length limited
+        let home = std::env::temp_dir().join(format!("g{}{name}", std::process::id()));
+        std::fs::create_dir_all(&home).expect("create GNUPGHOME");
+        std::fs::set_permissions(&home, std::os::unix::fs::PermissionsExt::from_mode(0o700))
+            .expect("chmod GNUPGHOME");
+
+        let status = gpg(&home)
+            .args(["--passphrase", "", "--quick-gen-key", KEY_UID])
+            .args(["default", "default", "never"])
+            .status()
+            .expect("run gpg");
+        assert!(status.success(), "failed to generate a key");
+
+        Self(home)
+    }
+
+    pub(crate) fn encrypt(&self, plaintext: &[u8], path: &Path) {
+        let mut child = gpg(&self.0)
+            .args([
+                "--trust-model",
+                "always",
+                "--encrypt",
+                "--recipient",
+                KEY_UID,
+            ])
+            .arg("--output")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .expect("run gpg");
+        child
+            .stdin
+            .take()
+            .expect("stdin")
+            .write_all(plaintext)
+            .expect("write plaintext");
+        assert!(child.wait().expect("wait").success(), "failed to encrypt");
+    }
+
+    pub(crate) fn delete_secret_keys(&self) {
+        let fingerprint = String::from_utf8(
+            gpg(&self.0)
+                .args(["--with-colons", "--list-secret-keys", KEY_UID])
+                .output()
+                .expect("run gpg")
+                .stdout,
+        )
+        .expect("utf8")
+        .lines()
+        .find_map(|line| line.strip_prefix("fpr:::::::::"))
+        .map(|fpr| fpr.trim_end_matches(':').to_string())
+        .expect("fingerprint");
+
+        let status = gpg(&self.0)
+            .args(["--yes", "--delete-secret-keys", &fingerprint])
+            .status()
+            .expect("run gpg");
+        assert!(status.success(), "failed to delete secret key");
+    }
+}
+
+impl Drop for GpgKeyring {
+    fn drop(&mut self) {
+        let _ = Command::new("gpgconf")
+            .args(["--kill", "gpg-agent"])
+            .env("GNUPGHOME", &self.0)
+            .status();
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Create an empty directory for a test's files, unique to
+/// this test run.
+pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create scratch dir");
+    dir
+}
+
+/// Write a fake CLI named `name` into `dir`.
+pub(crate) fn fake_cli(dir: &Path, name: &str, script: &str) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("create dir");
+    let path = dir.join(name);
+    let mut file = std::fs::File::create(&path).expect("create cli");
+    writeln!(file, "#!/bin/sh\n{script}").expect("write cli");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).expect("chmod cli");
+    path
+}
+
+/// Write a credential file only the owner can read.
+pub(crate) fn credential(path: &Path, contents: &str) {
+    std::fs::write(path, contents).expect("write credential");
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).expect("chmod");
+}
+
+/// A vault in `dir` keyed by a file.
+pub(crate) fn vault(dir: &Path) -> LocalVault {
+    let config = Config {
+        backend_config: Some(HashMap::from([(
+            "local".to_string(),
+            serde_json::json!({
+                "database": dir.join("local.age"),
+                "key": {"file": dir.join("local.key")},
+            }),
+        )])),
+        ..Config::default()
+    };
+
+    LocalVault::open(&config, "local").expect("open vault")
+}
+
+/// A request received by [`serve`].
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) line: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Answer one connection per response, in order, returning
+/// the address listened on and the requests received.
+pub(crate) fn serve(responses: Vec<(u16, String)>) -> (String, JoinHandle<Vec<Request>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = format!("http://{}", listener.local_addr().expect("address"));
+
+    let handle = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+
+        for (status, body) in responses {
+            let (stream, _) = listener.accept().expect("accept");
+            let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("request line");
+
+            let mut headers = Vec::new();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).expect("header");
+                let Some((key, value)) = header.trim_end().split_once(':') else {
+                    break;
+                };
+                headers.push((key.trim().to_string(), value.trim().to_string()));
+            }
+
+            let mut request = Request {
+                line: line.trim_end().to_string(),
+                headers,
+                body: Vec::new(),
+            };
+            let length = request
+                .header("content-length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or(0);
+            request.body.resize(length, 0);
+            reader.read_exact(&mut request.body).expect("body");
+            requests.push(request);
+
+            write!(
+                &stream,
+                "HTTP/1.1 {status} X\r\ncontent-type: application/json\r\n\
+                 content-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .expect("respond");
+        }
+
+        requests
+    });
+
+    (address, handle)
+}
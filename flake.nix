@@ -55,8 +55,13 @@
                 llvmPackages.clang
                 boost.dev
                 sops
+                gnupg
+                softhsm
               ];
 
+              # For the PKCS#11 backend's test, run with `--ignored`
+              SOFTHSM2_MODULE = "${pkgs.softhsm}/lib/softhsm/libsofthsm2.so";
+
               RUST_LOG = "debug";
             };
 
@@ -7,6 +7,7 @@
 
 namespace wrap {
 enum class NixErrorTag : std::uint8_t;
+struct AccessToken;
 class StorePath;
 
 class LocalStore {
@@ -20,6 +21,7 @@ public:
   rust::String get_derivation_env_val(std::shared_ptr<StorePath> path,
                                       rust::Str key) const;
   rust::String get_derivation_name(std::shared_ptr<StorePath> path) const;
+  bool get_derivation_is_fixed_output(std::shared_ptr<StorePath> path) const;
   rust::String get_store_relative_path(std::shared_ptr<StorePath> path) const;
 
 private:
@@ -50,4 +52,6 @@ public:
 
 std::unique_ptr<LocalStore> new_local_store();
 void init_lib_nix_store();
+rust::String get_netrc_file();
+rust::Vec<AccessToken> get_access_tokens();
 } // namespace wrap
@@ -4,7 +4,8 @@
 pub mod error;
 
 use error::{Error, Result};
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Once;
 use tracing::instrument;
 
@@ -18,10 +19,17 @@ mod ffi {
         EnvKeyDoesNotExist,
     }
 
+    struct AccessToken {
+        host: String,
+        token: String,
+    }
+
     unsafe extern "C++" {
         include!("libnixstore/include/nix-wrap.hh");
 
         fn init_lib_nix_store() -> Result<()>;
+        fn get_netrc_file() -> Result<String>;
+        fn get_access_tokens() -> Result<Vec<AccessToken>>;
 
         type StorePath;
 
@@ -35,6 +43,10 @@ mod ffi {
             key: &str,
         ) -> Result<String>;
         fn get_derivation_name(self: &LocalStore, path: SharedPtr<StorePath>) -> Result<String>;
+        fn get_derivation_is_fixed_output(
+            self: &LocalStore,
+            path: SharedPtr<StorePath>,
+        ) -> Result<bool>;
         fn get_store_relative_path(self: &LocalStore, path: SharedPtr<StorePath>)
         -> Result<String>;
     }
@@ -49,6 +61,50 @@ impl StorePath {
     }
 }
 
+/// Initialize libnixstore, which also loads the nix configuration.
+///
+/// # Panics
+///
+/// If `initLibStore()` from libnixstore throws an exception
+fn initialize() {
+    INITIALIZE_LIBNIXSTORE.call_once(|| {
+        ffi::init_lib_nix_store().expect("initLibStore() failed with an exception");
+    });
+}
+
+/// Fetch the `netrc-file` nix setting.
+///
+/// # Errors
+///
+/// If nix throws an exception.
+///
+/// # Panics
+///
+/// If `initLibStore()` from libnixstore throws an exception
+#[instrument]
+pub fn netrc_file() -> Result<PathBuf> {
+    initialize();
+    Ok(PathBuf::from(ffi::get_netrc_file()?))
+}
+
+/// Fetch the `access-tokens` nix setting, tokens keyed by host.
+///
+/// # Errors
+///
+/// If nix throws an exception.
+///
+/// # Panics
+///
+/// If `initLibStore()` from libnixstore throws an exception
+#[instrument]
+pub fn access_tokens() -> Result<HashMap<String, String>> {
+    initialize();
+    Ok(ffi::get_access_tokens()?
+        .into_iter()
+        .map(|ffi::AccessToken { host, token }| (host, token))
+        .collect())
+}
+
 impl Store {
     /// Create a new instance of `Store` which is a handle on the local
     /// nix store.
@@ -63,9 +119,7 @@ impl Store {
     /// If `initLibStore()` from libnixstore throws an exception
     #[tracing::instrument]
     pub fn new() -> Result<Self> {
-        INITIALIZE_LIBNIXSTORE.call_once(|| {
-            ffi::init_lib_nix_store().expect("initLibStore() failed with an exception");
-        });
+        initialize();
 
         Ok(Self(ffi::new_local_store()?))
     }
@@ -128,6 +182,18 @@ impl Store {
         Ok(self.0.get_derivation_name(path)?)
     }
 
+    /// Check whether a derivation is fixed-output, so its output
+    /// is pinned by a declared hash.
+    ///
+    /// # Errors
+    ///
+    /// If nix throws an exception.
+    #[instrument(skip_all)]
+    pub fn is_fixed_output(&self, drv_path: &StorePath) -> Result<bool> {
+        let path = drv_path.inner();
+        Ok(self.0.get_derivation_is_fixed_output(path)?)
+    }
+
     /// Fetch the "store relative" path of the object
     /// referenced by `store_path`
     ///
@@ -143,9 +209,21 @@ impl Store {
 
 #[cfg(test)]
 mod tests {
-    use super::Store;
+    use super::{Store, access_tokens, netrc_file};
     use crate::error::Error;
 
+    #[test]
+    fn read_netrc_file_setting() {
+        let netrc_file = netrc_file().expect("netrc_file");
+
+        assert!(netrc_file.ends_with("netrc"));
+    }
+
+    #[test]
+    fn read_access_tokens_setting() {
+        assert!(access_tokens().is_ok());
+    }
+
     #[test]
     fn create_local_store() {
         let res = Store::new();
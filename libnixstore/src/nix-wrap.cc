@@ -1,4 +1,7 @@
+#include <nix/cmd/common-eval-args.hh>
+#include <nix/fetchers/fetch-settings.hh>
 #include <nix/main/shared.hh>
+#include <nix/store/derivations.hh>
 #include <nix/store/globals.hh>
 #include <nix/store/path.hh>
 
@@ -9,6 +12,17 @@
 namespace wrap {
 void init_lib_nix_store() { nix::initLibStore(); }
 
+rust::String get_netrc_file() { return nix::settings.netrcFile.get(); }
+
+rust::Vec<AccessToken> get_access_tokens() {
+  rust::Vec<AccessToken> tokens;
+
+  for (const auto &[host, token] : nix::fetchSettings.accessTokens.get())
+    tokens.push_back(AccessToken{host, token});
+
+  return tokens;
+}
+
 std::shared_ptr<StorePath>
 LocalStore::parse_store_path(rust::Slice<const std::uint8_t> path) const {
   auto path_string =
@@ -61,6 +75,13 @@ LocalStore::get_derivation_name(std::shared_ptr<StorePath> path) const {
   return derivation.name;
 }
 
+bool LocalStore::get_derivation_is_fixed_output(
+    std::shared_ptr<StorePath> path) const {
+  nix::Derivation derivation =
+      store->readDerivation(path->valid_path_info.path);
+  return derivation.type().isFixed();
+}
+
 rust::String LocalStore::get_version() const {
   auto version = store->getVersion();
 
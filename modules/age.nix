@@ -0,0 +1,39 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.age;
+in
+{
+  options.buildtimeSecrets.age = {
+    enable = lib.mkEnableOption "the age backend";
+
+    identityFiles = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = map (key: key.path) (
+        lib.filter (key: key.type == "ed25519") config.services.openssh.hostKeys
+      );
+      defaultText = lib.literalExpression "the ed25519 SSH host keys";
+    };
+
+    secretDirectory = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "A directory of agenix style `<name>.age` files.";
+    };
+
+    secrets = lib.mkOption {
+      type = lib.types.attrsOf lib.types.path;
+      default = { };
+      description = "Encrypted files keyed by secret name.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.age = {
+        identity_files = cfg.identityFiles;
+        secret_dir = cfg.secretDirectory;
+        inherit (cfg) secrets;
+      };
+    };
+  };
+}
@@ -0,0 +1,81 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.aws;
+in
+{
+  options.buildtimeSecrets.aws = {
+    enable = lib.mkEnableOption "the AWS Secrets Manager and SSM backend";
+
+    service = lib.mkOption {
+      type = lib.types.enum [
+        "secrets_manager"
+        "ssm"
+      ];
+      default = "secrets_manager";
+    };
+
+    region = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "Read from the profile in `configFile` if unset.";
+    };
+
+    endpoint = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "http://localhost:4566";
+    };
+
+    profile = lib.mkOption {
+      type = lib.types.str;
+      default = "default";
+    };
+
+    credentialsFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "/root/.aws/credentials";
+    };
+
+    configFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "/root/.aws/config";
+    };
+
+    prefix = lib.mkOption {
+      type = lib.types.str;
+      default = "";
+      description = "Prepended to every secret name but ARNs.";
+    };
+
+    versionStage = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+
+    versionStages = lib.mkOption {
+      type = lib.types.attrsOf lib.types.str;
+      default = { };
+      description = "Version stages, or SSM parameter labels, keyed by secret name.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.aws = {
+        inherit (cfg)
+          service
+          region
+          endpoint
+          profile
+          prefix
+          ;
+        credentials_file = cfg.credentialsFile;
+        config_file = cfg.configFile;
+        version_stage = cfg.versionStage;
+        version_stages = cfg.versionStages;
+      };
+    };
+  };
+}
@@ -0,0 +1,42 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.bitwarden;
+in
+{
+  options.buildtimeSecrets.bitwarden = {
+    enable = lib.mkEnableOption "the Bitwarden CLI backend";
+
+    package = lib.mkOption {
+      type = lib.types.package;
+      example = lib.literalExpression "pkgs.bitwarden-cli";
+      description = "The package providing `bw`, the hook's PATH doesn't have it.";
+    };
+
+    sessionFile = lib.mkOption {
+      type = lib.types.str;
+      description = "A file holding the session key from `bw unlock --raw`.";
+    };
+
+    appdataDir = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "The CLI's data directory, where it was logged in.";
+    };
+
+    field = lib.mkOption {
+      type = lib.types.str;
+      default = "password";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.bitwarden = {
+        inherit (cfg) field;
+        program = lib.getExe' cfg.package "bw";
+        session_file = cfg.sessionFile;
+        appdata_dir = cfg.appdataDir;
+      };
+    };
+  };
+}
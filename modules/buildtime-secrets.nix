@@ -14,11 +14,34 @@ let
   cfg = config.buildtimeSecrets;
 
   hookName = lib.getName perSystem.config.packages.default;
-  backendTools = [ pkgs.sops ];
+  backendTools = [
+    pkgs.sops
+    pkgs.gnupg
+  ];
+
+  configFile = pkgs.writeText "${hookName}-config.json" (builtins.toJSON cfg.config);
 in
 {
   imports = [
+    ./age.nix
+    ./aws.nix
+    ./bitwarden.nix
+    ./derived.nix
+    ./generated.nix
+    ./gpg.nix
+    ./http.nix
+    ./keepass.nix
+    ./keyring.nix
+    ./local.nix
+    ./mint.nix
+    ./nix-settings.nix
+    ./onepassword.nix
+    ./pass.nix
+    ./pkcs11.nix
+    ./quorum.nix
     ./sops.nix
+    ./systemd-creds.nix
+    ./vault.nix
   ];
 
   options.buildtimeSecrets = {
@@ -29,14 +52,27 @@ in
       default = "/run/buildtime-secrets";
     };
 
+    ephemeralSecrets = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [ ];
+      description = ''
+        Secrets that fixed-output derivations may declare with
+        `kind = "ephemeral"`, for tokens that change every time and so
+        can't be pinned by their hash.
+      '';
+    };
+
     config = lib.mkOption {
-      type = lib.types.attrs;
+      # Merged recursively, so each backend module can add its own
+      # entry to `backend_config`
+      type = (pkgs.formats.json { }).type;
     };
   };
 
   config = lib.mkIf cfg.enable {
     buildtimeSecrets.config = {
       secret_dir = cfg.secretDirectory;
+      ephemeral_secrets = cfg.ephemeralSecrets;
     };
 
     nix.settings = {
@@ -52,8 +88,22 @@ in
               --set RUST_LOG "debug" \
               --prefix PATH : ${lib.makeBinPath backendTools} \
               --set LOG_FILE "/var/log/buildtime-secrets/log" \
-              --set CONFIG_FILE "${pkgs.writeText "${hookName}-config.json" (builtins.toJSON cfg.config)}"
+              --set CONFIG_FILE "${configFile}"
           '';
     };
+
+    # For the `vault` subcommands, e.g. `buildtime-secrets vault list`
+    environment.systemPackages = [
+      (pkgs.runCommand "buildtime-secrets"
+        {
+          nativeBuildInputs = [ pkgs.makeWrapper ];
+        }
+        ''
+          makeWrapper ${lib.getExe perSystem.config.packages.default} $out/bin/buildtime-secrets \
+            --set LOG_FILE "/var/log/buildtime-secrets/log" \
+            --set CONFIG_FILE "${configFile}"
+        ''
+      )
+    ];
   };
 }
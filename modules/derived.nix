@@ -0,0 +1,74 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.derived;
+
+  outputType = lib.types.submodule {
+    options = {
+      length = lib.mkOption {
+        type = lib.types.ints.between 1 8160;
+        default = 32;
+        description = "Bytes derived, before encoding.";
+      };
+
+      encoding = lib.mkOption {
+        type = lib.types.enum [
+          "hex"
+          "base64"
+          "bytes"
+        ];
+        default = "hex";
+      };
+
+      perDerivation = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = "Derive a different value for each derivation, by name.";
+      };
+    };
+  };
+in
+{
+  options.buildtimeSecrets.derived = {
+    enable = lib.mkEnableOption "secrets derived from a master key with HKDF-SHA256";
+
+    master = {
+      backend = lib.mkOption {
+        type = lib.types.str;
+        example = "local";
+        description = "The backend instance holding the master key.";
+      };
+
+      secret = lib.mkOption {
+        type = lib.types.str;
+        description = "The name of the master key secret.";
+      };
+    };
+
+    salt = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+
+    secrets = lib.mkOption {
+      type = lib.types.attrsOf outputType;
+      default = { };
+      description = ''
+        The secrets derived, keyed by name. `buildtime-secrets hash <name>`
+        prints the hash derivations declare each one by, given
+        `--derivation <name>` for those derived per derivation.
+      '';
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.derived = {
+        inherit (cfg) master salt;
+        secrets = lib.mapAttrs (_: output: {
+          inherit (output) length encoding;
+          per_derivation = output.perDerivation;
+        }) cfg.secrets;
+      };
+    };
+  };
+}
@@ -0,0 +1,69 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.generated;
+
+  generatorType = lib.types.submodule {
+    options = {
+      length = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 32;
+        description = "Characters, or random bytes for the hex, base64 and bytes formats.";
+      };
+
+      format = lib.mkOption {
+        type = lib.types.enum [
+          "alphanumeric"
+          "hex"
+          "base64"
+          "bytes"
+        ];
+        default = "alphanumeric";
+      };
+
+      alphabet = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        description = "Pick characters from this alphabet instead of using `format`.";
+      };
+    };
+  };
+in
+{
+  options.buildtimeSecrets.generated = {
+    enable = lib.mkEnableOption "secrets generated on first use, rotated with `buildtime-secrets regenerate`";
+
+    database = lib.mkOption {
+      type = lib.types.str;
+      default = "/var/lib/buildtime-secrets/generated.age";
+    };
+
+    keyFile = lib.mkOption {
+      type = lib.types.str;
+      default = "/var/lib/buildtime-secrets/generated.key";
+    };
+
+    secrets = lib.mkOption {
+      type = lib.types.attrsOf generatorType;
+      default = { };
+      description = ''
+        The secrets generated, keyed by name. Create each one with
+        `buildtime-secrets regenerate <name>`, which prints the hash
+        derivations declare it by.
+      '';
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.generated = {
+        inherit (cfg) database;
+        key.file = cfg.keyFile;
+        secrets = lib.mapAttrs (_: generator: {
+          inherit (generator) length;
+          format =
+            if generator.alphabet != null then { alphabet = generator.alphabet; } else generator.format;
+        }) cfg.secrets;
+      };
+    };
+  };
+}
@@ -0,0 +1,42 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.gpg;
+in
+{
+  options.buildtimeSecrets.gpg = {
+    enable = lib.mkEnableOption "the gpg backend";
+
+    gnupgHome = lib.mkOption {
+      type = lib.types.str;
+      description = "The GNUPGHOME holding the decryption keys.";
+    };
+
+    passphraseFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+
+    secretDirectory = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "A directory of `<name>.gpg` files.";
+    };
+
+    secrets = lib.mkOption {
+      type = lib.types.attrsOf lib.types.path;
+      default = { };
+      description = "Encrypted files keyed by secret name.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.gpg = {
+        gnupg_home = cfg.gnupgHome;
+        passphrase_file = cfg.passphraseFile;
+        secret_dir = cfg.secretDirectory;
+        inherit (cfg) secrets;
+      };
+    };
+  };
+}
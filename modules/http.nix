@@ -0,0 +1,96 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.http;
+in
+{
+  options.buildtimeSecrets.http = lib.mkOption {
+    default = { };
+    description = "HTTP backends, keyed by backend name.";
+    type = lib.types.attrsOf (
+      lib.types.submodule {
+        options = {
+          url = lib.mkOption {
+            type = lib.types.str;
+            example = "https://secrets.internal/v1/{name}";
+          };
+
+          method = lib.mkOption {
+            type = lib.types.enum [
+              "GET"
+              "POST"
+              "PUT"
+            ];
+            default = "GET";
+          };
+
+          headers = lib.mkOption {
+            type = lib.types.attrsOf lib.types.str;
+            default = { };
+          };
+
+          body = lib.mkOption {
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+            description = ''
+              The request body template. Placeholder values are JSON string
+              escaped when the `Content-Type` header is a JSON type.
+            '';
+          };
+
+          bearerTokenFile = lib.mkOption {
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+            description = "A root owned file holding a bearer token.";
+          };
+
+          pointer = lib.mkOption {
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+            example = "/data/value";
+            description = "A JSON pointer selecting the secret from the response.";
+          };
+
+          notFoundStatuses = lib.mkOption {
+            type = lib.types.listOf lib.types.int;
+            default = [ 404 ];
+          };
+
+          caFile = lib.mkOption {
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+
+          clientCert = lib.mkOption {
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+
+          clientKey = lib.mkOption {
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+          };
+        };
+      }
+    );
+  };
+
+  config = lib.mkIf (cfg != { }) {
+    buildtimeSecrets.config = {
+      backend_config = lib.mapAttrs (_: backend: {
+        kind = "http";
+        inherit (backend)
+          url
+          method
+          headers
+          body
+          pointer
+          ;
+        bearer_token_file = backend.bearerTokenFile;
+        not_found_statuses = backend.notFoundStatuses;
+        ca_file = backend.caFile;
+        client_cert = backend.clientCert;
+        client_key = backend.clientKey;
+      }) cfg;
+    };
+  };
+}
@@ -0,0 +1,47 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.keepass;
+in
+{
+  options.buildtimeSecrets.keepass = {
+    enable = lib.mkEnableOption "the KeePass backend";
+
+    database = lib.mkOption {
+      type = lib.types.str;
+      description = "The `.kdbx` database file.";
+    };
+
+    passwordFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+
+    keyFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+
+    field = lib.mkOption {
+      type = lib.types.str;
+      default = "Password";
+      description = "The field provisioned when the secret name doesn't select one.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    assertions = [
+      {
+        assertion = cfg.passwordFile != null || cfg.keyFile != null;
+        message = "buildtimeSecrets.keepass needs a passwordFile, a keyFile or both";
+      }
+    ];
+
+    buildtimeSecrets.config = {
+      backend_config.keepass = {
+        inherit (cfg) database field;
+        password_file = cfg.passwordFile;
+        key_file = cfg.keyFile;
+      };
+    };
+  };
+}
@@ -0,0 +1,41 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.keyring;
+in
+{
+  options.buildtimeSecrets.keyring = {
+    enable = lib.mkEnableOption "the Linux kernel keyring backend";
+
+    keyring = lib.mkOption {
+      type = lib.types.enum [
+        "user"
+        "session"
+        "user_session"
+        "persistent"
+      ];
+      default = "user";
+      description = "The keyring searched, unless `namedKeyring` is set.";
+    };
+
+    namedKeyring = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "Search the keyring with this description instead.";
+    };
+
+    prefix = lib.mkOption {
+      type = lib.types.str;
+      default = "buildsecret:";
+      description = "Prepended to the secret name to form the key description.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.keyring = {
+        keyring = if cfg.namedKeyring != null then { named = cfg.namedKeyring; } else cfg.keyring;
+        inherit (cfg) prefix;
+      };
+    };
+  };
+}
@@ -0,0 +1,39 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.local;
+in
+{
+  options.buildtimeSecrets.local = {
+    enable = lib.mkEnableOption "the local vault, managed with `buildtime-secrets vault`";
+
+    database = lib.mkOption {
+      type = lib.types.str;
+      default = "/var/lib/buildtime-secrets/local.age";
+    };
+
+    keyFile = lib.mkOption {
+      type = lib.types.str;
+      default = "/var/lib/buildtime-secrets/local.key";
+      description = "The vault key, created along with the vault.";
+    };
+
+    keyringKey = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = ''
+        Read the vault key from the `user` key with this description in
+        root's user keyring rather than `keyFile`. It must be added again
+        after every boot.
+      '';
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.local = {
+        inherit (cfg) database;
+        key = if cfg.keyringKey != null then { keyring = cfg.keyringKey; } else { file = cfg.keyFile; };
+      };
+    };
+  };
+}
@@ -0,0 +1,117 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.mint;
+
+  certificateType = lib.types.submodule {
+    options = {
+      caCertificate = lib.mkOption {
+        type = lib.types.str;
+        description = "The PEM encoded CA certificate.";
+      };
+
+      caKeyFile = lib.mkOption {
+        type = lib.types.str;
+        description = "The PEM encoded PKCS#8 CA key, readable only by root.";
+      };
+
+      subject = lib.mkOption {
+        type = lib.types.attrsOf lib.types.str;
+        example = {
+          CN = "builder";
+          O = "Example";
+        };
+        description = ''
+          Subject attributes, one of `CN`, `O`, `L`, `ST` or `C`. The
+          organizational unit is the store path of the derivation.
+        '';
+      };
+
+      dnsNames = lib.mkOption {
+        type = lib.types.listOf lib.types.str;
+        default = [ ];
+      };
+
+      validity = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 3600;
+        description = "Seconds each certificate is valid for.";
+      };
+    };
+  };
+
+  jwtType = lib.types.submodule {
+    options = {
+      keyFile = lib.mkOption {
+        type = lib.types.str;
+        description = "The HMAC secret, or a PEM encoded PKCS#8 key, readable only by root.";
+      };
+
+      algorithm = lib.mkOption {
+        type = lib.types.enum [
+          "HS256"
+          "EdDSA"
+          "ES256"
+        ];
+      };
+
+      keyId = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+      };
+
+      claims = lib.mkOption {
+        type = lib.types.attrsOf lib.types.anything;
+        default = { };
+        description = "Claims added to every token, alongside `iat`, `exp`, `jti` and `drv`.";
+      };
+
+      ttl = lib.mkOption {
+        type = lib.types.ints.positive;
+        default = 300;
+        description = "Seconds each token is valid for.";
+      };
+    };
+  };
+in
+{
+  options.buildtimeSecrets.mint = {
+    enable = lib.mkEnableOption "short-lived certificates and JWTs minted on every build";
+
+    certificates = lib.mkOption {
+      type = lib.types.attrsOf certificateType;
+      default = { };
+      description = "Client certificates minted, keyed by secret name.";
+    };
+
+    jwts = lib.mkOption {
+      type = lib.types.attrsOf jwtType;
+      default = { };
+      description = "JWTs minted, keyed by secret name.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.mint = {
+        credentials =
+          lib.mapAttrs (_: certificate: {
+            certificate = {
+              inherit (certificate) subject;
+              ca_certificate = certificate.caCertificate;
+              ca_key = certificate.caKeyFile;
+              dns_names = certificate.dnsNames;
+              validity_secs = certificate.validity;
+            };
+          }) cfg.certificates
+          // lib.mapAttrs (_: jwt: {
+            jwt = {
+              inherit (jwt) algorithm claims;
+              key = jwt.keyFile;
+              key_id = jwt.keyId;
+              ttl_secs = jwt.ttl;
+            };
+          }) cfg.jwts;
+      };
+    };
+  };
+}
@@ -0,0 +1,38 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.nixSettings;
+in
+{
+  options.buildtimeSecrets.nixSettings = {
+    enable = lib.mkEnableOption "serving nix's own `netrc-file` and `access-tokens` as secrets";
+
+    netrcFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "Read this netrc file rather than nix's `netrc-file`.";
+    };
+
+    machines = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [ ];
+      example = [ "cache.example.com" ];
+      description = "The netrc machines builds may read the credentials of, no others are served.";
+    };
+
+    hosts = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [ ];
+      example = [ "github.com" ];
+      description = "The hosts builds may read the access tokens of, no others are served.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.nix-settings = {
+        netrc_file = cfg.netrcFile;
+        inherit (cfg) machines hosts;
+      };
+    };
+  };
+}
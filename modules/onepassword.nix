@@ -0,0 +1,63 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.onepassword;
+in
+{
+  options.buildtimeSecrets.onepassword = {
+    enable = lib.mkEnableOption "the 1Password CLI backend";
+
+    package = lib.mkOption {
+      type = lib.types.package;
+      example = lib.literalExpression "pkgs._1password-cli";
+      description = "The package providing `op`, the hook's PATH doesn't have it.";
+    };
+
+    serviceAccountTokenFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "A file holding a service account token.";
+    };
+
+    sessionFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "A file holding a session token from `op signin --raw`.";
+    };
+
+    account = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "The account the session token belongs to.";
+    };
+
+    vault = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "The vault of secret names that don't name one.";
+    };
+
+    field = lib.mkOption {
+      type = lib.types.str;
+      default = "password";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    assertions = [
+      {
+        assertion =
+          cfg.serviceAccountTokenFile != null || (cfg.sessionFile != null && cfg.account != null);
+        message = "buildtimeSecrets.onepassword needs a serviceAccountTokenFile, or a sessionFile and account";
+      }
+    ];
+
+    buildtimeSecrets.config = {
+      backend_config.onepassword = {
+        inherit (cfg) account vault field;
+        program = lib.getExe' cfg.package "op";
+        service_account_token_file = cfg.serviceAccountTokenFile;
+        session_file = cfg.sessionFile;
+      };
+    };
+  };
+}
@@ -0,0 +1,38 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.pass;
+in
+{
+  options.buildtimeSecrets.pass = {
+    enable = lib.mkEnableOption "the password-store backend";
+
+    storeDirectory = lib.mkOption {
+      type = lib.types.str;
+      description = "The root of the password store.";
+    };
+
+    gnupgHome = lib.mkOption {
+      type = lib.types.str;
+      description = "The GNUPGHOME holding the decryption keys.";
+    };
+
+    extract = lib.mkOption {
+      type = lib.types.enum [
+        "password"
+        "entry"
+      ];
+      default = "password";
+      description = "Provision only the first line of an entry, or all of it.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.pass = {
+        store_dir = cfg.storeDirectory;
+        gnupg_home = cfg.gnupgHome;
+        inherit (cfg) extract;
+      };
+    };
+  };
+}
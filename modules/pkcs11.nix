@@ -0,0 +1,55 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.pkcs11;
+in
+{
+  options.buildtimeSecrets.pkcs11 = {
+    enable = lib.mkEnableOption "the PKCS#11 backend";
+
+    module = lib.mkOption {
+      type = lib.types.str;
+      example = lib.literalExpression ''"''${pkgs.softhsm}/lib/softhsm/libsofthsm2.so"'';
+      description = "The PKCS#11 module.";
+    };
+
+    tokenLabel = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "The label of the token, the first initialized token is used otherwise.";
+    };
+
+    slot = lib.mkOption {
+      type = lib.types.nullOr lib.types.ints.unsigned;
+      default = null;
+      description = "The slot of the token, instead of `tokenLabel`.";
+    };
+
+    pinFile = lib.mkOption {
+      type = lib.types.str;
+      description = "A file holding the user PIN, readable only by root.";
+    };
+
+    prefix = lib.mkOption {
+      type = lib.types.str;
+      default = "";
+      description = "Prepended to the secret name to form the object label.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    assertions = [
+      {
+        assertion = cfg.tokenLabel == null || cfg.slot == null;
+        message = "buildtimeSecrets.pkcs11 selects its token by either tokenLabel or slot, not both";
+      }
+    ];
+
+    buildtimeSecrets.config = {
+      backend_config.pkcs11 = {
+        inherit (cfg) module prefix slot;
+        token_label = cfg.tokenLabel;
+        pin_file = cfg.pinFile;
+      };
+    };
+  };
+}
@@ -0,0 +1,47 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.quorum;
+
+  quorumType = lib.types.submodule {
+    options = {
+      instances = lib.mkOption {
+        type = lib.types.nonEmptyListOf lib.types.str;
+        example = [
+          "sops"
+          "vault"
+        ];
+        description = "The backend instances cross-checked, tried in order.";
+      };
+
+      threshold = lib.mkOption {
+        type = lib.types.nullOr lib.types.ints.positive;
+        default = null;
+        description = "How many instances must agree, all of them by default.";
+      };
+
+      secrets = lib.mkOption {
+        type = lib.types.listOf lib.types.str;
+        description = "The secrets guarded, which no instance provisions on its own.";
+      };
+    };
+  };
+in
+{
+  options.buildtimeSecrets.quorum = lib.mkOption {
+    type = lib.types.attrsOf quorumType;
+    default = { };
+    description = ''
+      Quorums keyed by instance name, provisioning secrets only when
+      enough backend instances agree on them byte for byte.
+    '';
+  };
+
+  config = lib.mkIf (cfg != { }) {
+    buildtimeSecrets.config = {
+      backend_config = lib.mapAttrs (_: quorum: {
+        kind = "quorum";
+        inherit (quorum) instances threshold secrets;
+      }) cfg;
+    };
+  };
+}
@@ -11,15 +11,86 @@ in
     };
 
     keyFile = lib.mkOption {
-      type = lib.types.str;
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+
+    keySecret = lib.mkOption {
+      type = lib.types.nullOr (
+        lib.types.submodule {
+          options = {
+            backend = lib.mkOption {
+              type = lib.types.str;
+              description = "The backend instance the key is provisioned by.";
+            };
+
+            secret = lib.mkOption {
+              type = lib.types.str;
+            };
+          };
+        }
+      );
+      default = null;
+      example = {
+        backend = "keyring";
+        secret = "sops-key";
+      };
+      description = ''
+        Provision the key from another backend rather than reading
+        `keyFile`. It's written to a private file for as long as the
+        hook runs.
+      '';
+    };
+
+    sandbox = lib.mkOption {
+      type = lib.types.nullOr lib.types.attrs;
+      default = null;
+      example = {
+        user = "buildtime-secrets";
+      };
+      description = ''
+        Run sops unprivileged and sandboxed. `sopsFile` and `keyFile` are
+        always added to `readable_paths`, as is the file `keySecret` is
+        written to, which is owned by the sandbox user.
+
+        Landlock only narrows what the sandbox user can already read, so
+        `sopsFile` and `keyFile` must also be readable by that user. A
+        root-only key file must be handed over, e.g. owned by the sandbox
+        user with mode 0400.
+      '';
     };
   };
 
   config = lib.mkIf cfg.enable {
+    assertions = [
+      {
+        assertion = (cfg.keyFile == null) != (cfg.keySecret == null);
+        message = "buildtimeSecrets.sops needs exactly one of keyFile and keySecret";
+      }
+    ];
+
     buildtimeSecrets.config = {
       backend_config.sops = {
         sops_file = cfg.sopsFile;
+      }
+      // lib.optionalAttrs (cfg.keyFile != null) {
         environment.SOPS_AGE_SSH_PRIVATE_KEY_FILE = cfg.keyFile;
+      }
+      // lib.optionalAttrs (cfg.keySecret != null) {
+        dependencies = [
+          {
+            inherit (cfg.keySecret) backend secret;
+            pointer = "/environment/SOPS_AGE_SSH_PRIVATE_KEY_FILE";
+          }
+        ];
+      }
+      // lib.optionalAttrs (cfg.sandbox != null) {
+        sandbox = cfg.sandbox // {
+          readable_paths =
+            (cfg.sandbox.readable_paths or [ ])
+            ++ [ cfg.sopsFile ]
+            ++ lib.optional (cfg.keyFile != null) cfg.keyFile;
+        };
       };
     };
   };
@@ -0,0 +1,36 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.systemdCreds;
+in
+{
+  options.buildtimeSecrets.systemdCreds = {
+    enable = lib.mkEnableOption "the systemd-creds backend";
+
+    credentialDirectory = lib.mkOption {
+      type = lib.types.str;
+      description = "A directory of `<name>.cred` files made with `systemd-creds encrypt`.";
+    };
+
+    credentials = lib.mkOption {
+      type = lib.types.attrsOf lib.types.str;
+      default = { };
+      description = "Credential names keyed by secret name, other secrets use their own name.";
+    };
+
+    hostKeyFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "Overrides the host key, `/var/lib/systemd/credential.secret`.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    buildtimeSecrets.config = {
+      backend_config.systemd-creds = {
+        credential_dir = cfg.credentialDirectory;
+        host_key_file = cfg.hostKeyFile;
+        inherit (cfg) credentials;
+      };
+    };
+  };
+}
@@ -0,0 +1,152 @@
+{ config, lib, ... }:
+let
+  cfg = config.buildtimeSecrets.vault;
+in
+{
+  options.buildtimeSecrets.vault = {
+    enable = lib.mkEnableOption "the HashiCorp Vault backend";
+
+    address = lib.mkOption {
+      type = lib.types.str;
+      example = "https://vault.example.com:8200";
+    };
+
+    mount = lib.mkOption {
+      type = lib.types.str;
+      default = "secret";
+      description = "Where the KV v2 secrets engine is mounted.";
+    };
+
+    pathPrefix = lib.mkOption {
+      type = lib.types.str;
+      default = "";
+      description = "Prepended to every secret path.";
+    };
+
+    namespace = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+    };
+
+    tokenFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "A file holding the Vault token, used unless `appRole` is set.";
+    };
+
+    tokenSecret = lib.mkOption {
+      type = lib.types.nullOr (
+        lib.types.submodule {
+          options = {
+            backend = lib.mkOption {
+              type = lib.types.str;
+              description = "The backend instance the token is provisioned by.";
+            };
+
+            secret = lib.mkOption {
+              type = lib.types.str;
+            };
+          };
+        }
+      );
+      default = null;
+      example = {
+        backend = "keyring";
+        secret = "vault-token";
+      };
+      description = ''
+        Provision the token from another backend rather than reading
+        `tokenFile`, used unless `appRole` is set. It's only ever held in
+        memory while the hook runs, never written to the world readable
+        hook config.
+      '';
+    };
+
+    appRole = lib.mkOption {
+      type = lib.types.nullOr (
+        lib.types.submodule {
+          options = {
+            roleId = lib.mkOption { type = lib.types.str; };
+            secretIdFile = lib.mkOption { type = lib.types.str; };
+            mount = lib.mkOption {
+              type = lib.types.str;
+              default = "approle";
+            };
+          };
+        }
+      );
+      default = null;
+    };
+
+    field = lib.mkOption {
+      type = lib.types.str;
+      default = "value";
+      description = "The field provisioned when the secret name doesn't select one.";
+    };
+
+    versions = lib.mkOption {
+      type = lib.types.attrsOf lib.types.ints.positive;
+      default = { };
+      description = "Pinned secret versions, keyed by secret path.";
+    };
+
+    caFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "A PEM bundle of CA certificates to trust.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    assertions = [
+      {
+        assertion = cfg.tokenFile != null || cfg.tokenSecret != null || cfg.appRole != null;
+        message = "buildtimeSecrets.vault needs one of tokenFile, tokenSecret and appRole";
+      }
+      {
+        assertion = cfg.tokenFile == null || cfg.tokenSecret == null;
+        message = "buildtimeSecrets.vault can't use both tokenFile and tokenSecret";
+      }
+    ];
+
+    buildtimeSecrets.config = {
+      backend_config.vault = {
+        inherit (cfg)
+          address
+          mount
+          namespace
+          field
+          versions
+          ;
+        path_prefix = cfg.pathPrefix;
+        ca_file = cfg.caFile;
+        auth =
+          if cfg.appRole != null then
+            {
+              method = "approle";
+              role_id = cfg.appRole.roleId;
+              secret_id_file = cfg.appRole.secretIdFile;
+              inherit (cfg.appRole) mount;
+            }
+          else if cfg.tokenSecret != null then
+            {
+              method = "token";
+            }
+          else
+            {
+              method = "token_file";
+              path = cfg.tokenFile;
+            };
+      }
+      // lib.optionalAttrs (cfg.appRole == null && cfg.tokenSecret != null) {
+        dependencies = [
+          {
+            inherit (cfg.tokenSecret) backend secret;
+            pointer = "/auth/token";
+            inline = true;
+          }
+        ];
+      };
+    };
+  };
+}